@@ -0,0 +1,124 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use csbase::config::config::Config;
+use csbase::engine::db::Database;
+
+const ROWS: usize = 100_000;
+// Rows per category, so selecting a category reads the whole table but returns 1% of it
+const CATEGORIES: usize = 100;
+
+/**
+* A database directory in the system temp directory, removed when it's dropped
+*/
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("csbase-bench-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        TempDir { path }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Backend {
+    File,
+    Memory,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Backend::File => "file",
+            Backend::Memory => "memory",
+        }
+    }
+
+    /**
+    * Open an empty database with the table the benchmarks use. File databases are stored in dir,
+    * which must not have a database yet
+    */
+    fn open(self, dir: &TempDir) -> Database {
+        let mut database = match self {
+            Backend::File => Database::with_config(Config {
+                db_path: dir.path.to_string_lossy().into_owned(),
+                ..Config::default()
+            }),
+            Backend::Memory => Database::in_memory(),
+        };
+        database.bootstrap().expect("Error while starting the database");
+        database.run_query("CREATE TABLE items (id INT, category INT, name STRING, PRIMARY KEY (id))")
+            .expect("Error while creating the table");
+        database
+    }
+}
+
+fn insert_rows(database: &mut Database, rows: usize) {
+    for id in 0..rows {
+        database.run_query(&format!(
+            "INSERT INTO items (id, category, name) VALUES ({}, {}, 'item {}')", id, id % CATEGORIES, id))
+            .expect("Error while inserting a row");
+    }
+}
+
+/**
+* Insert ROWS rows with an INSERT each into an empty table
+*/
+fn bench_inserts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+    for backend in &[Backend::File, Backend::Memory] {
+        group.bench_function(format!("{}_{}_rows", backend.name(), ROWS), |b| {
+            b.iter_batched(
+                || {
+                    let dir = TempDir::new("insert");
+                    let database = backend.open(&dir);
+                    (dir, database)
+                },
+                |(dir, mut database)| {
+                    insert_rows(&mut database, ROWS);
+                    // Closing flushes the writes, which is part of inserting them
+                    database.close().expect("Error while closing the database");
+                    dir
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+/**
+* Select the rows of a category out of a table with ROWS rows
+*/
+fn bench_selective_select(c: &mut Criterion) {
+    let mut group = c.benchmark_group("select");
+    group.sample_size(10);
+    for backend in &[Backend::File, Backend::Memory] {
+        let dir = TempDir::new("select");
+        let mut database = backend.open(&dir);
+        insert_rows(&mut database, ROWS);
+        group.bench_function(format!("{}_{}_rows", backend.name(), ROWS), |b| {
+            b.iter(|| database.run_query("SELECT id, name FROM items WHERE category = 7")
+                .expect("Error while selecting the rows"))
+        });
+        database.close().expect("Error while closing the database");
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_inserts, bench_selective_select);
+criterion_main!(benches);
@@ -1,2 +1,69 @@
+use std::time::Duration;
+
+use crate::engine::pages::PAGE_SIZE;
+
 pub const DB_PATH: &str = "~/csbase";
-pub const TABLE_DEFINITIONS_FILE: &str = "table_definitions.csbase";
\ No newline at end of file
+pub const TABLE_DEFINITIONS_FILE: &str = "table_definitions.csbase";
+pub const DEFAULT_SORT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_JOIN_ROWS: usize = 1_000_000;
+pub const DEFAULT_FILL_FACTOR: u8 = 100;
+// Lowest fill factor accepted, so every page still holds a useful number of rows
+pub const MIN_FILL_FACTOR: u8 = 10;
+
+/**
+* Settings of a database instance. The defaults are the constants above, so a database created
+* without a config uses the same files as before
+*/
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Directory of the database files, where a leading ~ is expanded to the home directory
+    pub db_path: String,
+    pub definitions_file: String,
+    // Pages are fixed size arrays, so only the compiled page size is accepted for now
+    pub page_size: usize,
+    // Sync every write to disk before returning, so it survives a crash
+    pub durable: bool,
+    pub read_only: bool,
+    // Store the definitions as pretty printed JSON instead of bincode. Either format is read
+    pub json_definitions: bool,
+    // Approximate bytes of rows ORDER BY keeps in memory before spilling sorted runs to temporary
+    // files in the database directory
+    pub sort_memory_limit: usize,
+    // Longest a query may run before it's aborted. It's checked as scans move to the next page, so a
+    // query is only stopped between pages and may run past the timeout while it processes one
+    pub query_timeout: Option<Duration>,
+    // Most rows a join may produce before the query fails, so a join that matches far more rows than
+    // intended doesn't run until it exhausts the memory
+    pub max_join_rows: usize,
+    // Rows of single-row INSERTs kept in memory per table before they are written together. Buffered
+    // rows are written before any other query runs and when the database is flushed, but a crash
+    // loses them. None writes every INSERT right away
+    pub insert_batch_rows: Option<usize>,
+    // Percentage of each page inserts fill before moving on to a new page. The rest is left for
+    // updates that grow the rows in the page, which would otherwise be moved to the end of the table
+    pub fill_factor: u8,
+    // Sort the rows of a SELECT without ORDER BY by the primary key of its table, so repeated queries
+    // return them in the same order even after the rows move between pages. It costs a full sort of
+    // the result, so the first row is only returned once the whole table was read, and rows past
+    // sort_memory_limit are spilled to disk
+    pub default_order_by_primary_key: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            db_path: String::from(DB_PATH),
+            definitions_file: String::from(TABLE_DEFINITIONS_FILE),
+            page_size: PAGE_SIZE,
+            durable: false,
+            read_only: false,
+            json_definitions: false,
+            sort_memory_limit: DEFAULT_SORT_MEMORY_LIMIT,
+            query_timeout: None,
+            max_join_rows: DEFAULT_MAX_JOIN_ROWS,
+            insert_batch_rows: None,
+            fill_factor: DEFAULT_FILL_FACTOR,
+            default_order_by_primary_key: false,
+        }
+    }
+}
@@ -1,18 +1,81 @@
 use std::collections::HashMap;
+use std::convert::TryInto;
 use serde::{Serialize, Deserialize};
 
-use crate::engine::errors::QueryError;
+use crate::engine::constraints::{ConstraintKind, TableConstraint};
+use crate::engine::errors::{PagingError, QueryError};
+use crate::engine::temporal;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Column {
+    // Assigned when the column is created and kept when the columns of the table change, as stored
+    // items have the fields of their columns ordered by id instead of by the column position
+    pub id: u32,
     pub name: String,
     pub column_type: Type,
+    pub comment: Option<String>,
+    // Value stored when an INSERT doesn't provide one for the column
+    pub default: Option<ColumnDefault>,
+}
+
+/**
+* The default value of a column. CURRENT_TIMESTAMP is evaluated on every insert, not when the table
+* is created
+*/
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ColumnDefault {
+    Value(Value),
+    CurrentTimestamp,
+}
+
+impl ColumnDefault {
+    pub fn evaluate(&self) -> Value {
+        match self {
+            ColumnDefault::Value(value) => value.clone(),
+            ColumnDefault::CurrentTimestamp => Value::Timestamp(temporal::now_micros()),
+        }
+    }
+
+    /**
+    * Check that the default can be stored in a column of the given type, converting a literal to
+    * that type, e.g. an Int literal for a Float column
+    */
+    pub fn for_type(&self, column_type: &Type) -> Result<ColumnDefault, QueryError> {
+        match (self, column_type) {
+            (ColumnDefault::Value(value), column_type) => Ok(ColumnDefault::Value(value.cast(column_type)?)),
+            (ColumnDefault::CurrentTimestamp, Type::Timestamp) => Ok(ColumnDefault::CurrentTimestamp),
+            (ColumnDefault::CurrentTimestamp, column_type) => Err(QueryError::ValidationError(format!(
+                "CURRENT_TIMESTAMP can't be the default of a {:?} column", column_type))),
+        }
+    }
+}
+
+impl std::fmt::Display for ColumnDefault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ColumnDefault::Value(value) => write!(f, "{}", value.to_literal()),
+            ColumnDefault::CurrentTimestamp => write!(f, "CURRENT_TIMESTAMP"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Table {
     pub name: String,
-    pub columns: Vec<Column>
+    pub columns: Vec<Column>,
+    pub comment: Option<String>,
+    pub constraints: Vec<TableConstraint>,
+}
+
+impl Column {
+    /**
+    * Give the columns of a new table consecutive ids in definition order
+    */
+    pub fn assign_ids(columns: &mut [Column]) {
+        for (id, column) in columns.iter_mut().enumerate() {
+            column.id = id as u32;
+        }
+    }
 }
 
 impl Table {
@@ -22,12 +85,29 @@ impl Table {
     pub fn get_column(&self, column_name: &str) -> Option<&Column> {
         self.columns.iter().find(| column | &column.name == column_name)
     }
+
+    /**
+    * Get the positions of the columns in the order their fields are stored, which is by column id
+    */
+    pub fn get_physical_order(&self) -> Vec<usize> {
+        let mut positions: Vec<usize> = (0..self.columns.len()).collect();
+        positions.sort_by_key(|position| self.columns[*position].id);
+        positions
+    }
+
+    /**
+    * Test if the column is part of the primary key, so it can't be Null
+    */
+    pub fn is_primary_key_column(&self, column_name: &str) -> bool {
+        self.constraints.iter().any(|constraint| constraint.kind == ConstraintKind::PrimaryKey
+            && constraint.columns.iter().any(|column| column == column_name))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ColumnValue {
     pub column: String,
-    pub value: Value,
+    pub value: Box<Expression>,
 }
 
 #[derive(Debug, Clone)]
@@ -41,7 +121,11 @@ pub enum Operator {
     Add,
     Subtract,
     Multiply,
+    // Always produces a Float, even for two Int operands
     Divide,
+    // Produces an Int, truncating the quotient towards zero
+    IntegerDivide,
+    Modulo,
 }
 
 #[derive(Debug, Clone)]
@@ -66,7 +150,43 @@ pub enum Expression {
     Identifier(String),
     Op(Box<Expression>, Operator, Box<Expression>),
     Comp(Box<Expression>, Comparator, Box<Expression>),
-    LogicOp(Box<Expression>, LogicOperator, Box<Expression>)
+    LogicOp(Box<Expression>, LogicOperator, Box<Expression>),
+    Not(Box<Expression>),
+    Cast(Box<Expression>, Type),
+    // LOWER(value) and UPPER(value), to compare strings ignoring their case
+    Lower(Box<Expression>),
+    Upper(Box<Expression>),
+    // value [NOT] LIKE pattern [ESCAPE escape], where the escape string must be a single character.
+    // The first flag holds true when NOT is present, and the second one for ILIKE, which ignores the
+    // case of the value and the pattern
+    Like(Box<Expression>, Box<Expression>, Option<String>, bool, bool),
+    // value [NOT] IN (values)
+    In(Box<Expression>, Vec<Box<Expression>>, bool),
+    // value [NOT] BETWEEN low AND high
+    Between(Box<Expression>, Box<Expression>, Box<Expression>, bool),
+    // value IS [NOT] NULL
+    IsNull(Box<Expression>, bool),
+    // NOW() and CURRENT_DATE. They are replaced by the time the statement starts before running it,
+    // see Query::resolve_current_time
+    Now,
+    CurrentDate,
+    // DEFAULT as an INSERT value, replaced by the default of its column, see
+    // InsertQuery::evaluate_expressions
+    Default,
+    // NEXTVAL('sequence') in an INSERT value, replaced by the next value of the sequence before
+    // running the statement, see Query::resolve_next_values
+    NextVal(String),
+}
+
+/**
+* How strictly operand types are checked. Lenient mode converts Int operands to Float when mixed
+* with Float ones, while strict mode requires both operands to have the same type unless a CAST
+* makes them match
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TypeMode {
+    Lenient,
+    Strict,
 }
 
 impl Expression {
@@ -93,11 +213,20 @@ impl Expression {
                     Operator::Subtract => value1 - value2,
                     Operator::Multiply => value1 * value2,
                     Operator::Divide => value1 / value2,
+                    Operator::IntegerDivide => value1.integer_divide(value2),
+                    Operator::Modulo => value1 % value2,
                 }
             },
             Expression::Comp(exp1, comparator, exp2) => {
                 let value1 = exp1.evaluate(identifier_values)?;
                 let value2 = exp2.evaluate(identifier_values)?;
+                // Comparing with NULL gives an unknown result, so NOT and OR over it work like in SQL
+                if value1.is_null() || value2.is_null() {
+                    return Ok(Value::Null)
+                }
+                let value2 = value2.parse_for_comparison(&value1)?;
+                let value1 = value1.parse_for_comparison(&value2)?;
+                value1.check_ordered_comparison(comparator, &value2)?;
                 Ok(Value::Bool(
                     match comparator {
                         Comparator::Eq => value1 == value2,
@@ -110,19 +239,384 @@ impl Expression {
                 ))
             },
             Expression::LogicOp(exp1, logic_operator, exp2) => {
-                let value1 = exp1.evaluate(identifier_values)?.get_bool()?;
-                let value2 = exp2.evaluate(identifier_values)?.get_bool()?;
-                Ok(Value::Bool(
-                    match logic_operator {
-                        LogicOperator::And => value1 && value2,
-                        LogicOperator::Or => value1 || value2,
+                // NULL is an unknown truth value, so the result is only known when the other operand
+                // decides it, e.g. false AND NULL is false but true AND NULL is NULL
+                let value1 = exp1.evaluate(identifier_values)?.get_nullable_bool()?;
+                let value2 = exp2.evaluate(identifier_values)?.get_nullable_bool()?;
+                let result = match (logic_operator, value1, value2) {
+                    (LogicOperator::And, Some(false), _) | (LogicOperator::And, _, Some(false)) => Some(false),
+                    (LogicOperator::And, Some(true), Some(true)) => Some(true),
+                    (LogicOperator::Or, Some(true), _) | (LogicOperator::Or, _, Some(true)) => Some(true),
+                    (LogicOperator::Or, Some(false), Some(false)) => Some(false),
+                    _ => None,
+                };
+                Ok(result.map_or(Value::Null, Value::Bool))
+            },
+            Expression::Not(exp) => match exp.evaluate(identifier_values)? {
+                Value::Null => Ok(Value::Null),
+                value => Ok(Value::Bool(!value.get_bool()?)),
+            },
+            Expression::Cast(exp, value_type) => exp.evaluate(identifier_values)?.cast(value_type),
+            Expression::Lower(exp) => exp.evaluate(identifier_values)?.change_case("LOWER", str::to_lowercase),
+            Expression::Upper(exp) => exp.evaluate(identifier_values)?.change_case("UPPER", str::to_uppercase),
+            Expression::Like(exp1, exp2, escape, negated, case_insensitive) => {
+                let escape = match escape {
+                    Some(escape) => {
+                        let mut escape_chars = escape.chars();
+                        match (escape_chars.next(), escape_chars.next()) {
+                            (Some(escape), None) => Some(escape),
+                            _ => return Err(QueryError::ValidationError(format!(
+                                "The LIKE escape must be a single character, but it's '{}'", escape)))
+                        }
+                    },
+                    None => None,
+                };
+                let value1 = exp1.evaluate(identifier_values)?;
+                let value2 = exp2.evaluate(identifier_values)?;
+                if value1.is_null() || value2.is_null() {
+                    return Ok(Value::Null)
+                }
+                Ok(Value::Bool(value1.like(&value2, escape, *case_insensitive)? != *negated))
+            },
+            Expression::In(exp, exps, negated) => {
+                let value = exp.evaluate(identifier_values)?;
+                if value.is_null() {
+                    return Ok(Value::Null)
+                }
+                // A NULL in the list makes the result unknown unless another value matches
+                let mut has_null = false;
+                for exp in exps {
+                    let item = exp.evaluate(identifier_values)?.parse_for_comparison(&value)?;
+                    if item.is_null() {
+                        has_null = true;
+                    } else if value.clone().parse_for_comparison(&item)? == item {
+                        return Ok(Value::Bool(!negated))
                     }
-                ))
-            }
+                }
+                Ok(if has_null { Value::Null } else { Value::Bool(*negated) })
+            },
+            Expression::Between(exp, low, high, negated) => {
+                let value = exp.evaluate(identifier_values)?;
+                let low = low.evaluate(identifier_values)?.parse_for_comparison(&value)?;
+                let high = high.evaluate(identifier_values)?.parse_for_comparison(&value)?;
+                if value.is_null() {
+                    return Ok(Value::Null)
+                }
+                let value = value.parse_for_comparison(&low)?.parse_for_comparison(&high)?;
+                // Either bound being NULL leaves the result unknown, unless the other bound already
+                // rules the value out
+                let above_low = if low.is_null() { None } else { Some(value >= low) };
+                let below_high = if high.is_null() { None } else { Some(value <= high) };
+                Ok(match (above_low, below_high) {
+                    (Some(false), _) | (_, Some(false)) => Value::Bool(*negated),
+                    (Some(true), Some(true)) => Value::Bool(!negated),
+                    _ => Value::Null,
+                })
+            },
+            Expression::IsNull(exp, negated) => Ok(Value::Bool(exp.evaluate(identifier_values)?.is_null() != *negated)),
+            Expression::Now => Ok(Value::Timestamp(temporal::now_micros())),
+            Expression::CurrentDate => Ok(Value::Date(temporal::date_of_timestamp(temporal::now_micros()))),
+            Expression::Default => Err(QueryError::ValidationError(
+                String::from("DEFAULT can only be used as a value of INSERT"))),
+            Expression::NextVal(_) => Err(QueryError::ValidationError(
+                String::from("NEXTVAL can only be used in the values of INSERT"))),
+        }
+    }
+
+    /**
+    * Get the type this expression evaluates to without evaluating it, checking the operand types of
+    * every operator and comparison with the given mode
+    */
+    pub fn infer_type(&self, column_types: &HashMap<String, Type>, mode: TypeMode) -> Result<Type, QueryError> {
+        match self {
+            Expression::Value(value) => Ok(value.get_type()),
+            Expression::Identifier(name) => match column_types.get(name) {
+                Some(column_type) => Ok(column_type.clone()),
+                None => Err(QueryError::ValidationError(
+                    format!("Identifier {} not present in provided values", name)))
+            },
+            Expression::Op(exp1, operator, exp2) => {
+                let type1 = exp1.infer_type(column_types, mode)?;
+                let type2 = exp2.infer_type(column_types, mode)?;
+                let (coerced_type, _) = type1.coerce_for_binary_op(&type2, operator, mode)?;
+                Ok(match operator {
+                    Operator::IntegerDivide => Type::Int,
+                    _ => coerced_type,
+                })
+            },
+            Expression::Comp(exp1, _, exp2) => {
+                let type1 = exp1.infer_type(column_types, mode)?;
+                let type2 = exp2.infer_type(column_types, mode)?;
+                type1.check_comparison(&type2, mode)?;
+                Ok(Type::Bool)
+            },
+            Expression::LogicOp(exp1, _, exp2) => {
+                exp1.infer_type(column_types, mode)?;
+                exp2.infer_type(column_types, mode)?;
+                Ok(Type::Bool)
+            },
+            Expression::Not(exp) => {
+                exp.infer_type(column_types, mode)?;
+                Ok(Type::Bool)
+            },
+            Expression::Cast(exp, value_type) => {
+                exp.infer_type(column_types, mode)?;
+                Ok(value_type.clone())
+            },
+            Expression::Lower(exp) | Expression::Upper(exp) => match exp.infer_type(column_types, mode)? {
+                Type::Str => Ok(Type::Str),
+                Type::Null => Ok(Type::Null),
+                value_type => Err(QueryError::ValidationError(
+                    format!("{} needs a Str value, but it's {:?}", self, value_type))),
+            },
+            Expression::Like(exp1, exp2, _, _, _) => {
+                let type1 = exp1.infer_type(column_types, mode)?;
+                let type2 = exp2.infer_type(column_types, mode)?;
+                type1.check_comparison(&type2, mode)?;
+                Ok(Type::Bool)
+            },
+            Expression::In(exp, exps, _) => {
+                let value_type = exp.infer_type(column_types, mode)?;
+                for exp in exps {
+                    value_type.check_comparison(&exp.infer_type(column_types, mode)?, mode)?;
+                }
+                Ok(Type::Bool)
+            },
+            Expression::Between(exp, low, high, _) => {
+                let value_type = exp.infer_type(column_types, mode)?;
+                value_type.check_comparison(&low.infer_type(column_types, mode)?, mode)?;
+                value_type.check_comparison(&high.infer_type(column_types, mode)?, mode)?;
+                Ok(Type::Bool)
+            },
+            Expression::IsNull(exp, _) => {
+                exp.infer_type(column_types, mode)?;
+                Ok(Type::Bool)
+            },
+            Expression::Now => Ok(Type::Timestamp),
+            Expression::CurrentDate => Ok(Type::Date),
+            // The default was already checked against the type of its column
+            Expression::Default => Ok(Type::Null),
+            Expression::NextVal(_) => Ok(Type::Int),
+        }
+    }
+
+    /**
+    * Get the names of the identifiers used in the expression
+    */
+    pub fn get_identifiers(&self) -> Vec<&String> {
+        match self {
+            Expression::Value(_) | Expression::Now | Expression::CurrentDate | Expression::Default
+            | Expression::NextVal(_) => Vec::new(),
+            Expression::Identifier(name) => vec![name],
+            Expression::Op(exp1, _, exp2) | Expression::Comp(exp1, _, exp2) | Expression::LogicOp(exp1, _, exp2)
+            | Expression::Like(exp1, exp2, ..) => {
+                let mut identifiers = exp1.get_identifiers();
+                identifiers.extend(exp2.get_identifiers());
+                identifiers
+            },
+            Expression::In(exp, exps, _) => {
+                let mut identifiers = exp.get_identifiers();
+                identifiers.extend(exps.iter().flat_map(|exp| exp.get_identifiers()));
+                identifiers
+            },
+            Expression::Between(exp, low, high, _) => {
+                let mut identifiers = exp.get_identifiers();
+                identifiers.extend(low.get_identifiers());
+                identifiers.extend(high.get_identifiers());
+                identifiers
+            },
+            Expression::Not(exp) | Expression::Cast(exp, _) | Expression::IsNull(exp, _) | Expression::Lower(exp)
+            | Expression::Upper(exp) => exp.get_identifiers(),
+        }
+    }
+
+    /**
+    * Replace the name of every identifier for which rename returns a new one
+    */
+    pub fn rename_identifiers(&mut self,
+                              rename: &mut dyn FnMut(&str) -> Result<Option<String>, QueryError>) -> Result<(), QueryError> {
+        match self {
+            Expression::Identifier(name) => if let Some(new_name) = rename(name)? {
+                *name = new_name;
+            },
+            Expression::Value(_) | Expression::Now | Expression::CurrentDate | Expression::Default
+            | Expression::NextVal(_) => (),
+            Expression::Op(exp1, _, exp2) | Expression::Comp(exp1, _, exp2) | Expression::LogicOp(exp1, _, exp2)
+            | Expression::Like(exp1, exp2, ..) => {
+                exp1.rename_identifiers(rename)?;
+                exp2.rename_identifiers(rename)?;
+            },
+            Expression::In(exp, exps, _) => {
+                exp.rename_identifiers(rename)?;
+                for exp in exps {
+                    exp.rename_identifiers(rename)?;
+                }
+            },
+            Expression::Between(exp, low, high, _) => {
+                exp.rename_identifiers(rename)?;
+                low.rename_identifiers(rename)?;
+                high.rename_identifiers(rename)?;
+            },
+            Expression::Not(exp) | Expression::Cast(exp, _) | Expression::IsNull(exp, _) | Expression::Lower(exp)
+            | Expression::Upper(exp) => exp.rename_identifiers(rename)?,
+        }
+        Ok(())
+    }
+
+    /**
+    * Replace NOW() and CURRENT_DATE with the values they have at the given instant
+    */
+    pub fn resolve_current_time(&mut self, now: i64) {
+        match self {
+            Expression::Now => *self = Expression::Value(Value::Timestamp(now)),
+            Expression::CurrentDate => *self = Expression::Value(Value::Date(temporal::date_of_timestamp(now))),
+            Expression::Value(_) | Expression::Identifier(_) | Expression::Default | Expression::NextVal(_) => (),
+            Expression::Op(exp1, _, exp2) | Expression::Comp(exp1, _, exp2) | Expression::LogicOp(exp1, _, exp2)
+            | Expression::Like(exp1, exp2, ..) => {
+                exp1.resolve_current_time(now);
+                exp2.resolve_current_time(now);
+            },
+            Expression::In(exp, exps, _) => {
+                exp.resolve_current_time(now);
+                for exp in exps {
+                    exp.resolve_current_time(now);
+                }
+            },
+            Expression::Between(exp, low, high, _) => {
+                exp.resolve_current_time(now);
+                low.resolve_current_time(now);
+                high.resolve_current_time(now);
+            },
+            Expression::Not(exp) | Expression::Cast(exp, _) | Expression::IsNull(exp, _) | Expression::Lower(exp)
+            | Expression::Upper(exp) => exp.resolve_current_time(now),
+        }
+    }
+
+    /**
+    * Replace NEXTVAL('sequence') with the value next_value returns for the sequence, in the order
+    * they appear
+    */
+    pub fn resolve_next_values(&mut self,
+                               next_value: &mut dyn FnMut(&str) -> Result<i32, QueryError>) -> Result<(), QueryError> {
+        match self {
+            Expression::NextVal(sequence) => *self = Expression::Value(Value::Int(next_value(sequence)?)),
+            Expression::Value(_) | Expression::Identifier(_) | Expression::Now | Expression::CurrentDate
+            | Expression::Default => (),
+            Expression::Op(exp1, _, exp2) | Expression::Comp(exp1, _, exp2) | Expression::LogicOp(exp1, _, exp2)
+            | Expression::Like(exp1, exp2, ..) => {
+                exp1.resolve_next_values(next_value)?;
+                exp2.resolve_next_values(next_value)?;
+            },
+            Expression::In(exp, exps, _) => {
+                exp.resolve_next_values(next_value)?;
+                for exp in exps {
+                    exp.resolve_next_values(next_value)?;
+                }
+            },
+            Expression::Between(exp, low, high, _) => {
+                exp.resolve_next_values(next_value)?;
+                low.resolve_next_values(next_value)?;
+                high.resolve_next_values(next_value)?;
+            },
+            Expression::Not(exp) | Expression::Cast(exp, _) | Expression::IsNull(exp, _) | Expression::Lower(exp)
+            | Expression::Upper(exp) => exp.resolve_next_values(next_value)?,
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::IntegerDivide => "DIV",
+            Operator::Modulo => "%",
+        })
+    }
+}
+
+impl std::fmt::Display for Comparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Comparator::Eq => "=",
+            Comparator::Neq => "!=",
+            Comparator::Gt => ">",
+            Comparator::Gte => ">=",
+            Comparator::Lt => "<",
+            Comparator::Lte => "<=",
+        })
+    }
+}
+
+impl std::fmt::Display for LogicOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            LogicOperator::And => "AND",
+            LogicOperator::Or => "OR",
+        })
+    }
+}
+
+/**
+* Format an operand of a binary expression, wrapping it in parentheses when it's an expression with
+* operators itself, so the text keeps the evaluation order
+*/
+fn format_operand(expression: &Expression) -> String {
+    match expression {
+        Expression::Op(..) | Expression::Comp(..) | Expression::LogicOp(..) | Expression::Like(..)
+        | Expression::In(..) | Expression::Between(..) | Expression::IsNull(..) => format!("({})", expression),
+        expression => expression.to_string(),
+    }
+}
+
+/**
+* Format an expression as SQL text, e.g. to name the result column it computes
+*/
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Value(value) => write!(f, "{}", value.to_literal()),
+            Expression::Identifier(name) => write!(f, "{}", name),
+            Expression::Op(exp1, operator, exp2) =>
+                write!(f, "{} {} {}", format_operand(exp1), operator, format_operand(exp2)),
+            Expression::Comp(exp1, comparator, exp2) =>
+                write!(f, "{} {} {}", format_operand(exp1), comparator, format_operand(exp2)),
+            Expression::LogicOp(exp1, logic_operator, exp2) =>
+                write!(f, "{} {} {}", format_operand(exp1), logic_operator, format_operand(exp2)),
+            Expression::Not(exp) => write!(f, "NOT {}", format_operand(exp)),
+            Expression::Cast(exp, value_type) => write!(f, "CAST({} AS {})", exp, value_type.get_keyword()),
+            Expression::Lower(exp) => write!(f, "LOWER({})", exp),
+            Expression::Upper(exp) => write!(f, "UPPER({})", exp),
+            Expression::Like(exp1, exp2, escape, negated, case_insensitive) => {
+                write!(f, "{} {}{}LIKE {}", format_operand(exp1), format_not(*negated),
+                       if *case_insensitive { "I" } else { "" }, format_operand(exp2))?;
+                match escape {
+                    Some(escape) => write!(f, " ESCAPE {}", Value::Str(escape.clone()).to_literal()),
+                    None => Ok(()),
+                }
+            },
+            Expression::In(exp, exps, negated) => {
+                let values: Vec<String> = exps.iter().map(|exp| exp.to_string()).collect();
+                write!(f, "{} {}IN ({})", format_operand(exp), format_not(*negated), values.join(", "))
+            },
+            Expression::Between(exp, low, high, negated) => write!(f, "{} {}BETWEEN {} AND {}",
+                format_operand(exp), format_not(*negated), format_operand(low), format_operand(high)),
+            Expression::IsNull(exp, negated) => write!(f, "{} IS {}NULL", format_operand(exp), format_not(*negated)),
+            Expression::Now => write!(f, "NOW()"),
+            Expression::CurrentDate => write!(f, "CURRENT_DATE"),
+            Expression::Default => write!(f, "DEFAULT"),
+            Expression::NextVal(sequence) => write!(f, "NEXTVAL({})", Value::Str(sequence.clone()).to_literal()),
         }
     }
 }
 
+fn format_not(negated: bool) -> &'static str {
+    if negated { "NOT " } else { "" }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Type {
     Str,
@@ -130,6 +624,69 @@ pub enum Type {
     Int,
     Float,
     Null,
+    Timestamp,
+    Date,
+    Bytes,
+}
+
+impl Type {
+    /**
+    * Get the SQL keyword of the type, as written in CREATE TABLE and CAST
+    */
+    pub fn get_keyword(&self) -> &'static str {
+        match self {
+            Type::Str => "STRING",
+            Type::Bool => "BOOL",
+            Type::Int => "INT",
+            Type::Float => "FLOAT",
+            Type::Null => "NULL",
+            Type::Timestamp => "TIMESTAMP",
+            Type::Date => "DATE",
+            Type::Bytes => "BYTES",
+        }
+    }
+
+    /**
+    * Get the types the operands of a binary arithmetic operator are converted to before applying it.
+    * These are the only implicit conversions between types:
+    * - Int and Int stay as Int, except for / which converts both to Float
+    * - Int and Float, in any order, are both converted to Float. Rejected in strict mode
+    * - Str and Str are only allowed for +, which concatenates them
+    * - % only accepts Int operands
+    * - Null with any type gives Null, as the result of any operator on NULL is NULL
+    * Any other combination is an error
+    */
+    pub fn coerce_for_binary_op(&self, other: &Type, operator: &Operator,
+                                mode: TypeMode) -> Result<(Type, Type), QueryError> {
+        match (self, other, operator) {
+            (Type::Null, _, _) | (_, Type::Null, _) => Ok((Type::Null, Type::Null)),
+            (Type::Int, Type::Int, Operator::Modulo) => Ok((Type::Int, Type::Int)),
+            (Type::Float, _, Operator::Modulo) | (_, Type::Float, Operator::Modulo) =>
+                Err(QueryError::ValidationError(String::from("The % operator is only supported for Int values"))),
+            (Type::Int, Type::Int, Operator::Divide) => Ok((Type::Float, Type::Float)),
+            (Type::Int, Type::Int, _) => Ok((Type::Int, Type::Int)),
+            (Type::Int, Type::Float, _) | (Type::Float, Type::Int, _) if mode == TypeMode::Strict =>
+                Err(QueryError::ValidationError(format!(
+                    "Mixed types for operator {:?}: {:?} and {:?}. Use CAST to convert one of them",
+                    operator, self, other))),
+            (Type::Int, Type::Float, _) | (Type::Float, Type::Int, _) | (Type::Float, Type::Float, _) =>
+                Ok((Type::Float, Type::Float)),
+            (Type::Str, Type::Str, Operator::Add) => Ok((Type::Str, Type::Str)),
+            _ => Err(invalid_operand_types(operator, self, other)),
+        }
+    }
+
+    /**
+    * Check that values of the two types can be compared. In strict mode both types must be the same,
+    * except for Null which can be compared with anything
+    */
+    pub fn check_comparison(&self, other: &Type, mode: TypeMode) -> Result<(), QueryError> {
+        if mode == TypeMode::Strict && self != other && *self != Type::Null && *other != Type::Null {
+            return Err(QueryError::ValidationError(format!(
+                "Mixed types in comparison: {:?} and {:?}. Use CAST to convert one of them", self, other)))
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -139,34 +696,42 @@ pub enum Value {
     Int(i32),
     Float(f32),
     Null,
+    // Microseconds since 1970-01-01 00:00:00 UTC
+    Timestamp(i64),
+    // Days since 1970-01-01
+    Date(i32),
+    // Raw binary data, which unlike Str doesn't have to be valid UTF-8
+    Bytes(Vec<u8>),
 }
 
 impl std::ops::Div for Value {
     type Output = Result<Self, QueryError>;
 
     fn div(self, other: Self) -> Self::Output {
-        match self {
-            Value::Int(value1) => match other {
-                Value::Int(value2) =>
-                    if value2 != 0 {
-                        Ok(Value::Float((value1 as f32) / (value2 as f32)))
-                    } else {
-                        Err(QueryError::ValidationError(String::from("Division by 0")))
-                    },
-                Value::Float(value2) =>
-                    if value2 != 0.0 {
-                        Ok(Value::Float((value1 as f32) / value2))
-                    } else {
-                        Err(QueryError::ValidationError(String::from("Division by 0")))
-                    },
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            Value::Float(value1) => match other {
-                Value::Int(value2) => Ok(Value::Float(value1 / (value2 as f32))),
-                Value::Float(value2) => Ok(Value::Float(value1 / value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
+        match self.coerce_for_binary_op(&other, &Operator::Divide)? {
+            (Value::Null, Value::Null) => Ok(Value::Null),
+            (Value::Float(_), Value::Float(value2)) if value2 == 0.0 =>
+                Err(QueryError::ValidationError(String::from("Division by 0"))),
+            (Value::Float(value1), Value::Float(value2)) => finite_float(value1 / value2),
+            _ => Err(invalid_operand_types(&Operator::Divide, &self.get_type(), &other.get_type())),
+        }
+    }
+}
+
+/**
+* Remainder of an integer division. The result takes the sign of the dividend, so -7 % 2 = -1 and
+* 7 % -2 = 1, consistent with DIV truncating towards zero. Float operands are rejected
+*/
+impl std::ops::Rem for Value {
+    type Output = Result<Self, QueryError>;
+
+    fn rem(self, other: Self) -> Self::Output {
+        match self.coerce_for_binary_op(&other, &Operator::Modulo)? {
+            (Value::Null, Value::Null) => Ok(Value::Null),
+            (Value::Int(_), Value::Int(0)) => Err(QueryError::ValidationError(String::from("Division by 0"))),
+            (Value::Int(value1), Value::Int(value2)) => value1.checked_rem(value2).map(Value::Int)
+                .ok_or_else(|| QueryError::ValidationError(String::from("Integer overflow"))),
+            _ => Err(invalid_operand_types(&Operator::Modulo, &self.get_type(), &other.get_type())),
         }
     }
 }
@@ -175,18 +740,12 @@ impl std::ops::Mul for Value {
     type Output = Result<Self, QueryError>;
 
     fn mul(self, other: Self) -> Self::Output {
-        match self {
-            Value::Int(value1) => match other {
-                Value::Int(value2) => Ok(Value::Int(value1 * value2)),
-                Value::Float(value2) => Ok(Value::Float((value1 as f32) * value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            Value::Float(value1) => match other {
-                Value::Int(value2) => Ok(Value::Float(value1 * (value2 as f32))),
-                Value::Float(value2) => Ok(Value::Float(value1 * value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
+        match self.coerce_for_binary_op(&other, &Operator::Multiply)? {
+            (Value::Null, Value::Null) => Ok(Value::Null),
+            (Value::Int(value1), Value::Int(value2)) => value1.checked_mul(value2).map(Value::Int)
+                .ok_or_else(|| QueryError::ValidationError(String::from("Integer overflow"))),
+            (Value::Float(value1), Value::Float(value2)) => finite_float(value1 * value2),
+            _ => Err(invalid_operand_types(&Operator::Multiply, &self.get_type(), &other.get_type())),
         }
     }
 }
@@ -195,22 +754,13 @@ impl std::ops::Add for Value {
     type Output = Result<Self, QueryError>;
 
     fn add(self, other: Self) -> Self::Output {
-        match self {
-            Value::Int(value1) => match other {
-                Value::Int(value2) => Ok(Value::Int(value1 + value2)),
-                Value::Float(value2) => Ok(Value::Float((value1 as f32) + value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            Value::Float(value1) => match other {
-                Value::Int(value2) => Ok(Value::Float(value1 + (value2 as f32))),
-                Value::Float(value2) => Ok(Value::Float(value1 + value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            Value::Str(value1) => match other {
-                Value::Str(value2) => Ok(Value::Str(value1 + &value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
+        match self.coerce_for_binary_op(&other, &Operator::Add)? {
+            (Value::Null, Value::Null) => Ok(Value::Null),
+            (Value::Int(value1), Value::Int(value2)) => value1.checked_add(value2).map(Value::Int)
+                .ok_or_else(|| QueryError::ValidationError(String::from("Integer overflow"))),
+            (Value::Float(value1), Value::Float(value2)) => finite_float(value1 + value2),
+            (Value::Str(value1), Value::Str(value2)) => Ok(Value::Str(value1 + &value2)),
+            _ => Err(invalid_operand_types(&Operator::Add, &self.get_type(), &other.get_type())),
         }
     }
 }
@@ -219,22 +769,32 @@ impl std::ops::Sub for Value {
     type Output = Result<Self, QueryError>;
 
     fn sub(self, other: Self) -> Self::Output {
-        match self {
-            Value::Int(value1) => match other {
-                Value::Int(value2) => Ok(Value::Int(value1- value2)),
-                Value::Float(value2) => Ok(Value::Float((value1 as f32) - value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            Value::Float(value1) => match other {
-                Value::Int(value2) => Ok(Value::Float(value1 - (value2 as f32))),
-                Value::Float(value2) => Ok(Value::Float(value1 - value2)),
-                _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
-            },
-            _ => Err(QueryError::ValidationError(String::from("Invalid types for operator"))),
+        match self.coerce_for_binary_op(&other, &Operator::Subtract)? {
+            (Value::Null, Value::Null) => Ok(Value::Null),
+            (Value::Int(value1), Value::Int(value2)) => value1.checked_sub(value2).map(Value::Int)
+                .ok_or_else(|| QueryError::ValidationError(String::from("Integer overflow"))),
+            (Value::Float(value1), Value::Float(value2)) => finite_float(value1 - value2),
+            _ => Err(invalid_operand_types(&Operator::Subtract, &self.get_type(), &other.get_type())),
         }
     }
 }
 
+/**
+* Wrap the result of a float operation, rejecting NaN and infinite results. Float values are always
+* finite, so they can be compared and ordered consistently
+*/
+pub fn finite_float(value: f32) -> Result<Value, QueryError> {
+    if value.is_finite() {
+        Ok(Value::Float(value))
+    } else {
+        Err(QueryError::ValidationError(format!("Float overflow: the result is {}", value)))
+    }
+}
+
+fn invalid_operand_types(operator: &Operator, type1: &Type, type2: &Type) -> QueryError {
+    QueryError::ValidationError(format!("Invalid types for operator {:?}: {:?} and {:?}", operator, type1, type2))
+}
+
 impl std::cmp::PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match self {
@@ -256,6 +816,20 @@ impl std::cmp::PartialEq for Value {
                 Value::Bool(value2) => value1 == value2,
                 _ => false,
             },
+            Value::Timestamp(value1) => match other {
+                Value::Timestamp(value2) => value1 == value2,
+                Value::Date(value2) => *value1 == temporal::timestamp_of_date(*value2),
+                _ => false,
+            },
+            Value::Date(value1) => match other {
+                Value::Date(value2) => value1 == value2,
+                Value::Timestamp(value2) => temporal::timestamp_of_date(*value1) == *value2,
+                _ => false,
+            },
+            Value::Bytes(value1) => match other {
+                Value::Bytes(value2) => value1 == value2,
+                _ => false,
+            },
             Value::Null => false,
         }
     }
@@ -278,83 +852,656 @@ impl std::cmp::PartialOrd for Value {
                 Value::Str(value2) => Some(value1.cmp(value2)),
                 _ => None,
             },
+            // Dates are compared with timestamps as the midnight that starts them
+            Value::Timestamp(value1) => match other {
+                Value::Timestamp(value2) => Some(value1.cmp(value2)),
+                Value::Date(value2) => Some(value1.cmp(&temporal::timestamp_of_date(*value2))),
+                _ => None,
+            },
+            Value::Date(value1) => match other {
+                Value::Date(value2) => Some(value1.cmp(value2)),
+                Value::Timestamp(value2) => Some(temporal::timestamp_of_date(*value1).cmp(value2)),
+                _ => None,
+            },
+            // Byte by byte, so a prefix sorts first
+            Value::Bytes(value1) => match other {
+                Value::Bytes(value2) => Some(value1.cmp(value2)),
+                _ => None,
+            },
             Value::Bool(_) => None,
             Value::Null => None,
         }
     }
 }
 
+/**
+* Values that identify a group of GROUP BY or a row of DISTINCT. Unlike =, where NULL is never equal
+* to anything, two NULLs are the same key, and so are NaNs. Values equal under = are the same key, e.g.
+* Int(1) and Float(1.0) or a date and the timestamp of its midnight
+*/
+#[derive(Debug, Clone)]
+pub struct GroupKey(pub Vec<Value>);
+
 impl Value {
     /**
-    * Build value from bytes
+    * Test if two values fall in the same group, where NULL is a value like any other
     */
-    pub fn from_be_bytes(bytes: Vec<u8>, value_type: &Type) -> Value {
-        match value_type {
-            Type::Str => Value::Str(String::from_utf8(bytes).unwrap()),
-            Type::Bool => Value::Bool(if bytes[0] == 1u8 { true } else { false }),
-            Type::Int => Value::Int(i32::from_be_bytes(
-                [bytes[0], bytes[1], bytes[2], bytes[3]])),
-            Type::Float => Value::Float(f32::from_be_bytes(
-                [bytes[0], bytes[1], bytes[2], bytes[3]])),
-            Type::Null => Value::Null,
+    pub fn is_not_distinct_from(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Float(value1), Value::Float(value2)) if value1.is_nan() && value2.is_nan() => true,
+            (value1, value2) => value1 == value2,
         }
     }
 
     /**
-    * Get the type of this Value
+    * Feed the value to a hasher consistently with is_not_distinct_from. Numbers are hashed as f32,
+    * as that's how Int and Float are compared, and dates as the timestamp of their midnight
     */
-    pub fn get_type(&self) -> Type {
+    fn hash_grouped<H: std::hash::Hasher>(&self, state: &mut H) {
+        use std::hash::Hash;
         match self {
-            Value::Str(_) => Type::Str,
-            Value::Bool(_) => Type::Bool,
-            Value::Int(_) => Type::Int,
-            Value::Float(_) => Type::Float,
-            Value::Null => Type::Null,
+            Value::Null => 0u8.hash(state),
+            Value::Int(value) => hash_float(*value as f32, state),
+            Value::Float(value) => hash_float(*value, state),
+            Value::Str(value) => {
+                2u8.hash(state);
+                value.hash(state);
+            },
+            Value::Bool(value) => {
+                3u8.hash(state);
+                value.hash(state);
+            },
+            Value::Timestamp(value) => {
+                4u8.hash(state);
+                value.hash(state);
+            },
+            Value::Date(value) => {
+                4u8.hash(state);
+                temporal::timestamp_of_date(*value).hash(state);
+            },
+            Value::Bytes(value) => {
+                5u8.hash(state);
+                value.hash(state);
+            },
         }
     }
+}
 
-    /**
-    * Test if a value has the provided type
-    */
-    pub fn has_type(&self, value_type: &Type) -> bool {
-        if let
-            (Type::Str, Type::Str) |
-            (Type::Bool, Type::Bool) |
-            (Type::Int, Type::Int) |
-            (Type::Float, Type::Float)
-        = (self.get_type(), value_type) {
-            true
-        } else {
-            false
-        }
-    }
+fn hash_float<H: std::hash::Hasher>(value: f32, state: &mut H) {
+    use std::hash::Hash;
+    1u8.hash(state);
+    // 0.0 and -0.0 are equal, and every NaN is the same key
+    let value = if value == 0.0 { 0.0 } else if value.is_nan() { std::f32::NAN } else { value };
+    value.to_bits().hash(state);
+}
 
-    /**
-    * Get u8 array of the value
-    */
-    pub fn to_be_bytes(&self) -> Vec<u8> {
-        match self {
-            Value::Str(val) => val.clone().into_bytes(),
-            Value::Int(val) => val.clone().to_be_bytes().to_vec(),
-            Value::Float(val) => val.clone().to_be_bytes().to_vec(),
-            Value::Bool(val) => (if *val { 1u8 } else { 0u8 }).to_be_bytes().to_vec(),
-            Value::Null => Vec::new(),
-        }
+fn values_not_distinct(values1: &[Value], values2: &[Value]) -> bool {
+    values1.len() == values2.len()
+        && values1.iter().zip(values2).all(|(value1, value2)| value1.is_not_distinct_from(value2))
+}
+
+impl std::cmp::PartialEq for GroupKey {
+    fn eq(&self, other: &Self) -> bool {
+        values_not_distinct(&self.0, &other.0)
     }
+}
 
-    pub fn get_bool(&self) -> Result<bool, QueryError> {
-        match self {
-            Value::Bool(value) => Ok(value.clone()),
-            _ => Err(QueryError::ValidationError(String::from("Value is not boolean")))
+impl std::cmp::Eq for GroupKey {}
+
+impl std::hash::Hash for GroupKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for value in &self.0 {
+            value.hash_grouped(state);
+        }
+    }
+}
+
+impl Record {
+    /**
+    * Test if the records have the same values, comparing them like the rows of DISTINCT: NULL is equal
+    * to NULL, but not to an empty string, and values equal under = are equal, e.g. Int(1) and Float(1.0)
+    */
+    pub fn eq_values(&self, other: &Record) -> bool {
+        values_not_distinct(&self.values, &other.values)
+    }
+}
+
+/**
+* Records are equal with Record::eq_values and hashed consistently with it, so duplicate rows can be
+* found with a set
+*/
+impl std::cmp::PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_values(other)
+    }
+}
+
+impl std::cmp::Eq for Record {}
+
+impl std::hash::Hash for Record {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for value in &self.values {
+            value.hash_grouped(state);
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Str(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Timestamp(value) => write!(f, "{}", temporal::format_timestamp(*value)),
+            Value::Date(value) => write!(f, "{}", temporal::format_date(*value)),
+            Value::Bytes(value) => write!(f, "\\x{}", encode_hex(value)),
+            Value::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+impl Value {
+    /**
+    * Format the value as a SQL literal, quoting strings
+    */
+    pub fn to_literal(&self) -> String {
+        match self {
+            Value::Str(value) => format!("'{}'", value.replace('\'', "''")),
+            Value::Timestamp(value) => format!("TIMESTAMP '{}'", temporal::format_timestamp(*value)),
+            Value::Date(value) => format!("DATE '{}'", temporal::format_date(*value)),
+            Value::Bytes(value) => format!("X'{}'", encode_hex(value).to_uppercase()),
+            value => value.to_string(),
+        }
+    }
+
+    /**
+    * Build value from bytes
+    */
+    pub fn from_be_bytes(bytes: Vec<u8>, value_type: &Type) -> Result<Value, PagingError> {
+        Ok(match value_type {
+            Type::Str => Value::Str(String::from_utf8(bytes).map_err(
+                |_| PagingError::CorruptedData(String::from("Invalid UTF-8 string")))?),
+            Type::Bool => Value::Bool(if bytes[0] == 1u8 { true } else { false }),
+            Type::Int => Value::Int(i32::from_be_bytes(
+                [bytes[0], bytes[1], bytes[2], bytes[3]])),
+            Type::Float => Value::Float(f32::from_be_bytes(
+                [bytes[0], bytes[1], bytes[2], bytes[3]])),
+            Type::Timestamp => Value::Timestamp(i64::from_be_bytes(bytes.as_slice().try_into().map_err(
+                |_| PagingError::CorruptedData(format!("Invalid timestamp of {} bytes", bytes.len())))?)),
+            Type::Date => Value::Date(i32::from_be_bytes(bytes.as_slice().try_into().map_err(
+                |_| PagingError::CorruptedData(format!("Invalid date of {} bytes", bytes.len())))?)),
+            Type::Bytes => Value::Bytes(bytes),
+            Type::Null => Value::Null,
+        })
+    }
+
+    /**
+    * Get the type of this Value
+    */
+    pub fn get_type(&self) -> Type {
+        match self {
+            Value::Str(_) => Type::Str,
+            Value::Bool(_) => Type::Bool,
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Timestamp(_) => Type::Timestamp,
+            Value::Date(_) => Type::Date,
+            Value::Bytes(_) => Type::Bytes,
+            Value::Null => Type::Null,
+        }
+    }
+
+    /**
+    * Test if this is a Null value
+    */
+    pub fn is_null(&self) -> bool {
+        if let Value::Null = self { true } else { false }
+    }
+
+    /**
+    * Test if a value has the provided type
+    */
+    pub fn has_type(&self, value_type: &Type) -> bool {
+        if let
+            (Type::Str, Type::Str) |
+            (Type::Bool, Type::Bool) |
+            (Type::Int, Type::Int) |
+            (Type::Float, Type::Float) |
+            (Type::Timestamp, Type::Timestamp) |
+            (Type::Date, Type::Date) |
+            (Type::Bytes, Type::Bytes)
+        = (self.get_type(), value_type) {
+            true
+        } else {
+            false
+        }
+    }
+
+    /**
+    * Get u8 array of the value
+    */
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Str(val) => val.clone().into_bytes(),
+            Value::Int(val) => val.clone().to_be_bytes().to_vec(),
+            Value::Float(val) => val.clone().to_be_bytes().to_vec(),
+            Value::Bool(val) => (if *val { 1u8 } else { 0u8 }).to_be_bytes().to_vec(),
+            Value::Timestamp(val) => val.to_be_bytes().to_vec(),
+            Value::Date(val) => val.to_be_bytes().to_vec(),
+            Value::Bytes(val) => val.clone(),
+            Value::Null => Vec::new(),
+        }
+    }
+
+    /**
+    * Convert the operands of a binary arithmetic operator to the types the operator works with, as
+    * described in Type::coerce_for_binary_op. Values are always coerced leniently, since strict mode is
+    * enforced by checking the query types before running it
+    */
+    pub fn coerce_for_binary_op(&self, other: &Self, operator: &Operator) -> Result<(Value, Value), QueryError> {
+        let (type1, type2) = self.get_type().coerce_for_binary_op(&other.get_type(), operator, TypeMode::Lenient)?;
+        Ok((self.cast(&type1)?, other.cast(&type2)?))
+    }
+
+    /**
+    * Parse a string compared with a date or timestamp into that type, so '2024-01-31' can be compared
+    * with a DATE column. Other values are left alone. Strict mode rejects these comparisons before
+    * running the query, so it still needs DATE and TIMESTAMP literals
+    */
+    pub fn parse_for_comparison(self, other: &Value) -> Result<Value, QueryError> {
+        match (&self, other) {
+            (Value::Str(text), Value::Date(_)) | (Value::Str(text), Value::Timestamp(_)) => {
+                let other_type = other.get_type();
+                self.cast(&other_type).map_err(|_| QueryError::ValidationError(format!(
+                    "Can't compare '{}' with a {:?}, as it isn't a valid {:?}", text, other_type, other_type)))
+            },
+            _ => Ok(self),
+        }
+    }
+
+    /**
+    * Reject ordering booleans, as in (a < b) < c. Booleans have no order, so the comparison would
+    * always be false instead of comparing the operands the way it reads
+    */
+    fn check_ordered_comparison(&self, comparator: &Comparator, other: &Value) -> Result<(), QueryError> {
+        let is_ordering = match comparator {
+            Comparator::Eq | Comparator::Neq => false,
+            _ => true,
+        };
+        let is_bool = |value: &Value| match value {
+            Value::Bool(_) => true,
+            _ => false,
+        };
+        if is_ordering && (is_bool(self) || is_bool(other)) {
+            return Err(QueryError::ValidationError(format!("Cannot compare boolean with {}", comparator)))
+        }
+        Ok(())
+    }
+
+    /**
+    * Convert the value to the given type. Null stays Null for every type, and strings are parsed
+    * when converted to numbers or booleans. Strings converted to bytes are taken as hex when they
+    * start with \x, like bytes are shown, so bytes survive a trip through a string, and as their
+    * UTF-8 bytes otherwise
+    */
+    pub fn cast(&self, value_type: &Type) -> Result<Value, QueryError> {
+        let invalid_cast = || QueryError::ValidationError(format!("Can't cast {:?} to {:?}", self, value_type));
+        match (self, value_type) {
+            (Value::Null, _) | (_, Type::Null) => Ok(Value::Null),
+            (value, value_type) if value.has_type(value_type) => Ok(value.clone()),
+            (Value::Int(value), Type::Float) => Ok(Value::Float(*value as f32)),
+            (Value::Float(value), Type::Int) => Ok(Value::Int(value.trunc() as i32)),
+            (Value::Bool(value), Type::Int) => Ok(Value::Int(if *value { 1 } else { 0 })),
+            (Value::Int(value), Type::Bool) => Ok(Value::Bool(*value != 0)),
+            (value, Type::Str) => Ok(Value::Str(value.to_string())),
+            (Value::Str(value), Type::Int) => value.trim().parse().map(Value::Int).map_err(|_| invalid_cast()),
+            (Value::Str(value), Type::Float) => match value.trim().parse() {
+                Ok(value) => finite_float(value),
+                Err(_) => Err(invalid_cast()),
+            },
+            (Value::Str(value), Type::Bool) => match value.trim().to_lowercase().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(invalid_cast()),
+            },
+            (Value::Str(value), Type::Timestamp) => Ok(Value::Timestamp(temporal::parse_timestamp(value)?)),
+            (Value::Str(value), Type::Date) => Ok(Value::Date(temporal::parse_date(value)?)),
+            (Value::Timestamp(value), Type::Date) => Ok(Value::Date(temporal::date_of_timestamp(*value))),
+            (Value::Date(value), Type::Timestamp) => Ok(Value::Timestamp(temporal::timestamp_of_date(*value))),
+            (Value::Str(value), Type::Bytes) => match value.strip_prefix("\\x") {
+                Some(hex) => decode_hex(hex).map(Value::Bytes).ok_or_else(invalid_cast),
+                None => Ok(Value::Bytes(value.clone().into_bytes())),
+            },
+            _ => Err(invalid_cast()),
+        }
+    }
+
+    /**
+    * Divide two numeric values, truncating the quotient towards zero. Unlike the / operator, which
+    * always returns a Float, the result of this division is always an Int, so a quotient of Floats
+    * that doesn't fit one is an overflow
+    */
+    pub fn integer_divide(self, other: Self) -> Result<Self, QueryError> {
+        match self.coerce_for_binary_op(&other, &Operator::IntegerDivide)? {
+            (Value::Null, Value::Null) => Ok(Value::Null),
+            (Value::Int(_), Value::Int(0)) => Err(QueryError::ValidationError(String::from("Division by 0"))),
+            (Value::Int(value1), Value::Int(value2)) => value1.checked_div(value2).map(Value::Int)
+                .ok_or_else(|| QueryError::ValidationError(String::from("Integer overflow"))),
+            (Value::Float(_), Value::Float(value2)) if value2 == 0.0 =>
+                Err(QueryError::ValidationError(String::from("Division by 0"))),
+            (Value::Float(value1), Value::Float(value2)) => {
+                let quotient = (value1 / value2).trunc();
+                // -(i32::MIN as f32) is 2^31, as i32::MAX can't be represented exactly as a f32
+                if quotient.is_finite() && quotient >= i32::MIN as f32 && quotient < -(i32::MIN as f32) {
+                    Ok(Value::Int(quotient as i32))
+                } else {
+                    Err(QueryError::ValidationError(String::from("Integer overflow")))
+                }
+            },
+            _ => Err(invalid_operand_types(&Operator::IntegerDivide, &self.get_type(), &other.get_type())),
+        }
+    }
+
+    /**
+    * Match this value against a LIKE pattern, where % matches any sequence of characters and _
+    * matches exactly one character. The escape character makes the character after it match
+    * literally. Null values never match
+    */
+    pub fn like(&self, pattern: &Value, escape: Option<char>, case_insensitive: bool) -> Result<bool, QueryError> {
+        match (self, pattern) {
+            (Value::Str(value), Value::Str(pattern)) if case_insensitive => {
+                let value: Vec<char> = value.to_lowercase().chars().collect();
+                Ok(like_matches(&value, &parse_like_pattern(&pattern.to_lowercase(), escape)?))
+            },
+            (Value::Str(value), Value::Str(pattern)) => {
+                let value: Vec<char> = value.chars().collect();
+                Ok(like_matches(&value, &parse_like_pattern(pattern, escape)?))
+            },
+            _ if self.is_null() || pattern.is_null() => Ok(false),
+            _ => Err(QueryError::ValidationError(String::from("LIKE is only supported for Str values"))),
+        }
+    }
+
+    /**
+    * Total order of values used to sort rows: values of the same type compare like in comparisons,
+    * NULL goes after everything else and values of different types that can't be compared are
+    * ordered by their type
+    */
+    pub fn sort_cmp(&self, other: &Value) -> std::cmp::Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => std::cmp::Ordering::Equal,
+            (Value::Null, _) => std::cmp::Ordering::Greater,
+            (_, Value::Null) => std::cmp::Ordering::Less,
+            (Value::Bool(value1), Value::Bool(value2)) => value1.cmp(value2),
+            (value1, value2) => match value1.partial_cmp(value2) {
+                Some(ordering) => ordering,
+                None => value1.type_rank().cmp(&value2.type_rank()),
+            },
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Bool(_) => 0,
+            Value::Int(_) | Value::Float(_) => 1,
+            Value::Str(_) => 2,
+            Value::Date(_) | Value::Timestamp(_) => 3,
+            Value::Bytes(_) => 4,
+            Value::Null => 5,
+        }
+    }
+
+    /**
+    * Convert the case of a Str value with f, for the function with the given name. Null stays Null
+    */
+    fn change_case(self, function: &str, f: fn(&str) -> String) -> Result<Value, QueryError> {
+        match self {
+            Value::Str(value) => Ok(Value::Str(f(&value))),
+            Value::Null => Ok(Value::Null),
+            value => Err(QueryError::ValidationError(
+                format!("{} needs a Str value, but it's {:?}", function, value.get_type()))),
+        }
+    }
+
+    pub fn get_bool(&self) -> Result<bool, QueryError> {
+        match self {
+            Value::Bool(value) => Ok(value.clone()),
+            _ => Err(QueryError::ValidationError(String::from("Value is not boolean")))
+        }
+    }
+
+    /**
+    * Get the boolean value, where NULL stands for an unknown truth value
+    */
+    pub fn get_nullable_bool(&self) -> Result<Option<bool>, QueryError> {
+        match self {
+            Value::Null => Ok(None),
+            value => Ok(Some(value.get_bool()?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LikeToken {
+    // %
+    AnySequence,
+    // _
+    AnyChar,
+    Char(char),
+}
+
+/**
+* Split a LIKE pattern into wildcards and literal characters. A character preceded by the escape
+* character is always literal
+*/
+fn parse_like_pattern(pattern: &str, escape: Option<char>) -> Result<Vec<LikeToken>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            c if Some(c) == escape => match chars.next() {
+                Some(escaped) => LikeToken::Char(escaped),
+                None => return Err(QueryError::ValidationError(
+                    format!("The LIKE pattern '{}' ends with the escape character", pattern)))
+            },
+            '%' => LikeToken::AnySequence,
+            '_' => LikeToken::AnyChar,
+            c => LikeToken::Char(c),
+        });
+    }
+    Ok(tokens)
+}
+
+/**
+* Match value against a LIKE pattern. Backtracks to the last % seen on a mismatch, which keeps the
+* matching linear for patterns with a single %
+*/
+fn like_matches(value: &[char], pattern: &[LikeToken]) -> bool {
+    let (mut value_idx, mut pattern_idx) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while value_idx < value.len() {
+        match pattern.get(pattern_idx) {
+            Some(LikeToken::AnySequence) => {
+                pattern_idx += 1;
+                backtrack = Some((pattern_idx, value_idx));
+            },
+            Some(LikeToken::AnyChar) => {
+                pattern_idx += 1;
+                value_idx += 1;
+            },
+            Some(LikeToken::Char(c)) if *c == value[value_idx] => {
+                pattern_idx += 1;
+                value_idx += 1;
+            },
+            _ => match backtrack {
+                Some((wildcard_pattern_idx, wildcard_value_idx)) => {
+                    pattern_idx = wildcard_pattern_idx;
+                    value_idx = wildcard_value_idx + 1;
+                    backtrack = Some((wildcard_pattern_idx, value_idx));
+                },
+                None => return false,
+            }
+        }
+    }
+    pattern[pattern_idx..].iter().all(|token| *token == LikeToken::AnySequence)
+}
+
+#[derive(Debug, Clone)]
+pub enum SelectExpression {
+    Expression(Box<Expression>),
+    // COUNT(*), the number of matching records
+    CountAll,
+    // COUNT(DISTINCT expression), the number of different non NULL values of the expression
+    CountDistinct(Box<Expression>),
+    // MIN(expression) and MAX(expression), the lowest and highest non NULL value of the expression
+    Min(Box<Expression>),
+    Max(Box<Expression>),
+    // table.*, every column of the table of FROM with that name, in definition order. It's replaced
+    // by the columns once the tables are known
+    QualifiedAll(String),
+}
+
+impl SelectExpression {
+    pub fn is_aggregate(&self) -> bool {
+        match self {
+            SelectExpression::Expression(_) | SelectExpression::QualifiedAll(_) => false,
+            SelectExpression::CountAll | SelectExpression::CountDistinct(_) | SelectExpression::Min(_)
+                | SelectExpression::Max(_) => true,
+        }
+    }
+
+    /**
+    * Get the expression an aggregate is computed over, if it has one
+    */
+    pub fn get_aggregated_expression(&self) -> Option<&Expression> {
+        match self {
+            SelectExpression::CountDistinct(expression) | SelectExpression::Min(expression)
+                | SelectExpression::Max(expression) => Some(expression),
+            SelectExpression::Expression(_) | SelectExpression::CountAll | SelectExpression::QualifiedAll(_) => None,
+        }
+    }
+
+    pub fn get_aggregated_expression_mut(&mut self) -> Option<&mut Expression> {
+        match self {
+            SelectExpression::CountDistinct(expression) | SelectExpression::Min(expression)
+                | SelectExpression::Max(expression) => Some(expression),
+            SelectExpression::Expression(_) | SelectExpression::CountAll | SelectExpression::QualifiedAll(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SelectExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SelectExpression::Expression(expression) => write!(f, "{}", expression),
+            SelectExpression::CountAll => write!(f, "COUNT(*)"),
+            SelectExpression::CountDistinct(expression) => write!(f, "COUNT(DISTINCT {})", expression),
+            SelectExpression::Min(expression) => write!(f, "MIN({})", expression),
+            SelectExpression::Max(expression) => write!(f, "MAX({})", expression),
+            SelectExpression::QualifiedAll(table) => write!(f, "{}.*", table),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct SelectItem {
+    pub expression: SelectExpression,
+    pub alias: Option<String>,
+}
+
+impl SelectItem {
+    /**
+    * Get the name of the result column: the alias if there is one, the column name for a plain
+    * column, or else the text of the expression
+    */
+    pub fn get_name(&self) -> String {
+        match (&self.alias, &self.expression) {
+            (Some(alias), _) => alias.clone(),
+            (None, SelectExpression::Expression(expression)) => match expression.as_ref() {
+                Expression::Identifier(name) => name.clone(),
+                expression => expression.to_string(),
+            },
+            (None, expression) => expression.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SelectColumns {
+    // Every column of the table, in definition order
+    All,
+    Items(Vec<SelectItem>),
+}
+
 #[derive(Debug, Clone)]
 pub struct SelectQuery {
+    // None for a SELECT without FROM, which returns a single row
+    pub table: Option<String>,
+    // Name the columns of the table are qualified with instead of the table name
+    pub alias: Option<String>,
+    // Tables joined to the table, in order
+    pub joins: Vec<Join>,
+    pub columns: SelectColumns,
+    pub condition: Option<Box<Expression>>,
+    // Expressions the records are grouped by, each group giving a single result row
+    pub group_by: Vec<Box<Expression>>,
+    // Drop result rows equal to an earlier one
+    pub distinct: bool,
+    pub order_by: Vec<OrderBy>,
+}
+
+/**
+* A table joined to the tables before it in FROM. Every combination of a row of the tables before it
+* and a row of this table that matches the condition is a row of the join. Without a condition every
+* combination is
+*/
+#[derive(Debug, Clone)]
+pub struct Join {
     pub table: String,
-    pub columns: Vec<String>,
+    pub alias: Option<String>,
     pub condition: Option<Box<Expression>>,
+    pub kind: JoinKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    // Every combination of rows, requested explicitly, so it never has a condition
+    Cross,
+}
+
+impl Join {
+    /**
+    * Name the columns of the joined table are qualified with
+    */
+    pub fn get_qualifier(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.table)
+    }
+}
+
+/**
+* A key of ORDER BY. NULL sorts after every other value, so it comes last in ascending order and
+* first in descending order
+*/
+#[derive(Debug, Clone)]
+pub struct OrderBy {
+    pub expression: Box<Expression>,
+    pub descending: bool,
+}
+
+/**
+* Insert the rows returned by a SELECT, matching the selected columns by position to the listed
+* columns of the target table, or to all its columns in definition order without a column list
+*/
+#[derive(Debug, Clone)]
+pub struct InsertSelectQuery {
+    pub table: String,
+    // Omitted columns get their default, or Null
+    pub columns: Option<Vec<String>>,
+    pub select: SelectQuery,
+    pub on_conflict: Option<OnConflict>,
 }
 
 #[derive(Debug, Clone)]
@@ -362,44 +1509,636 @@ pub struct InsertQuery {
     pub table: String,
     pub columns: Vec<String>,
     pub values: Vec<Box<Expression>>,
+    pub on_conflict: Option<OnConflict>,
+}
+
+// In ON CONFLICT DO UPDATE SET, the values of the row that couldn't be inserted are referenced as
+// EXCLUDED.column, which is parsed as an identifier with this prefix
+pub const EXCLUDED_PREFIX: &str = "excluded.";
+
+/**
+* What to do with an inserted row that has the same key as a stored row on a PRIMARY KEY or UNIQUE
+* constraint. Without target columns, any of those constraints is checked
+*/
+#[derive(Debug, Clone)]
+pub struct OnConflict {
+    pub target: Option<Vec<String>>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConflictAction {
+    // Skip the row
+    Nothing,
+    // Update the stored row instead
+    Update(Vec<ColumnValue>),
+}
+
+impl OnConflict {
+    fn resolve_current_time(&mut self, now: i64) {
+        if let ConflictAction::Update(column_values) = &mut self.action {
+            for column_value in column_values {
+                column_value.value.resolve_current_time(now);
+            }
+        }
+    }
 }
 
 impl InsertQuery {
-    pub fn evaluate_expressions(&self) -> Result<Vec<Value>, QueryError> {
+    /**
+    * Check that the values don't reference columns, as there is no row to take them from. Functions
+    * like NOW() are constant for the statement, so they are allowed
+    */
+    pub fn check_constant_values(&self) -> Result<(), QueryError> {
+        for (column, expression) in self.columns.iter().zip(&self.values) {
+            if let Some(identifier) = expression.get_identifiers().first() {
+                return Err(QueryError::ValidationError(format!(
+                    "The value of {} references the column {}, but INSERT values can only be constants",
+                    column, identifier)))
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Evaluate the values, replacing DEFAULT with the default of its column, or Null if it has none.
+    * Primary key columns can't be Null, so DEFAULT is rejected for them unless they have a default
+    */
+    pub fn evaluate_expressions(&self, table: &Table) -> Result<Vec<Value>, QueryError> {
+        self.check_constant_values()?;
         let mut evaluated_expressions = Vec::new();
-        for expression in &self.values {
-            evaluated_expressions.push((*expression).evaluate(Option::None)?);
+        for (column_name, expression) in self.columns.iter().zip(&self.values) {
+            let value = match (expression.as_ref(), table.get_column(column_name)) {
+                (Expression::Default, Some(column)) => match &column.default {
+                    Some(default) => default.evaluate(),
+                    None if table.is_primary_key_column(column_name) => return Err(QueryError::ValidationError(
+                        format!("The column {} has no default, and it can't be NULL as it's part of the primary key",
+                                column_name))),
+                    None => Value::Null,
+                },
+                // Unknown columns are reported when the values are validated
+                (Expression::Default, None) => Value::Null,
+                (expression, _) => expression.evaluate(None)?,
+            };
+            evaluated_expressions.push(value);
+        }
+        // Extra values without a column are reported when the values are validated too
+        for expression in self.values.iter().skip(self.columns.len()) {
+            evaluated_expressions.push(expression.evaluate(None)?);
         }
         Ok(evaluated_expressions)
     }
 }
 
+/**
+* Load the records of a CSV file into a table. Trusted loads skip the validation pass over the file
+*/
+#[derive(Debug, Clone)]
+pub struct CopyQuery {
+    pub table: String,
+    pub path: String,
+    pub format: CopyFormat,
+    pub trusted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CopyFormat {
+    // A header with the column names of the table followed by a line per record
+    Csv,
+    // An array of objects whose keys are column names
+    Json,
+}
+
 #[derive(Debug, Clone)]
 pub struct UpdateQuery {
     pub table: String,
     pub column_values: Vec<ColumnValue>,
+    pub condition: Option<Box<Expression>>,
+    // Maximum number of rows to update, taken in scan order
+    pub limit: Option<usize>,
+    // Columns of the updated rows to return, with their new values
+    pub returning: Option<SelectColumns>,
 }
 
 #[derive(Debug, Clone)]
 pub struct DeleteQuery {
     pub table: String,
+    pub condition: Option<Box<Expression>>,
+    // Maximum number of rows to delete, taken in scan order
+    pub limit: Option<usize>,
+    // Columns of the deleted rows to return, with the values they had
+    pub returning: Option<SelectColumns>,
 }
 
 #[derive(Debug, Clone)]
 pub struct CreateTableQuery {
     pub table: String,
     pub columns: Vec<Column>,
+    pub constraints: Vec<TableConstraint>,
+    pub comment: Option<String>,
+}
+
+/**
+* An element of the CREATE TABLE list, which mixes column definitions and table constraints
+*/
+pub enum TableElement {
+    Column(Column),
+    Constraint(TableConstraint),
+}
+
+impl CreateTableQuery {
+    pub fn new(table: String, elements: Vec<TableElement>, comment: Option<String>) -> CreateTableQuery {
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        for element in elements {
+            match element {
+                TableElement::Column(column) => columns.push(column),
+                TableElement::Constraint(constraint) => constraints.push(constraint),
+            }
+        }
+        Column::assign_ids(&mut columns);
+        CreateTableQuery { table, columns, constraints, comment }
+    }
+}
+
+/**
+* Create an empty table with the columns, constraints and comment of an existing one
+*/
+#[derive(Debug, Clone)]
+pub struct CreateTableLikeQuery {
+    pub table: String,
+    pub source: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct DropTableQuery {
+    pub tables: Vec<String>,
+    // Skip the tables that don't exist instead of failing
+    pub if_exists: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterColumnTypeQuery {
     pub table: String,
+    pub column: String,
+    pub column_type: Type,
+}
+
+#[derive(Debug, Clone)]
+pub struct DescribeQuery {
+    pub table: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShowTableStatusQuery {
+    // Report a single table instead of all of them
+    pub table: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckTableQuery {
+    pub table: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RepairTableQuery {
+    pub table: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SavepointQuery {
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SequenceQuery {
+    pub name: String,
 }
 
 #[derive(Debug, Clone)]
 pub enum Query {
     Select(SelectQuery),
+    // Run the SELECT and report the rows, pages and time of each stage instead of its result
+    ExplainAnalyze(SelectQuery),
     Insert(InsertQuery),
+    InsertSelect(InsertSelectQuery),
+    Copy(CopyQuery),
+    Update(UpdateQuery),
+    Delete(DeleteQuery),
     CreateTable(CreateTableQuery),
+    CreateTableLike(CreateTableLikeQuery),
     DropTable(DropTableQuery),
-}
\ No newline at end of file
+    CreateSequence(SequenceQuery),
+    DropSequence(SequenceQuery),
+    AlterColumnType(AlterColumnTypeQuery),
+    DropDatabase,
+    Describe(DescribeQuery),
+    CheckTable(CheckTableQuery),
+    // Compare the table definitions with the table files in the storage
+    CheckConsistency,
+    RepairTable(RepairTableQuery),
+    ShowConfig,
+    ShowTableStatus(ShowTableStatusQuery),
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(SavepointQuery),
+    RollbackToSavepoint(SavepointQuery),
+    ReleaseSavepoint(SavepointQuery),
+}
+
+impl SelectColumns {
+    pub fn resolve_current_time(&mut self, now: i64) {
+        if let SelectColumns::Items(items) = self {
+            for item in items {
+                match &mut item.expression {
+                    SelectExpression::Expression(expression) => expression.resolve_current_time(now),
+                    expression => if let Some(expression) = expression.get_aggregated_expression_mut() {
+                        expression.resolve_current_time(now);
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl SelectQuery {
+    /**
+    * Replace NOW() and CURRENT_DATE in the selected columns and the condition
+    */
+    pub fn resolve_current_time(&mut self, now: i64) {
+        self.columns.resolve_current_time(now);
+        if let Some(condition) = &mut self.condition {
+            condition.resolve_current_time(now);
+        }
+        for expression in &mut self.group_by {
+            expression.resolve_current_time(now);
+        }
+        for join in &mut self.joins {
+            if let Some(condition) = &mut join.condition {
+                condition.resolve_current_time(now);
+            }
+        }
+        for order_by in &mut self.order_by {
+            order_by.expression.resolve_current_time(now);
+        }
+    }
+}
+
+impl Query {
+    /**
+    * Replace NOW() and CURRENT_DATE in every expression of the query with their values at the given
+    * instant, so they are the same for every row the statement reads or writes
+    */
+    pub fn resolve_current_time(&mut self, now: i64) {
+        match self {
+            Query::Select(query) | Query::ExplainAnalyze(query) => query.resolve_current_time(now),
+            Query::InsertSelect(query) => {
+                query.select.resolve_current_time(now);
+                if let Some(on_conflict) = &mut query.on_conflict {
+                    on_conflict.resolve_current_time(now);
+                }
+            },
+            Query::Insert(query) => {
+                for value in &mut query.values {
+                    value.resolve_current_time(now);
+                }
+                if let Some(on_conflict) = &mut query.on_conflict {
+                    on_conflict.resolve_current_time(now);
+                }
+            },
+            Query::Update(query) => {
+                for column_value in &mut query.column_values {
+                    column_value.value.resolve_current_time(now);
+                }
+                if let Some(condition) = &mut query.condition {
+                    condition.resolve_current_time(now);
+                }
+                if let Some(returning) = &mut query.returning {
+                    returning.resolve_current_time(now);
+                }
+            },
+            Query::Delete(query) => {
+                if let Some(condition) = &mut query.condition {
+                    condition.resolve_current_time(now);
+                }
+                if let Some(returning) = &mut query.returning {
+                    returning.resolve_current_time(now);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /**
+    * Test if the query modifies the records of a table
+    */
+    pub fn modifies_data(&self) -> bool {
+        match self {
+            Query::Insert(_) | Query::InsertSelect(_) | Query::Copy(_) | Query::Update(_) | Query::Delete(_) => true,
+            _ => false,
+        }
+    }
+
+    /**
+    * Replace NEXTVAL('sequence') in the values of an INSERT with the next values of the sequences.
+    * Anywhere else NEXTVAL is left alone, and fails when it's evaluated
+    */
+    pub fn resolve_next_values(&mut self,
+                               next_value: &mut dyn FnMut(&str) -> Result<i32, QueryError>) -> Result<(), QueryError> {
+        if let Query::Insert(query) = self {
+            for value in &mut query.values {
+                value.resolve_next_values(next_value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Test if the query modifies table definitions or rewrites table files
+    */
+    pub fn modifies_schema(&self) -> bool {
+        match self {
+            Query::CreateTable(_) | Query::CreateTableLike(_) | Query::DropTable(_) | Query::AlterColumnType(_)
+            | Query::CreateSequence(_) | Query::DropSequence(_) | Query::DropDatabase
+            | Query::RepairTable(_) => true,
+            _ => false,
+        }
+    }
+}
+/**
+* Format the bytes as lowercase hex digits, two per byte
+*/
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/**
+* Parse pairs of hex digits into bytes, in either case. None if there is an odd number of digits or
+* any other character
+*/
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None
+    }
+    (0..hex.len()).step_by(2)
+        .map(|idx| hex.get(idx..idx + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modulo(value1: i32, value2: i32) -> Result<Value, QueryError> {
+        Value::Int(value1) % Value::Int(value2)
+    }
+
+    fn integer_divide(value1: i32, value2: i32) -> Result<Value, QueryError> {
+        Value::Int(value1).integer_divide(Value::Int(value2))
+    }
+
+    #[test]
+    fn modulo_of_positive_operands() {
+        assert_eq!(modulo(7, 2).unwrap(), Value::Int(1));
+        assert_eq!(modulo(6, 3).unwrap(), Value::Int(0));
+        assert_eq!(modulo(2, 7).unwrap(), Value::Int(2));
+    }
+
+    #[test]
+    fn modulo_takes_the_sign_of_the_dividend() {
+        assert_eq!(modulo(-7, 2).unwrap(), Value::Int(-1));
+        assert_eq!(modulo(7, -2).unwrap(), Value::Int(1));
+        assert_eq!(modulo(-7, -2).unwrap(), Value::Int(-1));
+    }
+
+    #[test]
+    fn integer_division_truncates_towards_zero() {
+        assert_eq!(integer_divide(7, 2).unwrap(), Value::Int(3));
+        assert_eq!(integer_divide(-7, 2).unwrap(), Value::Int(-3));
+        assert_eq!(integer_divide(7, -2).unwrap(), Value::Int(-3));
+        assert_eq!(integer_divide(-7, -2).unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn quotient_and_remainder_give_back_the_dividend() {
+        for (value1, value2) in &[(7, 2), (-7, 2), (7, -2), (-7, -2), (9, 3), (-1, 5)] {
+            let quotient = match integer_divide(*value1, *value2).unwrap() { Value::Int(value) => value, _ => unreachable!() };
+            let remainder = match modulo(*value1, *value2).unwrap() { Value::Int(value) => value, _ => unreachable!() };
+            assert_eq!(quotient * value2 + remainder, *value1);
+        }
+    }
+
+    #[test]
+    fn division_by_zero_is_rejected() {
+        assert!(matches!(modulo(7, 0), Err(QueryError::ValidationError(_))));
+        assert!(matches!(integer_divide(7, 0), Err(QueryError::ValidationError(_))));
+    }
+
+    #[test]
+    fn overflowing_division_is_rejected() {
+        assert!(matches!(modulo(i32::MIN, -1), Err(QueryError::ValidationError(_))));
+        assert!(matches!(integer_divide(i32::MIN, -1), Err(QueryError::ValidationError(_))));
+    }
+
+    fn float_integer_divide(value1: f32, value2: f32) -> Result<Value, QueryError> {
+        Value::Float(value1).integer_divide(Value::Float(value2))
+    }
+
+    fn assert_integer_overflow(result: Result<Value, QueryError>) {
+        match result {
+            Err(QueryError::ValidationError(message)) => assert_eq!(message, "Integer overflow"),
+            result => panic!("Expected an integer overflow, but got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn float_integer_division_truncates_towards_zero() {
+        assert_eq!(float_integer_divide(7.0, 2.0).unwrap(), Value::Int(3));
+        assert_eq!(float_integer_divide(-7.5, 2.0).unwrap(), Value::Int(-3));
+        assert_eq!(float_integer_divide(7.5, -0.5).unwrap(), Value::Int(-15));
+        assert_eq!(float_integer_divide(-0.5, 2.0).unwrap(), Value::Int(0));
+        assert_eq!(Value::Float(7.0).integer_divide(Value::Int(2)).unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn float_integer_division_by_zero_is_rejected() {
+        match float_integer_divide(7.0, 0.0) {
+            Err(QueryError::ValidationError(message)) => assert_eq!(message, "Division by 0"),
+            result => panic!("Expected a division by 0, but got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn float_integer_division_out_of_the_int_range_is_rejected() {
+        assert_integer_overflow(float_integer_divide(1e30, 1.0));
+        assert_integer_overflow(float_integer_divide(-1e30, 1.0));
+        assert_integer_overflow(float_integer_divide(2147483648.0, 1.0));
+        assert_integer_overflow(float_integer_divide(i32::MIN as f32, -1.0));
+        assert_eq!(float_integer_divide(i32::MIN as f32, 1.0).unwrap(), Value::Int(i32::MIN));
+        assert_eq!(float_integer_divide(2147483520.0, 1.0).unwrap(), Value::Int(2147483520));
+    }
+
+    #[test]
+    fn float_integer_division_with_an_infinite_quotient_is_rejected() {
+        assert_integer_overflow(float_integer_divide(f32::MAX, 0.5));
+        assert_integer_overflow(float_integer_divide(1e30, 1e-30));
+    }
+
+    fn assert_float_overflow(result: Result<Value, QueryError>) {
+        match result {
+            Err(QueryError::ValidationError(message)) => assert!(message.starts_with("Float overflow"), "{}", message),
+            result => panic!("Expected a float overflow, but got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn float_division_by_zero_is_rejected() {
+        match Value::Float(1.0) / Value::Float(0.0) {
+            Err(QueryError::ValidationError(message)) => assert_eq!(message, "Division by 0"),
+            result => panic!("Expected a division by 0, but got {:?}", result),
+        }
+        assert!(matches!(Value::Float(-1.0) / Value::Float(-0.0), Err(QueryError::ValidationError(_))));
+    }
+
+    #[test]
+    fn float_operations_that_overflow_to_infinity_are_rejected() {
+        assert_float_overflow(Value::Float(3e38) * Value::Float(10.0));
+        assert_float_overflow(Value::Float(-3e38) * Value::Float(10.0));
+        assert_float_overflow(Value::Float(f32::MAX) + Value::Float(f32::MAX));
+        assert_float_overflow(Value::Float(-f32::MAX) - Value::Float(f32::MAX));
+        assert_float_overflow(Value::Float(f32::MAX) / Value::Float(0.5));
+        assert_eq!((Value::Float(1e38) * Value::Float(2.0)).unwrap(), Value::Float(2e38));
+    }
+
+    #[test]
+    fn float_integer_division_with_a_non_finite_result_is_rejected() {
+        assert_integer_overflow(float_integer_divide(f32::MAX, 1e-10));
+        assert_integer_overflow(Value::Float(3e38).integer_divide(Value::Int(1)));
+    }
+
+    #[test]
+    fn non_finite_strings_cant_be_cast_to_float() {
+        for value in &["inf", "-inf", "infinity", "NaN", "1e39"] {
+            assert_float_overflow(Value::Str(String::from(*value)).cast(&Type::Float));
+        }
+        assert_eq!(Value::Str(String::from(" 1.5 ")).cast(&Type::Float).unwrap(), Value::Float(1.5));
+    }
+
+    /**
+    * Types the operands of the operator are coerced to in lenient mode, or None if they are rejected.
+    * Null with any type is left out, as it always gives Null
+    */
+    fn expected_coercion(type1: &Type, type2: &Type, operator: &Operator) -> Option<(Type, Type)> {
+        let arithmetic = !matches!(operator, Operator::Modulo);
+        match (type1, type2) {
+            (Type::Int, Type::Int) if matches!(operator, Operator::Divide) => Some((Type::Float, Type::Float)),
+            (Type::Int, Type::Int) => Some((Type::Int, Type::Int)),
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) | (Type::Float, Type::Float) if arithmetic =>
+                Some((Type::Float, Type::Float)),
+            (Type::Str, Type::Str) if matches!(operator, Operator::Add) => Some((Type::Str, Type::Str)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn coercion_of_every_type_pair_and_operator() {
+        let types = [Type::Int, Type::Float, Type::Str, Type::Bool, Type::Timestamp, Type::Date, Type::Bytes, Type::Null];
+        let operators = [Operator::Add, Operator::Subtract, Operator::Multiply, Operator::Divide,
+                         Operator::IntegerDivide, Operator::Modulo];
+        for type1 in &types {
+            for type2 in &types {
+                for operator in &operators {
+                    let case = format!("{:?} {:?} {:?}", type1, operator, type2);
+                    let expected = if *type1 == Type::Null || *type2 == Type::Null {
+                        Some((Type::Null, Type::Null))
+                    } else {
+                        expected_coercion(type1, type2, operator)
+                    };
+                    let lenient = type1.coerce_for_binary_op(type2, operator, TypeMode::Lenient);
+                    match (&expected, &lenient) {
+                        (Some(expected), Ok(coerced)) => assert_eq!(coerced, expected, "{}", case),
+                        (None, Err(QueryError::ValidationError(_))) => (),
+                        _ => panic!("{}: expected {:?} but got {:?}", case, expected, lenient),
+                    }
+                    // Strict mode only differs in rejecting mixed Int and Float operands
+                    let mixed_numbers = matches!((type1, type2), (Type::Int, Type::Float) | (Type::Float, Type::Int));
+                    let strict = type1.coerce_for_binary_op(type2, operator, TypeMode::Strict);
+                    match (&expected, &strict) {
+                        (Some(_), Err(QueryError::ValidationError(_))) if mixed_numbers => (),
+                        (Some(expected), Ok(coerced)) if !mixed_numbers => assert_eq!(coerced, expected, "{}", case),
+                        (None, Err(QueryError::ValidationError(_))) => (),
+                        _ => panic!("{} in strict mode: expected {:?} but got {:?}", case, expected, strict),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn modulo_rejects_floats() {
+        assert!(matches!(Value::Float(7.5) % Value::Int(2), Err(QueryError::ValidationError(_))));
+    }
+
+    #[test]
+    fn modulo_of_null_is_null() {
+        assert!((Value::Null % Value::Int(2)).unwrap().is_null());
+        assert!((Value::Int(7) % Value::Null).unwrap().is_null());
+    }
+
+    fn record(values: Vec<Value>) -> Record {
+        Record { values }
+    }
+
+    #[test]
+    fn records_with_null_and_empty_string_are_different() {
+        let with_null = record(vec![Value::Int(1), Value::Null]);
+        let with_empty_string = record(vec![Value::Int(1), Value::Str(String::new())]);
+        assert!(!with_null.eq_values(&with_empty_string));
+        assert!(!with_empty_string.eq_values(&with_null));
+        assert!(with_null.eq_values(&record(vec![Value::Int(1), Value::Null])));
+        assert!(with_empty_string.eq_values(&record(vec![Value::Float(1.0), Value::Str(String::new())])));
+
+        let records: std::collections::HashSet<Record> = vec![with_null.clone(), with_empty_string.clone(),
+            with_null.clone(), with_empty_string.clone()].into_iter().collect();
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(&with_null) && records.contains(&with_empty_string));
+    }
+
+    fn arithmetic_results(value1: Value, value2: Value) -> Vec<(&'static str, Result<Value, QueryError>)> {
+        vec![
+            ("+", value1.clone() + value2.clone()),
+            ("-", value1.clone() - value2.clone()),
+            ("*", value1.clone() * value2.clone()),
+            ("/", value1.clone() / value2.clone()),
+            ("DIV", value1.integer_divide(value2)),
+        ]
+    }
+
+    #[test]
+    fn arithmetic_on_null_is_null() {
+        let values = [Value::Int(1), Value::Float(1.5), Value::Str(String::from("a")), Value::Bool(true),
+            Value::Timestamp(0), Value::Date(0), Value::Bytes(vec![1]), Value::Null];
+        for value in values.iter() {
+            let results = arithmetic_results(Value::Null, value.clone()).into_iter()
+                .chain(arithmetic_results(value.clone(), Value::Null));
+            for (operator, result) in results {
+                match result {
+                    Ok(Value::Null) => (),
+                    result => panic!("{} with NULL and {:?} should be NULL, but got {:?}", operator, value, result),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_on_mismatched_types_is_rejected() {
+        for (operator, result) in arithmetic_results(Value::Int(1), Value::Bool(true)) {
+            match result {
+                Err(QueryError::ValidationError(message)) => assert!(message.starts_with("Invalid types for operator"),
+                                                                     "{}: {}", operator, message),
+                result => panic!("{} with Int and Bool should be rejected, but got {:?}", operator, result),
+            }
+        }
+    }
+}
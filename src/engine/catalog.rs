@@ -0,0 +1,88 @@
+use crate::engine::asl;
+use crate::engine::db::DatabaseDefinition;
+use crate::engine::errors::QueryError;
+use crate::engine::fs::evaluate_for_record;
+use crate::engine::pages::RowId;
+use crate::engine::storage::ScanControl;
+
+// Table names starting with this prefix are reserved for the catalog
+pub const CATALOG_PREFIX: &str = "__";
+pub const TABLES_TABLE: &str = "__tables__";
+pub const COLUMNS_TABLE: &str = "__columns__";
+
+fn catalog_column(name: &str, column_type: asl::Type) -> asl::Column {
+    asl::Column { id: 0, name: String::from(name), column_type, comment: None, default: None }
+}
+
+fn optional_str(value: &Option<String>) -> asl::Value {
+    match value {
+        Some(value) => asl::Value::Str(value.clone()),
+        None => asl::Value::Null,
+    }
+}
+
+/**
+* Get the definition of a catalog table, or None if the name doesn't belong to one
+*/
+pub fn get_catalog_table(table_name: &str) -> Option<asl::Table> {
+    let mut columns = match table_name {
+        TABLES_TABLE => vec![
+            catalog_column("table_name", asl::Type::Str),
+            catalog_column("column_count", asl::Type::Int),
+            catalog_column("comment", asl::Type::Str),
+        ],
+        COLUMNS_TABLE => vec![
+            catalog_column("table_name", asl::Type::Str),
+            catalog_column("column_name", asl::Type::Str),
+            catalog_column("position", asl::Type::Int),
+            catalog_column("column_type", asl::Type::Str),
+            catalog_column("comment", asl::Type::Str),
+        ],
+        _ => return None,
+    };
+    asl::Column::assign_ids(&mut columns);
+    Some(asl::Table { name: String::from(table_name), columns, comment: None, constraints: Vec::new() })
+}
+
+/**
+* Build the rows of a catalog table from the database definition, ordered by table name and column
+* position
+*/
+fn get_catalog_records(table: &asl::Table, db_definition: &DatabaseDefinition) -> Vec<asl::Record> {
+    let tables: Vec<&asl::Table> = db_definition.get_tables().collect();
+    match table.name.as_str() {
+        TABLES_TABLE => tables.iter()
+            .map(|table| asl::Record { values: vec![
+                asl::Value::Str(table.name.clone()),
+                asl::Value::Int(table.columns.len() as i32),
+                optional_str(&table.comment),
+            ]})
+            .collect(),
+        COLUMNS_TABLE => tables.iter()
+            .flat_map(|table| table.columns.iter().enumerate().map(move |(idx, column)| asl::Record { values: vec![
+                asl::Value::Str(table.name.clone()),
+                asl::Value::Str(column.name.clone()),
+                asl::Value::Int(idx as i32 + 1),
+                asl::Value::Str(format!("{:?}", column.column_type)),
+                optional_str(&column.comment),
+            ]}))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/**
+* Call f with every row of the catalog table that matches the condition, like scanning a stored
+* table. The rows don't live in pages, so their row ids only hold their position
+*/
+pub fn scan_catalog(table: &asl::Table, db_definition: &DatabaseDefinition, condition: &Option<Box<asl::Expression>>,
+                    f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+    for (slot, record) in get_catalog_records(table, db_definition).into_iter().enumerate() {
+        if evaluate_for_record(table, &record, condition)? {
+            if f(RowId { page_id: 0, slot }, record)? == ScanControl::Stop {
+                return Ok(())
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,567 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use crate::engine::asl;
+use crate::engine::db::DatabaseDefinition;
+use crate::engine::errors::QueryError;
+use crate::engine::pages::RowId;
+use crate::engine::storage::{ScanControl, StorageEngine};
+
+/**
+* What happens to the referencing rows when a referenced row is deleted
+*/
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ReferentialAction {
+    // Reject the delete
+    Restrict,
+    // Delete the referencing rows too
+    Cascade,
+}
+
+// New kinds must be added at the end, so definition files written before they existed can still be read
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ConstraintKind {
+    PrimaryKey,
+    Unique,
+    ForeignKey {
+        table: String,
+        columns: Vec<String>,
+        on_delete: ReferentialAction,
+    },
+}
+
+/**
+* Table-level constraint declared after the column list, e.g. PRIMARY KEY (a, b)
+*/
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TableConstraint {
+    pub name: Option<String>,
+    pub kind: ConstraintKind,
+    pub columns: Vec<String>,
+}
+
+impl fmt::Display for TableConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "CONSTRAINT {} ", name)?;
+        }
+        match &self.kind {
+            ConstraintKind::PrimaryKey => write!(f, "PRIMARY KEY ({})", self.columns.join(", ")),
+            ConstraintKind::Unique => write!(f, "UNIQUE ({})", self.columns.join(", ")),
+            ConstraintKind::ForeignKey { table, columns, on_delete } => {
+                write!(f, "FOREIGN KEY ({}) REFERENCES {} ({})", self.columns.join(", "), table, columns.join(", "))?;
+                match on_delete {
+                    ReferentialAction::Restrict => Ok(()),
+                    ReferentialAction::Cascade => write!(f, " ON DELETE CASCADE"),
+                }
+            },
+        }
+    }
+}
+
+impl TableConstraint {
+    /**
+    * Test if the constraint requires its columns to be unique
+    */
+    pub fn is_unique(&self) -> bool {
+        match self.kind {
+            ConstraintKind::PrimaryKey | ConstraintKind::Unique => true,
+            ConstraintKind::ForeignKey { .. } => false,
+        }
+    }
+}
+
+/**
+* Get the indexes of the named columns in the table. The names must have been validated
+*/
+fn get_column_indexes(table: &asl::Table, column_names: &[String]) -> Vec<usize> {
+    column_names.iter()
+        .filter_map(|column_name| table.columns.iter().position(|column| &column.name == column_name))
+        .collect()
+}
+
+/**
+* Build a key from the values at the given indexes of the record, or None if any of them is Null, as
+* Null keys never match. Keys are equal when their values are equal under =
+*/
+fn get_key(record: &asl::Record, column_indexes: &[usize]) -> Option<asl::GroupKey> {
+    let values: Option<Vec<asl::Value>> = column_indexes.iter()
+        .map(|idx| match &record.values[*idx] {
+            asl::Value::Null => None,
+            value => Some(value.clone()),
+        })
+        .collect();
+    values.map(asl::GroupKey)
+}
+
+fn format_key(record: &asl::Record, column_indexes: &[usize]) -> String {
+    column_indexes.iter().map(|idx| record.values[*idx].to_string()).collect::<Vec<String>>().join(", ")
+}
+
+/**
+* Check that the constraints declared in CREATE TABLE only reference existing columns, don't repeat
+* columns, and that there is at most one primary key. Foreign keys are checked by
+* validate_foreign_keys, as they need the other tables
+*/
+pub fn validate_constraints(columns: &[asl::Column], constraints: &[TableConstraint]) -> Result<(), QueryError> {
+    let primary_keys = constraints.iter().filter(|constraint| constraint.kind == ConstraintKind::PrimaryKey).count();
+    if primary_keys > 1 {
+        return Err(QueryError::ValidationError(String::from("A table can only have one PRIMARY KEY")))
+    }
+    for constraint in constraints {
+        let mut seen_columns = HashSet::new();
+        for column_name in &constraint.columns {
+            if !columns.iter().any(|column| &column.name == column_name) {
+                return Err(QueryError::ValidationError(
+                    format!("The column {} in {} doesn't exist", column_name, constraint)))
+            }
+            if !seen_columns.insert(column_name) {
+                return Err(QueryError::ValidationError(
+                    format!("The column {} is repeated in {}", column_name, constraint)))
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+* Unique index of a table: the keys of its rows for each PRIMARY KEY and UNIQUE constraint, so a new
+* key is checked with a hash lookup instead of a scan. Keys with a Null value never conflict, except
+* in a primary key where Null is rejected. The index only lives in memory, so it's built with a scan
+* of the table the first time it's needed after the database is opened
+*/
+#[derive(Debug, Clone)]
+pub struct UniqueKeys {
+    table_name: String,
+    constraints: Vec<(TableConstraint, Vec<usize>, HashSet<asl::GroupKey>)>,
+}
+
+impl UniqueKeys {
+    pub fn new(table: &asl::Table) -> UniqueKeys {
+        let constraints = table.constraints.iter()
+            .filter(|constraint| constraint.is_unique())
+            .map(|constraint| (constraint.clone(), get_column_indexes(table, &constraint.columns), HashSet::new()))
+            .collect();
+        UniqueKeys { table_name: table.name.clone(), constraints }
+    }
+
+    /**
+    * Test if the table has no constraint to enforce
+    */
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    /**
+    * Get the key of the record for the constraint, or None if it has a Null value
+    */
+    fn get_record_key(&self, constraint: &TableConstraint, column_indexes: &[usize],
+                      record: &asl::Record) -> Result<Option<asl::GroupKey>, QueryError> {
+        match get_key(record, column_indexes) {
+            None if constraint.kind == ConstraintKind::PrimaryKey => Err(QueryError::ValidationError(
+                format!("The columns of the primary key of {} can't be NULL", self.table_name))),
+            key => Ok(key),
+        }
+    }
+
+    fn duplicate_key(&self, constraint: &TableConstraint, column_indexes: &[usize], record: &asl::Record) -> QueryError {
+        QueryError::Conflict(format!(
+            "Duplicate key ({}) = ({}) violates {} of {}",
+            constraint.columns.join(", "), format_key(record, column_indexes), constraint, self.table_name))
+    }
+
+    /**
+    * Register the keys of the record, failing if another registered record has the same key
+    */
+    pub fn add(&mut self, record: &asl::Record) -> Result<(), QueryError> {
+        for idx in 0..self.constraints.len() {
+            let (constraint, column_indexes, keys) = &self.constraints[idx];
+            let key = match self.get_record_key(constraint, column_indexes, record)? {
+                Some(key) => key,
+                None => continue,
+            };
+            if keys.contains(&key) {
+                return Err(self.duplicate_key(constraint, column_indexes, record))
+            }
+            self.constraints[idx].2.insert(key);
+        }
+        Ok(())
+    }
+
+    /**
+    * Unregister the keys of a record that is no longer in the table
+    */
+    pub fn remove(&mut self, record: &asl::Record) {
+        for (_, column_indexes, keys) in self.constraints.iter_mut() {
+            if let Some(key) = get_key(record, column_indexes) {
+                keys.remove(&key);
+            }
+        }
+    }
+
+    /**
+    * Check that the records don't share a key with the registered records or among themselves,
+    * without registering them. The keys of replaced_records, the rows the records overwrite, don't
+    * count as registered
+    */
+    pub fn check(&self, replaced_records: &[asl::Record], records: &[asl::Record]) -> Result<(), QueryError> {
+        for (constraint, column_indexes, keys) in &self.constraints {
+            let replaced_keys: HashSet<asl::GroupKey> = replaced_records.iter()
+                .filter_map(|record| get_key(record, column_indexes))
+                .collect();
+            let mut new_keys = HashSet::new();
+            for record in records {
+                if let Some(key) = self.get_record_key(constraint, column_indexes, record)? {
+                    if (keys.contains(&key) && !replaced_keys.contains(&key)) || !new_keys.insert(key) {
+                        return Err(self.duplicate_key(constraint, column_indexes, record))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Replace the keys of the old records with the keys of the new ones, after the records were
+    * written. Inserts don't have old records and deletes don't have new ones
+    */
+    pub fn replace(&mut self, old_records: &[asl::Record], new_records: &[asl::Record]) -> Result<(), QueryError> {
+        for record in old_records {
+            self.remove(record);
+        }
+        for record in new_records {
+            self.add(record)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+* Get the column indexes of the unique constraints checked by an ON CONFLICT clause: the constraint
+* with exactly the target columns, in any order, or every unique constraint without a target
+*/
+pub fn get_conflict_targets(table: &asl::Table, target: &Option<Vec<String>>) -> Result<Vec<Vec<usize>>, QueryError> {
+    let unique_constraints = table.constraints.iter().filter(|constraint| constraint.is_unique());
+    let target = match target {
+        Some(target) => target,
+        None => return Ok(unique_constraints
+            .map(|constraint| get_column_indexes(table, &constraint.columns))
+            .collect()),
+    };
+    let target_columns: BTreeSet<&String> = target.iter().collect();
+    for constraint in unique_constraints {
+        if constraint.columns.iter().collect::<BTreeSet<&String>>() == target_columns {
+            return Ok(vec![get_column_indexes(table, &constraint.columns)])
+        }
+    }
+    Err(QueryError::ValidationError(format!(
+        "There is no PRIMARY KEY or UNIQUE constraint on ({}) in {}", target.join(", "), table.name)))
+}
+
+/**
+* Row that an inserted record has the same key as
+*/
+#[derive(Debug, Clone)]
+pub enum Conflict {
+    // A stored row, with its current values
+    Stored(RowId, asl::Record),
+    // A record inserted before it by the same statement
+    Inserted,
+}
+
+/**
+* Find the row each record conflicts with on the target constraints, if any. The records are taken in
+* order, so a record also conflicts with the records before it that don't conflict, as those are
+* inserted. The table is scanned once, keeping only the rows that have the key of some record
+*/
+pub fn find_conflicts(storage: &dyn StorageEngine, table: &asl::Table, targets: &[Vec<usize>],
+                      records: &[asl::Record]) -> Result<Vec<Option<Conflict>>, QueryError> {
+    let record_keys: Vec<HashSet<asl::GroupKey>> = targets.iter()
+        .map(|column_indexes| records.iter().filter_map(|record| get_key(record, column_indexes)).collect())
+        .collect();
+    let mut stored_rows: Vec<HashMap<asl::GroupKey, (RowId, asl::Record)>> = targets.iter().map(|_| HashMap::new()).collect();
+    if !targets.is_empty() {
+        storage.scan_records(table, &None, &mut |row_id, record| {
+            for (idx, column_indexes) in targets.iter().enumerate() {
+                if let Some(key) = get_key(&record, column_indexes) {
+                    if record_keys[idx].contains(&key) {
+                        stored_rows[idx].insert(key, (row_id, record.clone()));
+                    }
+                }
+            }
+            Ok(ScanControl::Continue)
+        })?;
+    }
+    let mut inserted_keys: Vec<HashSet<asl::GroupKey>> = targets.iter().map(|_| HashSet::new()).collect();
+    let mut conflicts = Vec::new();
+    for record in records {
+        let keys: Vec<Option<asl::GroupKey>> = targets.iter()
+            .map(|column_indexes| get_key(record, column_indexes))
+            .collect();
+        let conflict = keys.iter().enumerate().find_map(|(idx, key)| {
+            let key = key.as_ref()?;
+            match stored_rows[idx].get(key) {
+                Some((row_id, stored_record)) => Some(Conflict::Stored(*row_id, stored_record.clone())),
+                None if inserted_keys[idx].contains(key) => Some(Conflict::Inserted),
+                None => None,
+            }
+        });
+        if conflict.is_none() {
+            for (idx, key) in keys.into_iter().enumerate() {
+                if let Some(key) = key {
+                    inserted_keys[idx].insert(key);
+                }
+            }
+        }
+        conflicts.push(conflict);
+    }
+    Ok(conflicts)
+}
+
+/**
+* Check the foreign keys of a new table: the referenced table must exist, and the referenced columns
+* must match the referencing ones in number and type and be the columns of a PRIMARY KEY or UNIQUE
+* constraint. The new table itself can be referenced
+*/
+pub fn validate_foreign_keys(db_definition: &DatabaseDefinition, table: &asl::Table) -> Result<(), QueryError> {
+    for constraint in &table.constraints {
+        let (referenced_table_name, referenced_columns) = match &constraint.kind {
+            ConstraintKind::ForeignKey { table, columns, .. } => (table, columns),
+            _ => continue,
+        };
+        let referenced_table = if referenced_table_name == &table.name {
+            table
+        } else {
+            db_definition.get_table(referenced_table_name)
+                .ok_or_else(|| QueryError::NotFound(referenced_table_name.clone()))?
+        };
+        if referenced_columns.len() != constraint.columns.len() {
+            return Err(QueryError::ValidationError(
+                format!("{} must reference as many columns as it has", constraint)))
+        }
+        for (column_name, referenced_column_name) in constraint.columns.iter().zip(referenced_columns) {
+            let column_type = table.get_column(column_name).map(|column| &column.column_type);
+            let referenced_column = referenced_table.get_column(referenced_column_name).ok_or_else(||
+                QueryError::ValidationError(format!(
+                    "The column {} doesn't exist in {}", referenced_column_name, referenced_table.name)))?;
+            if column_type != Some(&referenced_column.column_type) {
+                return Err(QueryError::ValidationError(format!(
+                    "The column {} doesn't have the type of {}.{}", column_name, referenced_table.name,
+                    referenced_column_name)))
+            }
+        }
+        let referenced_set: HashSet<&String> = referenced_columns.iter().collect();
+        if !referenced_table.constraints.iter()
+            .any(|other| other.is_unique() && other.columns.iter().collect::<HashSet<&String>>() == referenced_set) {
+            return Err(QueryError::ValidationError(format!(
+                "{} must reference the columns of a PRIMARY KEY or UNIQUE constraint of {}",
+                constraint, referenced_table.name)))
+        }
+    }
+    Ok(())
+}
+
+/**
+* Get the foreign keys of every table that reference the given table, along with the referencing table
+*/
+pub fn get_referencing_constraints<'a>(db_definition: &'a DatabaseDefinition,
+                                       table_name: &str) -> Vec<(&'a asl::Table, &'a TableConstraint)> {
+    let mut referencing_constraints = Vec::new();
+    for table in db_definition.get_tables() {
+        for constraint in &table.constraints {
+            if let ConstraintKind::ForeignKey { table: referenced_table, .. } = &constraint.kind {
+                if referenced_table == table_name {
+                    referencing_constraints.push((table, constraint));
+                }
+            }
+        }
+    }
+    referencing_constraints
+}
+
+/**
+* Collect the keys of every record in the table for the given columns
+*/
+fn scan_keys(storage: &dyn StorageEngine, table: &asl::Table,
+             column_indexes: &[usize]) -> Result<HashSet<asl::GroupKey>, QueryError> {
+    let mut keys = HashSet::new();
+    storage.scan_records(table, &None, &mut |_, record| {
+        if let Some(key) = get_key(&record, column_indexes) {
+            keys.insert(key);
+        }
+        Ok(ScanControl::Continue)
+    })?;
+    Ok(keys)
+}
+
+/**
+* Keys of the referenced tables for each foreign key of a table, to check that new records reference
+* existing rows. Records with a Null in a foreign key column don't reference anything
+*/
+pub struct ForeignKeys<'a> {
+    table: &'a asl::Table,
+    // The foreign key, the indexes of its columns, the indexes of the referenced columns in the
+    // referenced table, and the keys found there
+    constraints: Vec<(&'a TableConstraint, Vec<usize>, Vec<usize>, HashSet<asl::GroupKey>)>,
+}
+
+impl<'a> ForeignKeys<'a> {
+    /**
+    * Scan the tables referenced by the foreign keys of the table to collect their keys
+    */
+    pub fn new(storage: &dyn StorageEngine, db_definition: &'a DatabaseDefinition,
+               table: &'a asl::Table) -> Result<ForeignKeys<'a>, QueryError> {
+        let mut constraints = Vec::new();
+        for constraint in &table.constraints {
+            let (referenced_table_name, referenced_columns) = match &constraint.kind {
+                ConstraintKind::ForeignKey { table, columns, .. } => (table, columns),
+                _ => continue,
+            };
+            let referenced_table = db_definition.get_table(referenced_table_name)
+                .ok_or_else(|| QueryError::NotFound(referenced_table_name.clone()))?;
+            let referenced_indexes = get_column_indexes(referenced_table, referenced_columns);
+            let keys = scan_keys(storage, referenced_table, &referenced_indexes)?;
+            constraints.push((constraint, get_column_indexes(table, &constraint.columns), referenced_indexes, keys));
+        }
+        Ok(ForeignKeys { table, constraints })
+    }
+
+    /**
+    * Make the record available as a referenced row, for the foreign keys of the table that reference
+    * the table itself
+    */
+    pub fn add_referenced(&mut self, record: &asl::Record) {
+        let table = self.table;
+        for (constraint, _, referenced_indexes, keys) in self.constraints.iter_mut() {
+            match &constraint.kind {
+                ConstraintKind::ForeignKey { table: referenced_table, .. } if referenced_table == &table.name =>
+                    keys.extend(get_key(record, referenced_indexes)),
+                _ => (),
+            }
+        }
+    }
+
+    /**
+    * Check that the record only references existing rows
+    */
+    pub fn check(&self, record: &asl::Record) -> Result<(), QueryError> {
+        for (constraint, column_indexes, _, keys) in &self.constraints {
+            if let Some(key) = get_key(record, column_indexes) {
+                if !keys.contains(&key) {
+                    let referenced_table = match &constraint.kind {
+                        ConstraintKind::ForeignKey { table, .. } => table,
+                        _ => &self.table.name,
+                    };
+                    return Err(QueryError::Conflict(format!(
+                        "Key ({}) = ({}) is not present in {}, violating {} of {}", constraint.columns.join(", "),
+                        format_key(record, column_indexes), referenced_table, constraint, self.table.name)))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+* Check that the foreign keys of the records reference existing rows. The records of a
+* self-referencing table can reference each other
+*/
+pub fn check_foreign_keys(storage: &dyn StorageEngine, db_definition: &DatabaseDefinition,
+                          table: &asl::Table, records: &[asl::Record]) -> Result<(), QueryError> {
+    if !table.constraints.iter().any(|constraint| !constraint.is_unique()) {
+        return Ok(())
+    }
+    let mut foreign_keys = ForeignKeys::new(storage, db_definition, table)?;
+    for record in records {
+        foreign_keys.add_referenced(record);
+    }
+    for record in records {
+        foreign_keys.check(record)?;
+    }
+    Ok(())
+}
+
+/**
+* Add the rows to the set of rows to delete, following the foreign keys that reference them. Rows
+* referenced by an ON DELETE CASCADE foreign key are deleted too, while any other reference rejects
+* the delete. deletes maps each table name to the rows that will be deleted from it, with their records
+*/
+pub fn collect_deletes(storage: &dyn StorageEngine, db_definition: &DatabaseDefinition, table: &asl::Table,
+                       rows: Vec<(RowId, asl::Record)>,
+                       deletes: &mut HashMap<String, HashMap<RowId, asl::Record>>) -> Result<(), QueryError> {
+    if rows.is_empty() {
+        return Ok(())
+    }
+    let table_deletes = deletes.entry(table.name.clone()).or_insert_with(HashMap::new);
+    let rows: Vec<(RowId, asl::Record)> = rows.into_iter()
+        .filter(|(row_id, record)| table_deletes.insert(*row_id, record.clone()).is_none())
+        .collect();
+    for (referencing_table, constraint) in get_referencing_constraints(db_definition, &table.name) {
+        let (referenced_columns, on_delete) = match &constraint.kind {
+            ConstraintKind::ForeignKey { columns, on_delete, .. } => (columns, on_delete),
+            _ => continue,
+        };
+        let referenced_indexes = get_column_indexes(table, referenced_columns);
+        let deleted_keys: HashSet<asl::GroupKey> = rows.iter()
+            .filter_map(|(_, record)| get_key(record, &referenced_indexes))
+            .collect();
+        let column_indexes = get_column_indexes(referencing_table, &constraint.columns);
+        let already_deleted: HashSet<RowId> = deletes.get(&referencing_table.name)
+            .map(|rows| rows.keys().cloned().collect())
+            .unwrap_or_default();
+        let mut referencing_rows = Vec::new();
+        storage.scan_records(referencing_table, &None, &mut |row_id, record| {
+            let references_deleted_key = get_key(&record, &column_indexes)
+                .map_or(false, |key| deleted_keys.contains(&key));
+            if references_deleted_key && !already_deleted.contains(&row_id) {
+                if on_delete == &ReferentialAction::Restrict {
+                    return Err(QueryError::Conflict(format!(
+                        "Can't delete from {}: key ({}) = ({}) is still referenced by {} of {}",
+                        table.name, referenced_columns.join(", "), format_key(&record, &column_indexes),
+                        constraint, referencing_table.name)))
+                }
+                referencing_rows.push((row_id, record));
+            }
+            Ok(ScanControl::Continue)
+        })?;
+        collect_deletes(storage, db_definition, referencing_table, referencing_rows, deletes)?;
+    }
+    Ok(())
+}
+
+/**
+* Reject updates that change or remove key values still referenced by a foreign key, whatever its
+* ON DELETE action
+*/
+pub fn check_referenced_updates(storage: &dyn StorageEngine, db_definition: &DatabaseDefinition,
+                                table: &asl::Table, old_records: &[asl::Record],
+                                new_records: &[asl::Record]) -> Result<(), QueryError> {
+    for (referencing_table, constraint) in get_referencing_constraints(db_definition, &table.name) {
+        let referenced_columns = match &constraint.kind {
+            ConstraintKind::ForeignKey { columns, .. } => columns,
+            _ => continue,
+        };
+        let referenced_indexes = get_column_indexes(table, referenced_columns);
+        let new_keys: HashSet<asl::GroupKey> = new_records.iter()
+            .filter_map(|record| get_key(record, &referenced_indexes))
+            .collect();
+        let removed_records: Vec<(asl::GroupKey, &asl::Record)> = old_records.iter()
+            .filter_map(|record| get_key(record, &referenced_indexes).map(|key| (key, record)))
+            .filter(|(key, _)| !new_keys.contains(key))
+            .collect();
+        if removed_records.is_empty() {
+            continue
+        }
+        let column_indexes = get_column_indexes(referencing_table, &constraint.columns);
+        let referencing_keys = scan_keys(storage, referencing_table, &column_indexes)?;
+        if let Some((_, record)) = removed_records.iter().find(|(key, _)| referencing_keys.contains(key)) {
+            return Err(QueryError::Conflict(format!(
+                "Can't update {}: key ({}) = ({}) is still referenced by {} of {}", table.name,
+                referenced_columns.join(", "), format_key(record, &referenced_indexes), constraint,
+                referencing_table.name)))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::io::BufRead;
+
+use crate::engine::errors::QueryError;
+
+/**
+* Reads CSV records one at a time, so large files never need to be held in memory. Fields may be
+* quoted with double quotes, in which case they can contain separators, line breaks and doubled
+* quotes, which is the format written by the csv output format
+*/
+pub struct CsvReader<R: BufRead> {
+    reader: R,
+    line_number: usize,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    pub fn new(reader: R) -> CsvReader<R> {
+        CsvReader { reader, line_number: 0 }
+    }
+
+    /**
+    * Number of the last line read, starting at 1
+    */
+    pub fn get_line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn read_line(&mut self, line: &mut String) -> Result<usize, QueryError> {
+        line.clear();
+        let bytes_read = self.reader.read_line(line).map_err(|error| QueryError::ValidationError(
+            format!("Can't read line {}: {}", self.line_number + 1, error)))?;
+        if bytes_read > 0 {
+            self.line_number += 1;
+        }
+        Ok(bytes_read)
+    }
+
+    /**
+    * Read the next record, or None at the end of the input. Quoted fields that span several lines are
+    * read completely
+    */
+    pub fn read_record(&mut self) -> Result<Option<Vec<String>>, QueryError> {
+        let mut line = String::new();
+        if self.read_line(&mut line)? == 0 {
+            return Ok(None)
+        }
+        let first_line_number = self.line_number;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (c, in_quotes) {
+                    ('"', true) if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    },
+                    ('"', true) => in_quotes = false,
+                    ('"', false) if field.is_empty() => in_quotes = true,
+                    (',', false) => fields.push(std::mem::replace(&mut field, String::new())),
+                    ('\r', false) | ('\n', false) => break,
+                    (c, _) => field.push(c),
+                }
+            }
+            if !in_quotes {
+                break
+            }
+            if self.read_line(&mut line)? == 0 {
+                return Err(QueryError::ValidationError(
+                    format!("Unterminated quoted field starting at line {}", first_line_number)))
+            }
+        }
+        fields.push(field);
+        Ok(Some(fields))
+    }
+}
@@ -1,43 +1,436 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use serde::{Serialize, Deserialize};
 
 use crate::engine::asl;
+use crate::engine::catalog;
+use crate::engine::constraints::{
+    check_foreign_keys, check_referenced_updates, collect_deletes, find_conflicts, get_conflict_targets,
+    get_referencing_constraints, validate_constraints, validate_foreign_keys, Conflict, ConstraintKind, ForeignKeys,
+    UniqueKeys,
+};
+use crate::engine::csv::CsvReader;
 use crate::engine::errors::{QueryError, SystemError};
-use crate::engine::fs::DBFileSystem;
-use crate::sql_grammar;
+use crate::engine::explain::{get_stage_records, SelectAnalysis, StageStats, STAGE_COLUMNS};
+use crate::engine::format;
+use crate::engine::fs::{evaluate_constant_condition, evaluate_for_record, get_identifier_values, DBFileSystem};
+use crate::engine::join::{get_join_warnings, FromTables, NestedLoopJoin};
+use crate::engine::integrity::Inconsistency;
+use crate::engine::json;
+use crate::engine::memory::InMemoryStorage;
+use crate::engine::pages::{RowId, PAGE_SIZE};
+use crate::engine::rows::{Projection, RowIterator};
+use crate::engine::sort::{ExternalSorter, SortedRecords};
+use crate::engine::storage::{ScanControl, StorageEngine};
+use crate::engine::temporal;
+use crate::engine::transaction::{StagedStorage, Transaction};
+use crate::config::config::{Config, MIN_FILL_FACTOR};
 
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+// Pseudo-column that exposes the (page_id, slot) location of each record in SELECT
+pub const ROWID_COLUMN: &str = "rowid";
+// Number of records INSERT ... SELECT and COPY accumulate before writing them to the target table
+const INSERT_BATCH_SIZE: usize = 256;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct DatabaseDefinition {
-    tables: HashMap<String, asl::Table>,
+    // Kept ordered by name, so tables are always listed in the same order and the serialized
+    // definitions are reproducible. bincode encodes it like the HashMap used before
+    tables: BTreeMap<String, asl::Table>,
+    // The last value NEXTVAL returned for each sequence by name, or 0 if it wasn't called yet
+    sequences: BTreeMap<String, i32>,
+}
+
+impl DatabaseDefinition {
+    pub fn from_tables(tables: BTreeMap<String, asl::Table>) -> DatabaseDefinition {
+        DatabaseDefinition { tables, sequences: BTreeMap::new() }
+    }
+
+    /**
+    * Iterate over the tables ordered by name
+    */
+    pub fn get_tables(&self) -> impl Iterator<Item = &asl::Table> {
+        self.tables.values()
+    }
+
+    pub fn get_table(&self, table_name: &str) -> Option<&asl::Table> {
+        self.tables.get(table_name)
+    }
+}
+
+/**
+* Space used by a table, computed from its page headers and slot directories
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSize {
+    pub page_count: usize,
+    pub total_bytes: usize,
+    pub live_records: usize,
+    // Free space in every page but the last one. Records are only appended to the last page, so this
+    // space is only reused by updates that make records of the page bigger
+    pub dead_bytes: usize,
+}
+
+/**
+* Check that the value can be stored in the column
+*/
+fn check_value_type(column: &asl::Column, value: &asl::Value) -> Result<(), QueryError> {
+    if !value.has_type(&column.column_type) && !value.is_null() {
+        return Err(
+            QueryError::ValidationError(
+                format!("Incorrect value type for column {}. Expected '{:?}' and got '{:?}'",
+                        column.name, column.column_type, value))
+        )
+    };
+    Ok(())
+}
+
+/**
+* Convert the default of the column to the given type, failing if the default can't be stored in a
+* column of that type
+*/
+fn convert_column_default(column: &asl::Column,
+                          column_type: &asl::Type) -> Result<Option<asl::ColumnDefault>, QueryError> {
+    match &column.default {
+        Some(default) => match default.for_type(column_type) {
+            Ok(default) => Ok(Some(default)),
+            Err(_) => Err(QueryError::ValidationError(format!(
+                "The default {} of column {} isn't a valid {:?} value", default, column.name, column_type))),
+        },
+        None => Ok(None),
+    }
+}
+
+/**
+* Stop a scan once the number of affected rows reaches the limit, if there is one
+*/
+fn scan_control_for_limit(affected_rows: usize, limit: Option<usize>) -> ScanControl {
+    match limit {
+        Some(limit) if affected_rows >= limit => ScanControl::Stop,
+        _ => ScanControl::Continue,
+    }
+}
+
+/**
+* Every SET expression is evaluated against the row before the update, so the assignments don't
+* depend on their order. Assigning a column twice would make the last one win, so it's rejected
+*/
+fn check_assigned_columns(column_values: &[asl::ColumnValue]) -> Result<(), QueryError> {
+    let mut assigned_columns = HashSet::new();
+    for column_value in column_values {
+        if !assigned_columns.insert(&column_value.column) {
+            return Err(QueryError::ValidationError(
+                format!("The column {} is assigned more than once", column_value.column)))
+        }
+    }
+    Ok(())
+}
+
+/**
+* Staged statements only run at COMMIT, so there are no rows to return when they are issued
+*/
+fn check_staged_returning(returning: &Option<asl::SelectColumns>) -> Result<(), QueryError> {
+    match returning {
+        Some(_) => Err(QueryError::ValidationError(String::from(
+            "RETURNING can't be used in a transaction, as the rows are only known at COMMIT"))),
+        None => Ok(()),
+    }
 }
 
 #[derive(Debug)]
 pub struct Database {
+    config: Config,
     db_definition: DatabaseDefinition,
-    db_filesystem: DBFileSystem,
+    storage: Box<dyn StorageEngine>,
+    format_settings: format::FormatSettings,
+    transaction: Option<Transaction>,
+    // Reject every query that modifies data or schema
+    read_only: bool,
+    // DROP DATABASE is rejected unless explicitly allowed, to avoid wiping the data by accident
+    drop_database_allowed: bool,
+    type_mode: asl::TypeMode,
+    // When disabled, data modifications outside a transaction implicitly begin one
+    autocommit: bool,
+    // The definitions are only persisted on close after they were loaded, so a database that was
+    // never bootstrapped doesn't overwrite the stored ones. DROP DATABASE clears it, so the deleted
+    // definitions aren't written back. Schema changes store the definitions themselves
+    bootstrapped: bool,
+    closed: bool,
+    // Check on bootstrap that every table can be read, so a missing or broken file is reported
+    // before the first query. It reads the first page of every table, so it can be skipped for
+    // databases with many tables
+    startup_check: bool,
+    // Unique index of each table, created empty with the table or built with a scan the first time
+    // the table is written after the database is opened. Inserts, updates and deletes keep it up to
+    // date, while failed writes and REPAIR TABLE drop it, so it's rebuilt on the next write
+    unique_indexes: RefCell<HashMap<String, UniqueKeys>>,
+    // Records of single-row INSERTs not written yet, by table, when inserts are batched
+    insert_buffers: RefCell<HashMap<String, Vec<asl::Record>>>,
+    // When the running query has to be aborted, if there is a timeout
+    deadline: Cell<Option<Instant>>,
+    // Non-fatal issues found while running the last query, cleared when the next one starts
+    warnings: RefCell<Vec<String>>,
 }
 
 impl Database {
     pub fn new() -> Database {
+        Database::with_config(Config::default())
+    }
+
+    /**
+    * Create a database over the files described by the config
+    */
+    pub fn with_config(config: Config) -> Database {
+        let mut database = Database::with_storage(Box::new(DBFileSystem::with_config(&config)));
+        database.read_only = config.read_only;
+        database.config = config;
+        database
+    }
+
+    /**
+    * Create a database that keeps all its data in memory, without touching the disk
+    */
+    pub fn in_memory() -> Database {
+        Database::with_storage(Box::new(InMemoryStorage::new()))
+    }
+
+    /**
+    * Create a database that persists its data in the given storage backend
+    */
+    pub fn with_storage(storage: Box<dyn StorageEngine>) -> Database {
         Database {
-            db_definition: DatabaseDefinition { tables: HashMap::new() },
-            db_filesystem: DBFileSystem::new(),
+            config: Config::default(),
+            db_definition: DatabaseDefinition::from_tables(BTreeMap::new()),
+            storage,
+            format_settings: format::FormatSettings::default(),
+            transaction: None,
+            read_only: false,
+            drop_database_allowed: false,
+            type_mode: asl::TypeMode::Lenient,
+            autocommit: true,
+            bootstrapped: false,
+            closed: false,
+            startup_check: true,
+            unique_indexes: RefCell::new(HashMap::new()),
+            insert_buffers: RefCell::new(HashMap::new()),
+            deadline: Cell::new(None),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    /**
+    * Create a database over the files in the default location that only allows reading them
+    */
+    pub fn new_read_only() -> Database {
+        Database::with_config(Config { read_only: true, ..Config::default() })
+    }
+
+    /**
+    * Set the approximate bytes of rows ORDER BY sorts in memory before spilling them to disk
+    */
+    pub fn sort_memory_limit(mut self, bytes: usize) -> Database {
+        self.config.sort_memory_limit = bytes;
+        self
+    }
+
+    /**
+    * Abort queries that run for longer than the timeout. See set_query_timeout
+    */
+    pub fn query_timeout(mut self, timeout: Duration) -> Database {
+        self.config.query_timeout = Some(timeout);
+        self
+    }
+
+    /**
+    * Sort the rows of SELECTs without ORDER BY by the primary key of their table. See
+    * Config::default_order_by_primary_key for its cost
+    */
+    pub fn default_order_by_primary_key(mut self) -> Database {
+        self.config.default_order_by_primary_key = true;
+        self
+    }
+
+    /**
+    * Set or clear the timeout of the queries run after it. The timeout is cooperative: it's checked
+    * when a scan moves to the next page, not enforced preemptively, so a query is aborted with a
+    * Timeout error at the first page boundary after the timeout expires
+    */
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.config.query_timeout = timeout;
+    }
+
+    /**
+    * Set the most rows a join may produce. Queries whose joins produce more fail with a
+    * ValidationError
+    */
+    pub fn max_join_rows(mut self, rows: usize) -> Database {
+        self.config.max_join_rows = rows;
+        self
+    }
+
+    /**
+    * Batch single-row INSERTs, writing the records of each table once it has the given number of
+    * them buffered. See set_insert_batch_rows
+    */
+    pub fn insert_batch_rows(mut self, rows: usize) -> Database {
+        self.config.insert_batch_rows = Some(rows);
+        self
+    }
+
+    /**
+    * Set or clear the number of rows single-row INSERTs are buffered for before they are written.
+    * Buffered rows are written before any other query runs, on COMMIT and when the database is
+    * flushed or closed, so they are only lost if the process crashes. Clearing it writes the
+    * buffered rows
+    */
+    pub fn set_insert_batch_rows(&mut self, rows: Option<usize>) -> Result<(), QueryError> {
+        if rows.is_none() {
+            self.flush_insert_buffers()?;
+        }
+        self.config.insert_batch_rows = rows;
+        Ok(())
+    }
+
+    /**
+    * Make the database reject every query that modifies data or schema
+    */
+    pub fn read_only(mut self) -> Database {
+        self.read_only = true;
+        self
+    }
+
+    /**
+    * Allow running DROP DATABASE queries
+    */
+    pub fn allow_drop_database(mut self) -> Database {
+        self.drop_database_allowed = true;
+        self
+    }
+
+    /**
+    * Reject queries that mix Int and Float operands without a CAST
+    */
+    pub fn strict_types(mut self) -> Database {
+        self.type_mode = asl::TypeMode::Strict;
+        self
+    }
+
+    /**
+    * Don't check that every table can be read when bootstrapping
+    */
+    pub fn skip_startup_check(mut self) -> Database {
+        self.startup_check = false;
+        self
+    }
+
+    /**
+    * Enable or disable autocommit. With autocommit disabled, data modifications are staged in an
+    * implicit transaction until COMMIT. It can't be changed while a transaction is in progress
+    */
+    pub fn set_autocommit(&mut self, autocommit: bool) -> Result<(), QueryError> {
+        if self.transaction.is_some() {
+            return Err(QueryError::ValidationError(
+                String::from("Commit or roll back the transaction in progress before changing autocommit")))
         }
+        self.autocommit = autocommit;
+        Ok(())
+    }
+
+    pub fn format_settings(&self) -> &format::FormatSettings {
+        &self.format_settings
+    }
+
+    pub fn format_settings_mut(&mut self) -> &mut format::FormatSettings {
+        &mut self.format_settings
+    }
+
+    /**
+    * Get the warnings of the last query: issues that didn't stop it, but may have made it do
+    * something other than intended
+    */
+    pub fn get_warnings(&self) -> Vec<String> {
+        self.warnings.borrow().clone()
+    }
+
+    fn add_warning(&self, warning: String) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    fn add_join_warnings(&self, query: &asl::SelectQuery) {
+        self.warnings.borrow_mut().extend(get_join_warnings(query));
     }
 
     pub fn bootstrap(&mut self) -> Result<(), SystemError> {
-        self.db_filesystem.ensure_base_path()?;
-        match self.db_filesystem.load_definitions() {
+        if self.config.page_size != PAGE_SIZE {
+            return Err(SystemError::InvalidConfig(format!(
+                "The page size must be {}, but it's {}", PAGE_SIZE, self.config.page_size)))
+        }
+        if self.config.fill_factor < MIN_FILL_FACTOR || self.config.fill_factor > 100 {
+            return Err(SystemError::InvalidConfig(format!(
+                "The fill factor must be between {} and 100, but it's {}", MIN_FILL_FACTOR, self.config.fill_factor)))
+        }
+        self.storage.initialize()?;
+        match self.storage.load_definitions() {
             Ok(definition) => {
                 self.db_definition = definition
             },
-            Err(_) => {}
+            // A new database has no definitions yet
+            Err(QueryError::NotFound(_)) => {},
+            Err(error) => return Err(SystemError::InvalidDefinitions(
+                format!("The table definitions can't be loaded: {}", error))),
         }
+        if self.startup_check {
+            for table in self.db_definition.get_tables() {
+                self.storage.check_table_files(table).map_err(|error| SystemError::InvalidTable(
+                    format!("The table {} can't be read: {}", table.name, error)))?;
+            }
+        }
+        self.bootstrapped = true;
         Ok(())
     }
 
+    /**
+    * Flush the buffered writes of the storage and persist the definitions
+    */
+    fn flush(&self) -> Result<(), QueryError> {
+        if self.read_only {
+            return Ok(())
+        }
+        self.flush_insert_buffers()?;
+        self.storage.flush()?;
+        if !self.bootstrapped {
+            return Ok(())
+        }
+        self.storage.store_definitions(&self.db_definition)
+    }
+
+    /**
+    * Flush and close the database. An open transaction is rolled back. Prefer this over just dropping
+    * the database, as dropping it can only print a warning if the flush fails. Buffered INSERTs that
+    * can't be written are lost, which is reported with a LostRows error that counts them
+    */
+    pub fn close(mut self) -> Result<(), QueryError> {
+        self.closed = true;
+        match self.flush() {
+            Err(error) if self.get_buffered_rows() > 0 => Err(QueryError::LostRows(format!(
+                "{} inserted rows couldn't be written before closing the database: {}", self.get_buffered_rows(), error))),
+            result => result,
+        }
+    }
+
+    /**
+    * Number of rows of INSERTs that are buffered but not written yet
+    */
+    fn get_buffered_rows(&self) -> usize {
+        self.insert_buffers.borrow().values().map(Vec::len).sum()
+    }
+
     /**
     * Get an existing table from the definition
     */
@@ -48,15 +441,401 @@ impl Database {
         }
     }
 
+    /**
+    * Get a table that can be read from: an existing table or one of the catalog tables, which are
+    * built from the definition
+    */
+    fn get_readable_table(&self, table_name: &str) -> Result<Cow<'_, asl::Table>, QueryError> {
+        match catalog::get_catalog_table(table_name) {
+            Some(table) => Ok(Cow::Owned(table)),
+            None => Ok(Cow::Borrowed(self.get_table(table_name)?)),
+        }
+    }
+
+    /**
+    * Fail with a Timeout error if the running query is past its deadline
+    */
+    fn check_deadline(&self) -> Result<(), QueryError> {
+        match (self.deadline.get(), self.config.query_timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() > deadline => Err(QueryError::Timeout(
+                format!("The query was aborted after running for more than {} ms", timeout.as_millis()))),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+    * Call f with every record of a readable table that matches the condition. The query timeout is
+    * checked at every page of stored tables, so the condition is evaluated here instead of by the
+    * storage, which would skip the pages without matching records
+    */
+    fn scan_table(&self, table: &asl::Table, condition: &Option<Box<asl::Expression>>,
+                  f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        if catalog::get_catalog_table(&table.name).is_some() {
+            return catalog::scan_catalog(table, &self.db_definition, condition, f)
+        }
+        let mut current_page_id = None;
+        self.storage.scan_records(table, &None, &mut |row_id, record| {
+            if current_page_id != Some(row_id.page_id) {
+                current_page_id = Some(row_id.page_id);
+                self.check_deadline()?;
+            }
+            if !evaluate_for_record(table, &record, condition)? {
+                return Ok(ScanControl::Continue)
+            }
+            f(row_id, record)
+        })
+    }
+
+    /**
+    * In strict mode, check the operand types of the expression against the column types of the table
+    * before running the query. Lenient mode accepts every expression and coerces values while
+    * evaluating them
+    */
+    fn check_expression_types(&self, table: &asl::Table, expression: &asl::Expression) -> Result<(), QueryError> {
+        if self.type_mode == asl::TypeMode::Lenient {
+            return Ok(())
+        }
+        let column_types: HashMap<String, asl::Type> = table.columns.iter()
+            .map(|column| (column.name.clone(), column.column_type.clone()))
+            .collect();
+        expression.infer_type(&column_types, self.type_mode)?;
+        Ok(())
+    }
+
+    fn check_condition_types(&self, table: &asl::Table,
+                             condition: &Option<Box<asl::Expression>>) -> Result<(), QueryError> {
+        match condition {
+            Some(condition) => self.check_expression_types(table, condition),
+            None => Ok(()),
+        }
+    }
+
     fn validate_select(&self, query: asl::SelectQuery) -> Result<(), QueryError> {
         Ok(())
     }
 
+    /**
+    * Get the tables in FROM of a SELECT: the first one and then the joined ones, in order
+    */
+    fn get_from_tables(&self, query: &asl::SelectQuery) -> Result<Vec<Cow<'_, asl::Table>>, QueryError> {
+        query.table.iter().map(String::as_str)
+            .chain(query.joins.iter().map(|join| join.table.as_str()))
+            .map(|table_name| self.get_readable_table(table_name))
+            .collect()
+    }
+
+    /**
+    * Qualify the column names of a SELECT with the joins, and strip the qualifier of the columns
+    * of a SELECT over a single table. See FromTables::resolve_identifiers
+    */
+    fn resolve_select_columns(&self, query: &mut asl::SelectQuery) -> Result<(), QueryError> {
+        let tables = self.get_from_tables(query)?;
+        if tables.is_empty() {
+            return Ok(())
+        }
+        FromTables::new(query, tables.iter().map(Cow::as_ref).collect())?.resolve_identifiers(query)
+    }
+
+    /**
+    * Get the table a SELECT reads from, or None when it has no FROM clause. With joins, it's a table
+    * with the columns of every joined table
+    */
+    fn get_select_table(&self, query: &asl::SelectQuery) -> Result<Option<Cow<'_, asl::Table>>, QueryError> {
+        match &query.table {
+            Some(_) if !query.joins.is_empty() => {
+                let tables = self.get_from_tables(query)?;
+                let from_tables = FromTables::new(query, tables.iter().map(Cow::as_ref).collect())?;
+                Ok(Some(Cow::Owned(from_tables.get_joined_table())))
+            },
+            Some(table_name) => Ok(Some(self.get_readable_table(table_name)?)),
+            None => Ok(None),
+        }
+    }
+
+    /**
+    * Call f with every row of the joins of a SELECT, before WHERE is applied. The joined tables are
+    * read into memory and the first one is scanned, so rows come in the order of the first table and
+    * each has its row id
+    */
+    fn scan_joined(&self, joined_table: &asl::Table, query: &asl::SelectQuery,
+                   f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        let tables = self.get_from_tables(query)?;
+        let mut inner_records = Vec::new();
+        for table in &tables[1..] {
+            let mut records = Vec::new();
+            self.scan_table(table, &None, &mut |_, record| {
+                records.push(record);
+                Ok(ScanControl::Continue)
+            })?;
+            inner_records.push(records);
+        }
+        let check_deadline = || self.check_deadline();
+        let mut join = NestedLoopJoin::new(joined_table, &query.joins, inner_records, self.config.max_join_rows,
+                                           &check_deadline);
+        self.scan_table(&tables[0], &None, &mut |row_id, record| {
+            for row in join.join(record)? {
+                if let ScanControl::Stop = f(row_id, row)? {
+                    return Ok(ScanControl::Stop)
+                }
+            }
+            Ok(ScanControl::Continue)
+        })
+    }
+
+    /**
+    * Call f with every row of the table of a SELECT that matches its condition, joining the tables of
+    * FROM if there are joins. A condition without columns is evaluated once: the table isn't read when
+    * it's false, and it isn't evaluated for each row when it's true
+    */
+    fn scan_select(&self, table: &asl::Table, query: &asl::SelectQuery,
+                   f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        let condition = match evaluate_constant_condition(&query.condition)? {
+            Some(false) => return Ok(()),
+            Some(true) => &None,
+            None => &query.condition,
+        };
+        if query.joins.is_empty() {
+            return self.scan_table(table, condition, f)
+        }
+        self.scan_joined(table, query, &mut |row_id, row| {
+            if !evaluate_for_record(table, &row, condition)? {
+                return Ok(ScanControl::Continue)
+            }
+            f(row_id, row)
+        })
+    }
+
+    /**
+    * Get the ORDER BY of the query, which without one is the primary key of its table when the
+    * default order is enabled. Aggregations and joins keep their order, as their rows don't map to
+    * a single row of a table
+    */
+    fn get_order_by<'a>(&self, table: Option<&asl::Table>, query: &'a asl::SelectQuery,
+                        projection: &Projection) -> Cow<'a, [asl::OrderBy]> {
+        if !self.config.default_order_by_primary_key || !query.order_by.is_empty() || !query.joins.is_empty()
+            || projection.is_aggregate() {
+            return Cow::Borrowed(&query.order_by)
+        }
+        let primary_key = table.and_then(|table| table.constraints.iter()
+            .find(|constraint| constraint.kind == ConstraintKind::PrimaryKey));
+        match primary_key {
+            Some(primary_key) => Cow::Owned(primary_key.columns.iter()
+                .map(|column| asl::OrderBy {
+                    expression: Box::new(asl::Expression::Identifier(column.clone())),
+                    descending: false,
+                })
+                .collect()),
+            None => Cow::Borrowed(&query.order_by),
+        }
+    }
+
+    /**
+    * Resolve the selected columns of the table, checking in strict mode the operand types of the
+    * condition and the selected expressions
+    */
+    fn get_projection(&self, table: Option<&asl::Table>, query: &asl::SelectQuery) -> Result<Projection, QueryError> {
+        let projection = Projection::new(table, &query.columns, &query.group_by)?
+            .with_distinct(query.distinct);
+        let order_by = self.get_order_by(table, query, &projection);
+        let projection = projection.with_order_by(table, &order_by)?;
+        if let Some(table) = table {
+            self.check_condition_types(table, &query.condition)?;
+            for join in &query.joins {
+                self.check_condition_types(table, &join.condition)?;
+            }
+            for expression in &query.group_by {
+                self.check_expression_types(table, expression)?;
+            }
+        }
+        if self.type_mode == asl::TypeMode::Strict {
+            projection.get_types(table, self.type_mode)?;
+        }
+        Ok(projection)
+    }
+
+    /**
+    * Call f with the result row of every record that matches the condition, in the order of the
+    * ORDER BY keys if there are any. Aggregates are computed over all the matching records, so f is
+    * called once with their single row, or once per group with GROUP BY. Without a table the
+    * projection is evaluated once, without a record. With DISTINCT, rows equal to an earlier one are
+    * skipped
+    */
+    fn scan_projected(&self, table: Option<&asl::Table>, projection: &Projection, query: &asl::SelectQuery,
+                      f: &mut dyn FnMut(asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        let mut seen_rows = HashSet::new();
+        let f = &mut |record: asl::Record| {
+            if projection.is_distinct() && !seen_rows.insert(record.clone()) {
+                return Ok(ScanControl::Continue)
+            }
+            f(record)
+        };
+        let table = match table {
+            Some(table) => table,
+            None => {
+                f(projection.evaluate()?)?;
+                return Ok(())
+            }
+        };
+        if projection.is_aggregate() {
+            let mut aggregation = projection.aggregate(table);
+            self.scan_select(table, query, &mut |_, record| {
+                aggregation.add(&record)?;
+                Ok(ScanControl::Continue)
+            })?;
+            for record in projection.sort_aggregated(aggregation.finish()?)? {
+                if let ScanControl::Stop = f(record)? {
+                    break
+                }
+            }
+            return Ok(())
+        }
+        if projection.is_sorted() {
+            for record in self.sort_projected(table, projection, query)? {
+                if let ScanControl::Stop = f(record?)? {
+                    break
+                }
+            }
+            return Ok(())
+        }
+        self.scan_select(table, query, &mut |row_id, record| f(projection.project(table, row_id, &record)?))
+    }
+
+    fn get_sorter(&self, projection: &Projection) -> Option<ExternalSorter> {
+        if !projection.is_sorted() {
+            return None
+        }
+        Some(ExternalSorter::new(projection.get_sort_descending(), self.config.sort_memory_limit, self.storage.temp_path()))
+    }
+
+    /**
+    * Sort the result rows of the records that match the condition by the ORDER BY keys of the
+    * projection. Past the sort memory limit, sorted runs are spilled to temporary files in the
+    * database directory and merged as the rows are read
+    */
+    fn sort_projected(&self, table: &asl::Table, projection: &Projection,
+                      query: &asl::SelectQuery) -> Result<SortedRecords, QueryError> {
+        let mut sorter = match self.get_sorter(projection) {
+            Some(sorter) => sorter,
+            None => return Err(QueryError::ValidationError(String::from("The query has no ORDER BY"))),
+        };
+        self.scan_select(table, query, &mut |row_id, record| {
+            let output = projection.project(table, row_id, &record)?;
+            sorter.add(projection.get_sort_keys(table, row_id, &record, &output)?, output)?;
+            Ok(ScanControl::Continue)
+        })?;
+        sorter.finish()
+    }
+
     fn run_select(&self, query: asl::SelectQuery) -> Result<String, QueryError> {
-        let table = self.get_table(&query.table)?;
-        let records = self.db_filesystem.select_records(table,
-                                                        &query.columns, &query.condition)?;
-        Ok(format!("Found Records {:?}", records))
+        let table = self.get_select_table(&query)?;
+        let table = table.as_deref();
+        let projection = self.get_projection(table, &query)?;
+        let max_rows = self.format_settings.max_rows;
+        let mut records: Vec<asl::Record> = Vec::new();
+        let mut omitted_records = 0;
+        self.scan_projected(table, &projection, &query, &mut |record| {
+            if max_rows.map_or(true, |max_rows| records.len() < max_rows) {
+                records.push(record);
+            } else {
+                omitted_records += 1;
+            }
+            Ok(ScanControl::Continue)
+        })?;
+        let mut result = format::format_records(projection.get_columns(), &records, &self.format_settings);
+        if omitted_records > 0 {
+            result.push_str(&format!("\n{} more rows...", omitted_records));
+        }
+        self.add_join_warnings(&query);
+        Ok(result)
+    }
+
+    /**
+    * Run the SELECT through instrumented stages, reporting for each one the rows that came out of it
+    * and the time spent in it, along with the pages read by the scan
+    */
+    fn run_explain_analyze(&self, query: asl::SelectQuery) -> Result<String, QueryError> {
+        let start = Instant::now();
+        let table = self.get_select_table(&query)?;
+        let table = table.as_deref();
+        let projection = self.get_projection(table, &query)?;
+        let stages = match table {
+            None => {
+                let mut output = StageStats::new(String::from("Project"));
+                output.time(|| projection.evaluate())?;
+                output.rows = 1;
+                vec![output]
+            },
+            Some(table) if !query.joins.is_empty() => {
+                let mut analysis = SelectAnalysis::new(table, &query.condition, &projection, self.get_sorter(&projection));
+                self.scan_joined(table, &query, &mut |row_id, row| {
+                    analysis.add(row_id, row)?;
+                    Ok(ScanControl::Continue)
+                })?;
+                analysis.finish(None)?
+            },
+            Some(table) if catalog::get_catalog_table(&table.name).is_some() => {
+                let mut analysis = SelectAnalysis::new(table, &query.condition, &projection, self.get_sorter(&projection));
+                catalog::scan_catalog(table, &self.db_definition, &None, &mut |row_id, record| {
+                    analysis.add(row_id, record)?;
+                    Ok(ScanControl::Continue)
+                })?;
+                analysis.finish(None)?
+            },
+            Some(table) => {
+                let mut analysis = SelectAnalysis::new(table, &query.condition, &projection, self.get_sorter(&projection));
+                let mut page_idx = 0;
+                while let Some(page) = self.storage.read_page(table, page_idx)? {
+                    self.check_deadline()?;
+                    page_idx += 1;
+                    for (slot, item) in page.items_iter()? {
+                        analysis.add(RowId { page_id: page.id, slot }, item?.to_record(table)?)?;
+                    }
+                }
+                analysis.finish(Some(page_idx))?
+            },
+        };
+        let mut result = format::format_records(
+            &STAGE_COLUMNS.iter().map(|column| String::from(*column)).collect::<Vec<String>>(),
+            &get_stage_records(&stages), &self.format_settings);
+        result.push_str(&format!("\nTotal time: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0));
+        self.add_join_warnings(&query);
+        Ok(result)
+    }
+
+    /**
+    * Run a SELECT and return its rows as an iterator instead of formatted output. Stored tables are
+    * read one page at a time while iterating, and the output row limit doesn't apply
+    */
+    pub fn query_iter(&self, query: &str) -> Result<RowIterator<'_>, QueryError> {
+        let mut query = match crate::parse(query)? {
+            asl::Query::Select(query) => query,
+            _ => return Err(QueryError::ValidationError(String::from("Only SELECT queries can be iterated"))),
+        };
+        query.resolve_current_time(temporal::now_micros());
+        self.resolve_select_columns(&mut query)?;
+        self.flush_insert_buffers()?;
+        let table = self.get_select_table(&query)?;
+        let projection = self.get_projection(table.as_deref(), &query)?;
+        match table {
+            Some(table) if projection.is_sorted() => {
+                let sorted = self.sort_projected(&table, &projection, &query)?;
+                Ok(RowIterator::from_sorted(projection, sorted))
+            },
+            Some(table) if catalog::get_catalog_table(&table.name).is_none() && !projection.is_aggregate()
+                && query.joins.is_empty() => match evaluate_constant_condition(&query.condition)? {
+                    Some(false) => Ok(RowIterator::from_rows(projection, Vec::new())),
+                    Some(true) => Ok(RowIterator::scan(self.storage.as_ref(), table, None, projection)),
+                    None => Ok(RowIterator::scan(self.storage.as_ref(), table, query.condition, projection)),
+                },
+            table => {
+                let mut rows = Vec::new();
+                self.scan_projected(table.as_deref(), &projection, &query, &mut |row| {
+                    rows.push(row);
+                    Ok(ScanControl::Continue)
+                })?;
+                Ok(RowIterator::from_rows(projection, rows))
+            }
+        }
     }
 
     fn validate_insert(&self, table: &asl::Table,
@@ -74,55 +853,1280 @@ impl Database {
                 None => return Err(QueryError::ValidationError(
                     format!("The column {} doesn't exist in {}", column_name, table.name)))
             };
-            if !value.has_type(&table_column.column_type) {
-                return Err(
-                    QueryError::ValidationError(
-                        format!("Incorrect value type for column {}. Expected '{:?}' and got '{:?}'",
-                                column_name, table_column.column_type, value))
-                )
-            };
+            check_value_type(table_column, value)?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Collect the keys of the records stored in the table for its unique constraints. The table is
+    * scanned only if it has constraints
+    */
+    fn get_unique_keys(&self, table: &asl::Table) -> Result<UniqueKeys, QueryError> {
+        let mut unique_keys = UniqueKeys::new(table);
+        if !unique_keys.is_empty() {
+            self.storage.scan_records(table, &None, &mut |_, record| {
+                unique_keys.add(&record)?;
+                Ok(ScanControl::Continue)
+            })?;
+        }
+        Ok(unique_keys)
+    }
+
+    /**
+    * Check that the records keep the PRIMARY KEY and UNIQUE constraints of the table, both against
+    * the stored records and among themselves, using its unique index, which is built first if the
+    * table doesn't have one. replaced_records are the stored records the records overwrite, whose
+    * keys can be reused
+    */
+    fn check_unique_index(&self, table: &asl::Table, replaced_records: &[asl::Record],
+                          records: &[asl::Record]) -> Result<(), QueryError> {
+        let mut unique_indexes = self.unique_indexes.borrow_mut();
+        if !unique_indexes.contains_key(&table.name) {
+            unique_indexes.insert(table.name.clone(), self.get_unique_keys(table)?);
+        }
+        unique_indexes[&table.name].check(replaced_records, records)
+    }
+
+    /**
+    * Move the keys of the unique index of the table from the old records to the new ones after they
+    * were written. The index is dropped if that fails, so it's rebuilt from the table
+    */
+    fn update_unique_index(&self, table_name: &str, old_records: &[asl::Record], new_records: &[asl::Record]) {
+        let mut unique_indexes = self.unique_indexes.borrow_mut();
+        let updated = match unique_indexes.get_mut(table_name) {
+            Some(unique_index) => unique_index.replace(old_records, new_records).is_ok(),
+            None => true,
+        };
+        if !updated {
+            unique_indexes.remove(table_name);
+        }
+    }
+
+    /**
+    * Drop the unique index of the table after its records change in a way that can't be tracked
+    */
+    fn invalidate_unique_index(&self, table_name: &str) {
+        self.unique_indexes.borrow_mut().remove(table_name);
+    }
+
+    /**
+    * Write the updated records over their rows and update the unique index with their keys.
+    * old_records are the stored records of the rows
+    */
+    fn update_indexed(&self, table: &asl::Table, updates: &[(RowId, asl::Record)],
+                      old_records: &[asl::Record]) -> Result<(), QueryError> {
+        if let Err(error) = self.storage.update_records(table, updates) {
+            // Some of the pages may have been written, so the index can't be trusted
+            self.invalidate_unique_index(&table.name);
+            return Err(error)
+        }
+        let new_records: Vec<asl::Record> = updates.iter().map(|(_, record)| record.clone()).collect();
+        self.update_unique_index(&table.name, old_records, &new_records);
+        Ok(())
+    }
+
+    /**
+    * Insert the records after checking the unique constraints and the foreign keys of the table, and
+    * add their keys to the unique index
+    */
+    fn insert_checked(&self, table: &asl::Table, records: &[asl::Record]) -> Result<(), QueryError> {
+        self.check_unique_index(table, &[], records)?;
+        check_foreign_keys(self.storage.as_ref(), &self.db_definition, table, records)?;
+        if let Err(error) = self.storage.insert_records(table, records) {
+            // The page being written when it failed may hold part of the records, so the index can't
+            // be trusted
+            self.invalidate_unique_index(&table.name);
+            return Err(error.into())
+        }
+        self.update_unique_index(&table.name, &[], records);
+        Ok(())
+    }
+
+    /**
+    * Check the record like insert_checked, but keep it in the insert buffer of the table until the
+    * buffer has batch_rows records, so a load of many single-row INSERTs writes each page once
+    * instead of once per row. The unique index already has the keys of the buffered records
+    */
+    fn insert_buffered(&self, table: &asl::Table, record: asl::Record, batch_rows: usize) -> Result<(), QueryError> {
+        self.check_unique_index(table, &[], std::slice::from_ref(&record))?;
+        self.update_unique_index(&table.name, &[], std::slice::from_ref(&record));
+        let buffered_rows = {
+            let mut insert_buffers = self.insert_buffers.borrow_mut();
+            let buffer = insert_buffers.entry(table.name.clone()).or_insert_with(Vec::new);
+            buffer.push(record);
+            buffer.len()
+        };
+        if buffered_rows >= batch_rows {
+            if let Err(error) = self.write_insert_buffer(&table.name) {
+                // The INSERT fails, so its record is taken back out of the buffer unless it was
+                // written. The records of the INSERTs before it stay buffered
+                let mut insert_buffers = self.insert_buffers.borrow_mut();
+                if let Some(buffer) = insert_buffers.get_mut(&table.name) {
+                    if let Some(record) = buffer.pop() {
+                        self.update_unique_index(&table.name, &[record], &[]);
+                    }
+                    if buffer.is_empty() {
+                        insert_buffers.remove(&table.name);
+                    }
+                }
+                drop(insert_buffers);
+                self.warn_unwritten_rows(&table.name, &error);
+                return Err(error)
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Write the buffered records of the table in one batch. Their INSERTs already succeeded, so if the
+    * write fails the records that weren't stored are kept in the buffer, to be written again by the
+    * next flush. The unique index keeps their keys meanwhile
+    */
+    fn write_insert_buffer(&self, table_name: &str) -> Result<(), QueryError> {
+        let table = self.get_table(table_name)?;
+        let mut records = match self.insert_buffers.borrow_mut().remove(table_name) {
+            Some(records) => records,
+            None => return Ok(()),
+        };
+        if let Err(error) = self.storage.insert_records(table, &records) {
+            records.drain(..error.written_records);
+            if !records.is_empty() {
+                self.insert_buffers.borrow_mut().insert(String::from(table_name), records);
+            }
+            return Err(error.error)
+        }
+        Ok(())
+    }
+
+    /**
+    * Write the buffered records of the table like write_insert_buffer, warning about the rows left
+    * to write if it fails
+    */
+    fn flush_insert_buffer(&self, table_name: &str) -> Result<(), QueryError> {
+        let result = self.write_insert_buffer(table_name);
+        if let Err(error) = &result {
+            self.warn_unwritten_rows(table_name, error);
+        }
+        result
+    }
+
+    fn warn_unwritten_rows(&self, table_name: &str, error: &QueryError) {
+        let unwritten_rows = self.insert_buffers.borrow().get(table_name).map_or(0, Vec::len);
+        if unwritten_rows > 0 {
+            self.add_warning(format!(
+                "{} inserted rows of {} couldn't be written ({}), they will be written again by the next query",
+                unwritten_rows, table_name, error));
+        }
+    }
+
+    /**
+    * Write the buffered records of every table, so queries and the storage see them
+    */
+    fn flush_insert_buffers(&self) -> Result<(), QueryError> {
+        let table_names: Vec<String> = self.insert_buffers.borrow().keys().cloned().collect();
+        for table_name in table_names {
+            self.flush_insert_buffer(&table_name)?;
         }
         Ok(())
     }
 
     fn run_insert(&self, query: asl::InsertQuery) -> Result<String, QueryError> {
         let table = self.get_table(&query.table)?;
-        let evaluated_expressions = query.evaluate_expressions()?;
-        print!("Evaluated expressions: {:?}", evaluated_expressions);
+        query.check_constant_values()?;
+        for expression in &query.values {
+            self.check_expression_types(table, expression)?;
+        }
+        let evaluated_expressions = query.evaluate_expressions(table)?;
         self.validate_insert(&table, &query, &evaluated_expressions)?;
+        // Records are stored with a value for every column of the table, in table order, so the values
+        // are placed by column position and the omitted columns get their default, or Null
+        let mut record = asl::Record {
+            values: table.columns.iter()
+                .map(|column| column.default.as_ref().map_or(asl::Value::Null, |default| default.evaluate()))
+                .collect()
+        };
+        let mut provided_columns = HashSet::new();
+        for (column_name, value) in query.columns.iter().zip(evaluated_expressions) {
+            if !provided_columns.insert(column_name) {
+                return Err(QueryError::ValidationError(
+                    format!("The column {} is specified more than once", column_name)))
+            }
+            if let Some(idx) = table.columns.iter().position(|column| &column.name == column_name) {
+                record.values[idx] = value;
+            }
+        }
+        if let Some(on_conflict) = &query.on_conflict {
+            return self.insert_on_conflict(table, vec![record], on_conflict)
+        }
         let result = format!("Running Insert {:?}", query);
-        self.db_filesystem.insert_record(table, &asl::Record { values: evaluated_expressions })?;
+        match self.config.insert_batch_rows {
+            // Checking the foreign keys reads the referenced tables, which need their buffered rows
+            Some(_) if table.constraints.iter().any(|constraint| !constraint.is_unique()) => {
+                self.flush_insert_buffers()?;
+                self.insert_checked(table, &[record])?;
+            },
+            Some(batch_rows) => self.insert_buffered(table, record, batch_rows)?,
+            None => self.insert_checked(table, &[record])?,
+        }
         Ok(result)
     }
 
+    /**
+    * Check the ON CONFLICT clause and get the column indexes of the constraints it checks. DO UPDATE
+    * can only set columns of the table, with expressions over the columns of the stored row and the
+    * EXCLUDED columns of the row that couldn't be inserted
+    */
+    fn validate_on_conflict(&self, table: &asl::Table, on_conflict: &asl::OnConflict) -> Result<Vec<Vec<usize>>, QueryError> {
+        let targets = get_conflict_targets(table, &on_conflict.target)?;
+        let column_values = match &on_conflict.action {
+            asl::ConflictAction::Nothing => return Ok(targets),
+            asl::ConflictAction::Update(column_values) => column_values,
+        };
+        check_assigned_columns(column_values)?;
+        let mut column_types = HashMap::new();
+        for column in &table.columns {
+            column_types.insert(column.name.clone(), column.column_type.clone());
+            column_types.insert(format!("{}{}", asl::EXCLUDED_PREFIX, column.name), column.column_type.clone());
+        }
+        for column_value in column_values {
+            if table.get_column(&column_value.column).is_none() {
+                return Err(QueryError::ValidationError(
+                    format!("The column {} doesn't exist in {}", column_value.column, table.name)))
+            }
+            for identifier in column_value.value.get_identifiers() {
+                if !column_types.contains_key(identifier) {
+                    return Err(QueryError::ValidationError(
+                        format!("The column {} doesn't exist in {}", identifier, table.name)))
+                }
+            }
+            if self.type_mode == asl::TypeMode::Strict {
+                column_value.value.infer_type(&column_types, self.type_mode)?;
+            }
+        }
+        Ok(targets)
+    }
+
+    /**
+    * Insert the records, skipping or updating instead the stored rows they conflict with, as the ON
+    * CONFLICT action says. A row can only be updated once per statement. The updated rows and the
+    * inserted ones are checked together against the constraints of the table before anything is
+    * written
+    */
+    fn insert_on_conflict(&self, table: &asl::Table, records: Vec<asl::Record>,
+                          on_conflict: &asl::OnConflict) -> Result<String, QueryError> {
+        let targets = self.validate_on_conflict(table, on_conflict)?;
+        let conflicts = find_conflicts(self.storage.as_ref(), table, &targets, &records)?;
+        let mut inserts = Vec::new();
+        let mut updates = Vec::new();
+        let mut old_records = Vec::new();
+        let mut skipped_records = 0;
+        for (record, conflict) in records.into_iter().zip(conflicts) {
+            let (row_id, stored_record) = match (conflict, &on_conflict.action) {
+                (None, _) => {
+                    inserts.push(record);
+                    continue
+                },
+                (Some(_), asl::ConflictAction::Nothing) => {
+                    skipped_records += 1;
+                    continue
+                },
+                (Some(Conflict::Inserted), asl::ConflictAction::Update(_)) => return Err(QueryError::Conflict(
+                    String::from("ON CONFLICT DO UPDATE can't update a row inserted by the same statement"))),
+                (Some(Conflict::Stored(row_id, stored_record)), asl::ConflictAction::Update(_)) => (row_id, stored_record),
+            };
+            if updates.iter().any(|(updated_row_id, _)| updated_row_id == &row_id) {
+                return Err(QueryError::Conflict(
+                    String::from("ON CONFLICT DO UPDATE can't update the same row twice in a statement")))
+            }
+            let mut identifier_values = get_identifier_values(table, &stored_record);
+            for (column, value) in table.columns.iter().zip(record.values) {
+                identifier_values.insert(format!("{}{}", asl::EXCLUDED_PREFIX, column.name), value);
+            }
+            let mut updated_record = stored_record.clone();
+            if let asl::ConflictAction::Update(column_values) = &on_conflict.action {
+                for column_value in column_values {
+                    let idx = table.columns.iter().position(|column| column.name == column_value.column).unwrap();
+                    let value = column_value.value.evaluate(Some(&identifier_values))?;
+                    check_value_type(&table.columns[idx], &value)?;
+                    updated_record.values[idx] = value;
+                }
+            }
+            updates.push((row_id, updated_record));
+            old_records.push(stored_record);
+        }
+        if !updates.is_empty() {
+            let updated_records: Vec<asl::Record> = updates.iter().map(|(_, record)| record.clone()).collect();
+            let mut new_records = updated_records.clone();
+            new_records.extend(inserts.iter().cloned());
+            self.check_unique_index(table, &old_records, &new_records)?;
+            check_foreign_keys(self.storage.as_ref(), &self.db_definition, table, &new_records)?;
+            check_referenced_updates(self.storage.as_ref(), &self.db_definition, table, &old_records, &updated_records)?;
+            self.update_indexed(table, &updates, &old_records)?;
+        }
+        self.insert_checked(table, &inserts)?;
+        Ok(format!("Inserted {} records, updated {}, skipped {}", inserts.len(), updates.len(), skipped_records))
+    }
+
+    /**
+    * Get the position in the target table of the column each selected column is inserted into: the
+    * listed columns in their order, or all the columns in definition order without a column list
+    */
+    fn get_insert_select_positions(&self, table: &asl::Table,
+                                   query: &asl::InsertSelectQuery) -> Result<Vec<usize>, QueryError> {
+        let column_names = match &query.columns {
+            Some(column_names) => column_names,
+            None => return Ok((0..table.columns.len()).collect()),
+        };
+        let mut positions = Vec::new();
+        for column_name in column_names {
+            let position = match table.columns.iter().position(|column| &column.name == column_name) {
+                Some(position) => position,
+                None => return Err(QueryError::ValidationError(
+                    format!("The column {} doesn't exist in {}", column_name, table.name)))
+            };
+            if positions.contains(&position) {
+                return Err(QueryError::ValidationError(
+                    format!("The column {} is specified more than once", column_name)))
+            }
+            positions.push(position);
+        }
+        Ok(positions)
+    }
+
+    /**
+    * Check that the rows selected from the source table can be stored in the target table: the number
+    * of selected columns must match the number of target columns and each selected column must have
+    * the type of the target column at the same position, or be NULL. Returns the projection and the
+    * position in the table of each target column
+    */
+    fn validate_insert_select(&self, query: &asl::InsertSelectQuery) -> Result<(Projection, Vec<usize>), QueryError> {
+        let table = self.get_table(&query.table)?;
+        let positions = self.get_insert_select_positions(table, query)?;
+        let source_table = self.get_select_table(&query.select)?;
+        let source_table = source_table.as_deref();
+        let projection = self.get_projection(source_table, &query.select)?;
+        let column_names = projection.get_columns();
+        if column_names.len() != positions.len() {
+            return Err(QueryError::ValidationError(match query.columns {
+                Some(_) => format!("The INSERT lists {} columns but the SELECT returns {}", positions.len(), column_names.len()),
+                None => format!("{} has {} columns but the SELECT returns {}", table.name, table.columns.len(), column_names.len()),
+            }))
+        }
+        let source_types = projection.get_types(source_table, self.type_mode)?;
+        for ((column_name, source_type), position) in column_names.iter().zip(&source_types).zip(&positions) {
+            let column = &table.columns[*position];
+            if source_type != &column.column_type && source_type != &asl::Type::Null {
+                return Err(QueryError::ValidationError(format!(
+                    "Can't insert {} of type '{:?}' into column {} of type '{:?}'",
+                    column_name, source_type, column.name, column.column_type)))
+            }
+        }
+        Ok((projection, positions))
+    }
+
+    /**
+    * Insert the selected rows in batches while the source table is scanned, so the rows are never all
+    * held in memory. When a table is copied into itself the rows are collected first, so the scan
+    * doesn't see the rows being inserted. With ON CONFLICT, all the rows are collected first too, so
+    * their conflicts are resolved together
+    */
+    fn run_insert_select(&self, query: asl::InsertSelectQuery) -> Result<String, QueryError> {
+        let (projection, positions) = self.validate_insert_select(&query)?;
+        let table = self.get_table(&query.table)?;
+        let source_table = self.get_select_table(&query.select)?;
+        let source_table = source_table.as_deref();
+        // Selected rows have the target columns in the order of the column list, so their values are
+        // moved to the position of their column, and the omitted columns get their default, or Null
+        let defaults: Vec<asl::Value> = table.columns.iter()
+            .map(|column| column.default.as_ref().map_or(asl::Value::Null, |default| default.evaluate()))
+            .collect();
+        let to_table_record = |record: asl::Record| {
+            let mut values = defaults.clone();
+            for (value, position) in record.values.into_iter().zip(&positions) {
+                values[*position] = value;
+            }
+            asl::Record { values }
+        };
+        if let Some(on_conflict) = &query.on_conflict {
+            self.validate_on_conflict(table, on_conflict)?;
+            let mut records = Vec::new();
+            self.scan_projected(source_table, &projection, &query.select, &mut |record| {
+                records.push(to_table_record(record));
+                Ok(ScanControl::Continue)
+            })?;
+            self.add_join_warnings(&query.select);
+            return self.insert_on_conflict(table, records, on_conflict)
+        }
+        let batch_size = if source_table.map_or(false, |source_table| source_table.name == table.name) { usize::max_value() } else { INSERT_BATCH_SIZE };
+        let mut batch = Vec::new();
+        let mut inserted_records = 0;
+        self.scan_projected(source_table, &projection, &query.select, &mut |record| {
+            batch.push(to_table_record(record));
+            if batch.len() >= batch_size {
+                self.insert_checked(table, &batch)?;
+                inserted_records += batch.len();
+                batch.clear();
+            }
+            Ok(ScanControl::Continue)
+        })?;
+        self.insert_checked(table, &batch)?;
+        inserted_records += batch.len();
+        self.add_join_warnings(&query.select);
+        Ok(format!("Inserted {} records", inserted_records))
+    }
+
+    /**
+    * Convert the fields of a CSV row to a record of the table. Fields equal to the null display
+    * string are loaded as Null, as that's how the csv output format writes them. The null display
+    * isn't persisted, so a file written with another one must be loaded after setting it again
+    */
+    fn decode_csv_record(&self, table: &asl::Table, fields: Vec<String>) -> Result<asl::Record, QueryError> {
+        if fields.len() != table.columns.len() {
+            return Err(QueryError::ValidationError(
+                format!("Expected {} fields but found {}", table.columns.len(), fields.len())))
+        }
+        let mut values = Vec::new();
+        for (field, column) in fields.into_iter().zip(&table.columns) {
+            if field == self.format_settings.null_display {
+                values.push(asl::Value::Null);
+            } else {
+                values.push(asl::Value::Str(field).cast(&column.column_type)?);
+            }
+        }
+        Ok(asl::Record { values })
+    }
+
+    /**
+    * Read the CSV file and call f with every row decoded as a record of the table. The first line must
+    * contain the column names of the table, in order
+    */
+    fn read_csv(&self, table: &asl::Table, path: &str,
+                f: &mut dyn FnMut(asl::Record) -> Result<(), QueryError>) -> Result<(), QueryError> {
+        let mut reader = CsvReader::new(BufReader::new(File::open(path)?));
+        let column_names: Vec<String> = table.columns.iter().map(|column| column.name.clone()).collect();
+        match reader.read_record()? {
+            Some(ref header) if header == &column_names => (),
+            Some(header) => return Err(QueryError::ValidationError(
+                format!("The CSV header {:?} doesn't match the columns of {}: {:?}", header, table.name, column_names))),
+            None => return Err(QueryError::ValidationError(format!("{} is empty", path))),
+        }
+        while let Some(fields) = reader.read_record()? {
+            let record = self.decode_csv_record(table, fields).map_err(|error| match error {
+                QueryError::ValidationError(message) =>
+                    QueryError::ValidationError(format!("Line {}: {}", reader.get_line_number(), message)),
+                error => error,
+            })?;
+            f(record)?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Call f with every record of the file to copy, in its format
+    */
+    fn read_copy_file(&self, table: &asl::Table, query: &asl::CopyQuery,
+                      f: &mut dyn FnMut(asl::Record) -> Result<(), QueryError>) -> Result<(), QueryError> {
+        match query.format {
+            asl::CopyFormat::Csv => self.read_csv(table, &query.path, f),
+            asl::CopyFormat::Json => json::read_json(table, &query.path, f),
+        }
+    }
+
+    /**
+    * Load the rows of a CSV or JSON file into the table. By default every row is decoded and checked
+    * against the constraints while the file is read, and the rows are only inserted once all of them
+    * are valid, so they are held in memory until then. Rows of a self-referencing table can only
+    * reference the rows before them. A trusted load checks the first row like that and then inserts
+    * the rows in batches while reading them, so a later row that can't be decoded or breaks a
+    * constraint stops the load but keeps the batches inserted before it
+    */
+    fn run_copy(&self, query: asl::CopyQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        let mut unique_keys = self.get_unique_keys(table)?;
+        let mut foreign_keys = ForeignKeys::new(self.storage.as_ref(), &self.db_definition, table)?;
+        let mut check_record = |record: &asl::Record| {
+            unique_keys.add(record)?;
+            foreign_keys.add_referenced(record);
+            foreign_keys.check(record)
+        };
+        if !query.trusted {
+            let mut records = Vec::new();
+            self.read_copy_file(table, &query, &mut |record| {
+                check_record(&record)?;
+                records.push(record);
+                Ok(())
+            })?;
+            for batch in records.chunks(INSERT_BATCH_SIZE) {
+                self.insert_checked(table, batch)?;
+            }
+            return Ok(format!("Copied {} records", records.len()))
+        }
+        let mut batch = Vec::new();
+        let mut inserted_records = 0;
+        self.read_copy_file(table, &query, &mut |record| {
+            if inserted_records == 0 && batch.is_empty() {
+                check_record(&record).map_err(|error| QueryError::ValidationError(
+                    format!("The first row can't be loaded into {}: {}", table.name, error)))?;
+            }
+            batch.push(record);
+            if batch.len() >= INSERT_BATCH_SIZE {
+                self.insert_checked(table, &batch)?;
+                inserted_records += batch.len();
+                batch.clear();
+            }
+            Ok(())
+        })?;
+        self.insert_checked(table, &batch)?;
+        inserted_records += batch.len();
+        Ok(format!("Copied {} records", inserted_records))
+    }
+
+    fn run_update(&self, query: asl::UpdateQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        check_assigned_columns(&query.column_values)?;
+        let mut column_indexes = Vec::new();
+        for column_value in &query.column_values {
+            match table.columns.iter().position(|column| column.name == column_value.column) {
+                Some(idx) => column_indexes.push(idx),
+                None => return Err(QueryError::ValidationError(
+                    format!("The column {} doesn't exist in {}", column_value.column, table.name)))
+            }
+            self.check_expression_types(table, &column_value.value)?;
+        }
+        self.check_condition_types(table, &query.condition)?;
+        let returning = self.get_returning(table, &query.returning)?;
+        // Rows are taken in scan order, as there is no ORDER BY to pick which ones the limit keeps
+        if query.limit == Some(0) {
+            return match &returning {
+                Some(returning) => self.format_returning(table, returning, &[]),
+                None => Ok(String::from("Updated 0 records")),
+            }
+        }
+        let mut updates = Vec::new();
+        let mut old_records = Vec::new();
+        self.scan_table(table, &query.condition, &mut |row_id, record| {
+            // Taken before any assignment, so SET a = b, b = a swaps the values
+            let identifier_values = get_identifier_values(table, &record);
+            let mut updated_record = record.clone();
+            for (column_value, idx) in query.column_values.iter().zip(&column_indexes) {
+                let value = column_value.value.evaluate(Option::Some(&identifier_values))?;
+                check_value_type(&table.columns[*idx], &value)?;
+                updated_record.values[*idx] = value;
+            }
+            updates.push((row_id, updated_record));
+            old_records.push(record);
+            Ok(scan_control_for_limit(updates.len(), query.limit))
+        })?;
+        let updated_records: Vec<asl::Record> = updates.iter().map(|(_, record)| record.clone()).collect();
+        self.check_unique_index(table, &old_records, &updated_records)?;
+        check_foreign_keys(self.storage.as_ref(), &self.db_definition, table, &updated_records)?;
+        check_referenced_updates(self.storage.as_ref(), &self.db_definition, table, &old_records, &updated_records)?;
+        self.update_indexed(table, &updates, &old_records)?;
+        match &returning {
+            Some(returning) => self.format_returning(table, returning, &updates),
+            None => Ok(format!("Updated {} records", updates.len())),
+        }
+    }
+
+    /**
+    * Resolve the RETURNING columns of a data modification against its table
+    */
+    fn get_returning(&self, table: &asl::Table,
+                     returning: &Option<asl::SelectColumns>) -> Result<Option<Projection>, QueryError> {
+        let columns = match returning {
+            Some(columns) => columns,
+            None => return Ok(None),
+        };
+        let projection = Projection::new(Some(table), columns, &[])?;
+        if projection.is_aggregate() {
+            return Err(QueryError::ValidationError(String::from("RETURNING can't use aggregate functions")))
+        }
+        if self.type_mode == asl::TypeMode::Strict {
+            projection.get_types(Some(table), self.type_mode)?;
+        }
+        Ok(Some(projection))
+    }
+
+    /**
+    * Format the RETURNING columns of the rows written or removed by a data modification
+    */
+    fn format_returning(&self, table: &asl::Table, returning: &Projection,
+                        rows: &[(RowId, asl::Record)]) -> Result<String, QueryError> {
+        let records = rows.iter()
+            .map(|(row_id, record)| returning.project(table, *row_id, record))
+            .collect::<Result<Vec<asl::Record>, QueryError>>()?;
+        Ok(format::format_records(returning.get_columns(), &records, &self.format_settings))
+    }
+
+    /**
+    * Delete the matching rows. Rows referenced by foreign keys with ON DELETE CASCADE are deleted
+    * along with the rows referencing them, and any other reference rejects the whole delete. RETURNING
+    * only returns the matching rows, not the ones deleted by a cascade
+    */
+    fn run_delete(&self, query: asl::DeleteQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        self.check_condition_types(table, &query.condition)?;
+        let returning = self.get_returning(table, &query.returning)?;
+        // Rows are taken in scan order, as there is no ORDER BY to pick which ones the limit keeps
+        if query.limit == Some(0) {
+            return match &returning {
+                Some(returning) => self.format_returning(table, returning, &[]),
+                None => Ok(String::from("Deleted 0 records")),
+            }
+        }
+        let mut rows = Vec::new();
+        self.scan_table(table, &query.condition, &mut |row_id, record| {
+            rows.push((row_id, record));
+            Ok(scan_control_for_limit(rows.len(), query.limit))
+        })?;
+        let deleted_records = rows.len();
+        // Captured before the delete, as the records can't be read back afterwards
+        let returned_rows = match &returning {
+            Some(_) => rows.clone(),
+            None => Vec::new(),
+        };
+        let mut deletes = HashMap::new();
+        collect_deletes(self.storage.as_ref(), &self.db_definition, table, rows, &mut deletes)?;
+        let mut cascaded = Vec::new();
+        for (table_name, rows) in &deletes {
+            let row_ids: Vec<RowId> = rows.keys().cloned().collect();
+            if let Err(error) = self.storage.delete_records(self.get_table(table_name)?, &row_ids) {
+                self.invalidate_unique_index(table_name);
+                return Err(error)
+            }
+            let records: Vec<asl::Record> = rows.values().cloned().collect();
+            self.update_unique_index(table_name, &records, &[]);
+            if table_name == &table.name && row_ids.len() > deleted_records {
+                cascaded.push(format!("{} more from {}", row_ids.len() - deleted_records, table_name));
+            } else if table_name != &table.name {
+                cascaded.push(format!("{} from {}", row_ids.len(), table_name));
+            }
+        }
+        if let Some(returning) = &returning {
+            return self.format_returning(table, returning, &returned_rows)
+        }
+        if cascaded.is_empty() {
+            return Ok(format!("Deleted {} records", deleted_records))
+        }
+        cascaded.sort();
+        Ok(format!("Deleted {} records, cascaded to {}", deleted_records, cascaded.join(", ")))
+    }
+
     fn run_create_table(&mut self, query: asl::CreateTableQuery) -> Result<String, QueryError> {
         if self.get_table(&query.table).is_ok() {
             return Err(QueryError::Conflict(query.table))
         }
+        if query.table.starts_with(catalog::CATALOG_PREFIX) {
+            return Err(QueryError::ValidationError(format!(
+                "Table names starting with {} are reserved for the catalog", catalog::CATALOG_PREFIX)))
+        }
+        validate_constraints(&query.columns, &query.constraints)?;
         let result = format!("Running Create Table {:?}", query);
-        let table = asl::Table {name: query.table, columns: query.columns};
-        self.db_filesystem.create_table_files(&table)?;
+        let mut columns = query.columns;
+        for column in columns.iter_mut() {
+            column.default = convert_column_default(column, &column.column_type)?;
+        }
+        let table = asl::Table {
+            name: query.table,
+            columns,
+            comment: query.comment,
+            constraints: query.constraints,
+        };
+        validate_foreign_keys(&self.db_definition, &table)?;
+        self.storage.create_table_files(&table)?;
+        self.unique_indexes.borrow_mut().insert(table.name.clone(), UniqueKeys::new(&table));
         self.db_definition.tables.insert(table.name.clone(), table);
-        self.db_filesystem.store_definitions(&self.db_definition)?;
+        self.storage.store_definitions(&self.db_definition)?;
         Ok(result)
     }
 
+    /**
+    * Create an empty table with the definition of the source table. Foreign keys of the source that
+    * reference the source itself reference the new table instead
+    */
+    fn run_create_table_like(&mut self, query: asl::CreateTableLikeQuery) -> Result<String, QueryError> {
+        let source = self.get_table(&query.source)?;
+        let mut columns = source.columns.clone();
+        asl::Column::assign_ids(&mut columns);
+        let mut constraints = source.constraints.clone();
+        for constraint in constraints.iter_mut() {
+            if let ConstraintKind::ForeignKey { table, .. } = &mut constraint.kind {
+                if table == &source.name {
+                    *table = query.table.clone();
+                }
+            }
+        }
+        let create_table = asl::CreateTableQuery { table: query.table, columns, constraints, comment: source.comment.clone() };
+        self.run_create_table(create_table)
+    }
+
+    /**
+    * Drop the tables, checking all of them before dropping any: a table that doesn't exist, unless
+    * IF EXISTS skips it, or that is referenced by a table that isn't dropped fails the whole statement
+    */
     fn run_drop_table(&mut self, query: asl::DropTableQuery) -> Result<String, QueryError> {
-        self.db_filesystem.delete_table_files(self.get_table(&query.table)?)?;
-        self.db_definition.tables.remove(&query.table);
-        Ok(format!("Running Drop Table {:?}", query))
+        let mut table_names: Vec<&String> = Vec::new();
+        for table_name in &query.tables {
+            if table_names.contains(&table_name) {
+                return Err(QueryError::ValidationError(format!("The table {} is listed more than once", table_name)))
+            }
+            match self.get_table(table_name) {
+                Ok(_) => table_names.push(table_name),
+                Err(QueryError::NotFound(_)) if query.if_exists =>
+                    self.add_warning(format!("The table {} doesn't exist, so it was skipped", table_name)),
+                Err(error) => return Err(error),
+            }
+        }
+        for table_name in &table_names {
+            let referencing_constraints = get_referencing_constraints(&self.db_definition, table_name);
+            if let Some((referencing_table, constraint)) = referencing_constraints.iter()
+                .find(|(referencing_table, _)| !table_names.contains(&&referencing_table.name)) {
+                return Err(QueryError::Conflict(format!(
+                    "Can't drop {}: it's referenced by {} of {}", table_name, constraint, referencing_table.name)))
+            }
+        }
+        if table_names.is_empty() {
+            return Ok(String::from("No tables dropped"))
+        }
+        // The definitions are stored before any file is deleted, so a failure leaves every table in
+        // place, and a file that can't be deleted afterwards only leaves an unused file behind
+        let dropped_tables: Vec<asl::Table> = table_names.iter()
+            .filter_map(|table_name| self.db_definition.tables.remove(*table_name))
+            .collect();
+        if let Err(error) = self.storage.store_definitions(&self.db_definition) {
+            for table in dropped_tables {
+                self.db_definition.tables.insert(table.name.clone(), table);
+            }
+            return Err(error)
+        }
+        for table in &dropped_tables {
+            self.invalidate_unique_index(&table.name);
+            if let Err(error) = self.storage.delete_table_files(table) {
+                self.add_warning(format!("The files of the dropped table {} couldn't be deleted: {}", table.name, error));
+            }
+        }
+        let table_names: Vec<&str> = table_names.iter().map(|table_name| table_name.as_str()).collect();
+        Ok(format!("Dropped {}", table_names.join(", ")))
+    }
+
+    fn run_create_sequence(&mut self, query: asl::SequenceQuery) -> Result<String, QueryError> {
+        if self.db_definition.sequences.contains_key(&query.name) {
+            return Err(QueryError::Conflict(query.name))
+        }
+        self.db_definition.sequences.insert(query.name.clone(), 0);
+        self.storage.store_definitions(&self.db_definition)?;
+        Ok(format!("CREATE SEQUENCE {}", query.name))
+    }
+
+    fn run_drop_sequence(&mut self, query: asl::SequenceQuery) -> Result<String, QueryError> {
+        if self.db_definition.sequences.remove(&query.name).is_none() {
+            return Err(QueryError::NotFound(format!("Sequence {}", query.name)))
+        }
+        self.storage.store_definitions(&self.db_definition)?;
+        Ok(format!("DROP SEQUENCE {}", query.name))
     }
 
     /**
-    Parse and run query
+    * Replace NEXTVAL in the values of an INSERT with the next values of the sequences. The values
+    * are taken when the statement is issued, even if it's staged in a transaction or fails later, and
+    * the definitions are stored right away, so a value is never returned twice, even after a crash
     */
-    pub fn run_query(&mut self, query: &str) -> Result<String, QueryError> {
-        let query = sql_grammar::QueryParser::new().parse(query)?;
+    fn resolve_next_values(&mut self, query: &mut asl::Query) -> Result<(), QueryError> {
+        let sequences = &mut self.db_definition.sequences;
+        let mut advanced = false;
+        let result = query.resolve_next_values(&mut |name| {
+            let value = match sequences.get_mut(name) {
+                Some(value) => value,
+                None => return Err(QueryError::NotFound(format!("Sequence {}", name))),
+            };
+            *value = value.checked_add(1).ok_or_else(|| QueryError::ValidationError(
+                format!("The sequence {} reached the largest Int value", name)))?;
+            advanced = true;
+            Ok(*value)
+        });
+        if advanced {
+            self.storage.store_definitions(&self.db_definition)?;
+        }
+        result
+    }
+
+    /**
+    * Change the type of a column, converting its value in every record with the CAST rules. All the
+    * records are converted before anything is written, so a value that can't be converted leaves the
+    * table unchanged. Columns that are part of a foreign key keep their type, as both sides of a
+    * foreign key must have the same type
+    */
+    fn run_alter_column_type(&mut self, query: asl::AlterColumnTypeQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        let column_idx = match table.columns.iter().position(|column| column.name == query.column) {
+            Some(idx) => idx,
+            None => return Err(QueryError::ValidationError(
+                format!("The column {} doesn't exist in {}", query.column, table.name)))
+        };
+        let in_foreign_key = self.db_definition.get_tables()
+            .flat_map(|other| other.constraints.iter().map(move |constraint| (other, constraint)))
+            .any(|(other, constraint)| match &constraint.kind {
+                ConstraintKind::ForeignKey { table: referenced_table, columns, .. } =>
+                    (other.name == table.name && constraint.columns.contains(&query.column))
+                        || (referenced_table == &table.name && columns.contains(&query.column)),
+                _ => false,
+            });
+        if in_foreign_key {
+            return Err(QueryError::ValidationError(format!(
+                "Can't change the type of {}.{}, it's part of a foreign key", table.name, query.column)))
+        }
+        let mut altered_table = table.clone();
+        altered_table.columns[column_idx].default = convert_column_default(&table.columns[column_idx], &query.column_type)?;
+        altered_table.columns[column_idx].column_type = query.column_type.clone();
+        let mut updates = Vec::new();
+        self.storage.scan_records(table, &None, &mut |row_id, mut record| {
+            let value = &record.values[column_idx];
+            record.values[column_idx] = value.cast(&query.column_type).map_err(|error| {
+                let reason = match error {
+                    QueryError::ValidationError(message) => message,
+                    error => format!("{:?}", error),
+                };
+                QueryError::ValidationError(format!("Can't convert the value {} of {}: {}", value, query.column, reason))
+            })?;
+            updates.push((row_id, record));
+            Ok(ScanControl::Continue)
+        })?;
+        let mut unique_keys = UniqueKeys::new(&altered_table);
+        for (_, record) in &updates {
+            unique_keys.add(record)?;
+        }
+        self.invalidate_unique_index(&altered_table.name);
+        self.storage.update_records(&altered_table, &updates)?;
+        // Every row was converted, so the keys collected above are the new index
+        self.unique_indexes.borrow_mut().insert(altered_table.name.clone(), unique_keys);
+        self.db_definition.tables.insert(altered_table.name.clone(), altered_table);
+        self.storage.store_definitions(&self.db_definition)?;
+        Ok(format!("Converted {} records of {}.{} to {:?}", updates.len(), query.table, query.column, query.column_type))
+    }
+
+    /**
+    * Remove every table and the stored definition, leaving an empty database. Closing it doesn't
+    * store the empty definition, so the directory is left without one
+    */
+    pub fn drop_all(&mut self) -> Result<(), QueryError> {
+        if self.read_only {
+            return Err(QueryError::ValidationError(String::from("database is read-only")))
+        }
+        self.unique_indexes.borrow_mut().clear();
+        self.insert_buffers.borrow_mut().clear();
+        self.storage.delete_database(&self.db_definition)?;
+        self.db_definition = DatabaseDefinition::from_tables(BTreeMap::new());
+        self.bootstrapped = false;
+        Ok(())
+    }
+
+    fn run_drop_database(&mut self) -> Result<String, QueryError> {
+        if !self.drop_database_allowed {
+            return Err(QueryError::ValidationError(
+                String::from("DROP DATABASE is disabled, start with --allow-drop-database to enable it")))
+        }
+        let table_count = self.db_definition.tables.len();
+        self.drop_all()?;
+        Ok(format!("Dropped database with {} tables", table_count))
+    }
+
+    /**
+    * List the columns of the table with their types, defaults and comments. The table comment, if any, is
+    * shown above the columns
+    */
+    fn run_describe(&self, query: asl::DescribeQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        let optional_str = |value: &Option<String>| match value {
+            Some(value) => asl::Value::Str(value.clone()),
+            None => asl::Value::Null,
+        };
+        let records: Vec<asl::Record> = table.columns.iter()
+            .map(|column| asl::Record { values: vec![
+                asl::Value::Str(column.name.clone()),
+                asl::Value::Str(format!("{:?}", column.column_type)),
+                optional_str(&column.default.as_ref().map(|default| default.to_string())),
+                optional_str(&column.comment),
+            ]})
+            .collect();
+        let columns = vec![String::from("column"), String::from("type"), String::from("default"), String::from("comment")];
+        let mut description = format::format_records(&columns, &records, &self.format_settings);
+        for constraint in &table.constraints {
+            description.push_str(&format!("\n{}", constraint));
+        }
+        match &table.comment {
+            Some(comment) => Ok(format!("Table {}: {}\n{}", table.name, comment, description)),
+            None => Ok(description),
+        }
+    }
+
+    fn run_check_table(&self, query: asl::CheckTableQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        let problems = self.storage.check_table(table)?;
+        if problems.is_empty() {
+            Ok(String::from("OK"))
+        } else {
+            Ok(problems.iter().map(|problem| problem.to_string()).collect::<Vec<String>>().join("\n"))
+        }
+    }
+
+    /**
+    * Find the defined tables without a data file and the data files without a table definition, which
+    * can only happen when the files are changed outside of the database. Sorted by table name
+    */
+    pub fn check_consistency(&self) -> Result<Vec<Inconsistency>, QueryError> {
+        let table_files: HashSet<String> = self.storage.list_table_files()?.into_iter().collect();
+        let mut inconsistencies: Vec<Inconsistency> = self.db_definition.tables.keys()
+            .filter(|name| !table_files.contains(*name))
+            .map(|name| Inconsistency::MissingDataFile(name.clone()))
+            .collect();
+        let mut orphans: Vec<&String> = table_files.iter()
+            .filter(|name| !self.db_definition.tables.contains_key(*name))
+            .collect();
+        orphans.sort();
+        inconsistencies.extend(orphans.into_iter().map(|name| Inconsistency::OrphanDataFile(name.clone())));
+        Ok(inconsistencies)
+    }
+
+    fn run_check_consistency(&self) -> Result<String, QueryError> {
+        let inconsistencies = self.check_consistency()?;
+        if inconsistencies.is_empty() {
+            Ok(String::from("OK"))
+        } else {
+            Ok(inconsistencies.iter().map(|inconsistency| inconsistency.to_string()).collect::<Vec<String>>().join("\n"))
+        }
+    }
+
+    fn run_repair_table(&self, query: asl::RepairTableQuery) -> Result<String, QueryError> {
+        let table = self.get_table(&query.table)?;
+        self.invalidate_unique_index(&table.name);
+        Ok(self.storage.repair_table(table)?.to_string())
+    }
+
+    /**
+    * Compute the space used by the table from its page headers and slot directories, without
+    * decoding its records. Pages with an invalid header count as empty
+    */
+    pub fn table_size(&self, name: &str) -> Result<TableSize, QueryError> {
+        let table = self.get_table(name)?;
+        self.flush_insert_buffer(name)?;
+        let mut size = TableSize { page_count: 0, total_bytes: 0, live_records: 0, dead_bytes: 0 };
+        let mut last_free_space = 0;
+        while let Some(page) = self.storage.read_page(table, size.page_count)? {
+            size.page_count += 1;
+            size.dead_bytes += last_free_space;
+            last_free_space = page.get_header().get_free_space();
+            if page.validate_header().is_ok() {
+                size.live_records += page.get_item_count();
+            }
+        }
+        size.total_bytes = size.page_count * PAGE_SIZE;
+        Ok(size)
+    }
+
+    /**
+    * Describe a page of the table for debugging storage problems: its header fields and the offset
+    * and size of each item in its slot directory, along with whether the item decodes. Page ids
+    * start at 1. The slot directory isn't read when the header is invalid
+    */
+    pub fn dump_page(&self, table_name: &str, page_id: usize) -> Result<String, QueryError> {
+        let table = self.get_table(table_name)?;
+        self.flush_insert_buffer(table_name)?;
+        let page = match page_id.checked_sub(1) {
+            Some(page_idx) => self.storage.read_page(table, page_idx)?,
+            None => None,
+        };
+        let page = page.ok_or_else(|| QueryError::NotFound(format!("Page {} of table {}", page_id, table.name)))?;
+        let header = page.get_header();
+        let mut result = format!(
+            "Page {} of {}\nid: {}\nfree_space_start: {}\nfree_space_end: {}\nslots: {}\nfree_space: {}\n",
+            page_id, table.name, page.id, header.get_free_space_start(), header.get_free_space_end(),
+            header.get_slot_count(), header.get_free_space());
+        if let Err(error) = page.validate_header() {
+            result.push_str(&format!("Invalid header: {}", error));
+            return Ok(result)
+        }
+        let records: Vec<asl::Record> = page.get_item_offset_and_sizes().into_iter().enumerate()
+            .map(|(slot, item)| {
+                let (offset, size) = item.unwrap_or_default();
+                let status = match item.map(|_| page.get_item(offset, size).and_then(|item| item.to_record(table))) {
+                    Some(Ok(_)) => String::from("ok"),
+                    Some(Err(error)) => error.to_string(),
+                    None => String::from("deleted"),
+                };
+                asl::Record { values: vec![
+                    asl::Value::Int(slot as i32),
+                    asl::Value::Int(offset as i32),
+                    asl::Value::Int(size as i32),
+                    asl::Value::Str(status),
+                ]}
+            })
+            .collect();
+        let columns: Vec<String> = ["slot", "offset", "size", "status"].iter().map(|column| String::from(*column)).collect();
+        result.push_str(&format::format_records(&columns, &records, &self.format_settings));
+        Ok(result)
+    }
+
+    fn run_show_table_status(&self, query: asl::ShowTableStatusQuery) -> Result<String, QueryError> {
+        let table_names: Vec<&String> = match &query.table {
+            Some(table) => vec![&self.get_table(table)?.name],
+            None => self.db_definition.tables.keys().collect(),
+        };
+        let mut records = Vec::new();
+        for name in table_names {
+            let size = self.table_size(name)?;
+            records.push(asl::Record { values: vec![
+                asl::Value::Str(name.clone()),
+                asl::Value::Int(size.page_count as i32),
+                asl::Value::Int(size.total_bytes as i32),
+                asl::Value::Int(size.live_records as i32),
+                asl::Value::Int(size.dead_bytes as i32),
+            ]});
+        }
+        let columns: Vec<String> = vec!["table", "pages", "bytes", "live_records", "dead_bytes"].into_iter()
+            .map(String::from)
+            .collect();
+        Ok(format::format_records(&columns, &records, &self.format_settings))
+    }
+
+    /**
+    * Report the storage location and the active settings
+    */
+    fn run_show_config(&self) -> Result<String, QueryError> {
+        let settings = vec![
+            ("storage", self.storage.location()),
+            ("definitions_file", self.config.definitions_file.clone()),
+            ("page_size", PAGE_SIZE.to_string()),
+            ("durable", String::from(if self.config.durable { "on" } else { "off" })),
+            ("definitions_format", String::from(if self.config.json_definitions { "json" } else { "binary" })),
+            ("sort_memory_limit", self.config.sort_memory_limit.to_string()),
+            ("query_timeout", self.config.query_timeout.map_or(String::from("off"), |timeout| format!("{} ms", timeout.as_millis()))),
+            ("max_join_rows", self.config.max_join_rows.to_string()),
+            ("fill_factor", format!("{}%", self.config.fill_factor)),
+            ("default_order_by_primary_key", String::from(if self.config.default_order_by_primary_key { "on" } else { "off" })),
+            ("insert_batch_rows", self.config.insert_batch_rows.map_or(String::from("off"), |rows| rows.to_string())),
+            ("format", format!("{:?}", self.format_settings.format).to_lowercase()),
+            ("null_display", self.format_settings.null_display.clone()),
+            ("max_rows", self.format_settings.max_rows.map_or(String::from("unlimited"), |max| max.to_string())),
+            ("float_precision", self.format_settings.float_precision
+                .map_or(String::from("default"), |precision| precision.to_string())),
+            ("pager", String::from(if self.format_settings.pager { "on" } else { "off" })),
+            ("type_mode", format!("{:?}", self.type_mode).to_lowercase()),
+            ("autocommit", String::from(if self.autocommit { "on" } else { "off" })),
+        ];
+        let records: Vec<asl::Record> = settings.into_iter()
+            .map(|(name, value)| asl::Record { values: vec![asl::Value::Str(String::from(name)), asl::Value::Str(value)] })
+            .collect();
+        let columns = vec![String::from("setting"), String::from("value")];
+        Ok(format::format_records(&columns, &records, &self.format_settings))
+    }
+
+    fn get_transaction(&mut self) -> Result<&mut Transaction, QueryError> {
+        match &mut self.transaction {
+            Some(transaction) => Ok(transaction),
+            None => Err(QueryError::ValidationError(String::from("No transaction in progress")))
+        }
+    }
+
+    fn run_begin(&mut self) -> Result<String, QueryError> {
+        if self.transaction.is_some() {
+            return Err(QueryError::ValidationError(String::from("A transaction is already in progress")))
+        }
+        self.transaction = Some(Transaction::new());
+        Ok(String::from("BEGIN"))
+    }
+
+    /**
+    * Apply the staged queries in order through a StagedStorage, which keeps the pages they write in
+    * memory. Only once every query succeeded are the written pages installed, replacing the pages of
+    * each table at once. If a query fails nothing is written, the transaction ends and the error is
+    * returned. Installing is atomic per table, so a crash while installing can still leave only
+    * some of the tables written
+    */
+    fn run_commit(&mut self) -> Result<String, QueryError> {
+        self.get_transaction()?;
+        let staged_queries = self.transaction.take().unwrap().into_staged_queries();
+        let staged_count = staged_queries.len();
+        // Buffered rows of earlier INSERTs are committed already, so they are written to the storage
+        self.flush_insert_buffers()?;
+        let storage = mem::replace(&mut self.storage, Box::new(InMemoryStorage::new()));
+        self.storage = Box::new(StagedStorage::new(storage, InMemoryStorage::with_config(&self.config)));
+        let result = self.execute_staged(staged_queries);
+        let (storage, staged) = match self.storage.as_any_mut().downcast_mut::<StagedStorage>() {
+            Some(staged_storage) => staged_storage.take_parts(),
+            // Nothing replaces the storage while the staged queries run, but if something did, that
+            // storage is kept and the staged writes are discarded
+            None => return Err(QueryError::Conflict(String::from("The staged storage was replaced before the commit"))),
+        };
+        self.storage = storage;
+        let result = result.and_then(|_| {
+            let tables = staged.into_tables().into_iter()
+                .map(|(table_name, pages)| Ok((self.get_table(&table_name)?, pages)))
+                .collect::<Result<Vec<_>, QueryError>>()?;
+            self.storage.replace_tables_pages(&tables)
+        });
+        if let Err(error) = result {
+            // The unique indexes and insert buffers may have the rows of the discarded writes
+            self.unique_indexes.borrow_mut().clear();
+            self.insert_buffers.borrow_mut().clear();
+            return Err(error)
+        }
+        Ok(format!("COMMIT: applied {} statements", staged_count))
+    }
+
+    fn execute_staged(&mut self, queries: Vec<asl::Query>) -> Result<(), QueryError> {
+        for query in queries {
+            self.execute(query)?;
+        }
+        self.flush_insert_buffers()
+    }
+
+    fn run_rollback(&mut self) -> Result<String, QueryError> {
+        let discarded = self.get_transaction()?.get_staged_count();
+        self.transaction = None;
+        Ok(format!("ROLLBACK: discarded {} statements", discarded))
+    }
+
+    /**
+    * Check the staged query as far as possible without the changes staged before it, so mistakes are
+    * reported when the statement is issued instead of at commit time
+    */
+    fn validate_staged_query(&self, query: &asl::Query) -> Result<(), QueryError> {
+        match query {
+            asl::Query::Insert(q) => {
+                let table = self.get_table(&q.table)?;
+                if let Some(on_conflict) = &q.on_conflict {
+                    self.validate_on_conflict(table, on_conflict)?;
+                }
+                self.validate_insert(table, q, &q.evaluate_expressions(table)?)
+            },
+            asl::Query::InsertSelect(q) => {
+                self.validate_insert_select(q)?;
+                match &q.on_conflict {
+                    Some(on_conflict) => self.validate_on_conflict(self.get_table(&q.table)?, on_conflict).map(|_| ()),
+                    None => Ok(()),
+                }
+            },
+            asl::Query::Copy(q) => self.get_table(&q.table).map(|_| ()),
+            asl::Query::Update(q) => {
+                self.get_table(&q.table)?;
+                check_assigned_columns(&q.column_values)?;
+                check_staged_returning(&q.returning)
+            },
+            asl::Query::Delete(q) => {
+                self.get_table(&q.table)?;
+                check_staged_returning(&q.returning)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /**
+    * Run a query with the query timeout, if there is one
+    */
+    fn execute(&mut self, query: asl::Query) -> Result<String, QueryError> {
+        // Every other query may read the tables, so it has to see the buffered INSERTs
+        match &query {
+            asl::Query::Insert(insert) if insert.on_conflict.is_none() => {},
+            _ => self.flush_insert_buffers()?,
+        }
+        self.deadline.set(self.config.query_timeout.map(|timeout| Instant::now() + timeout));
+        let result = self.execute_query(query);
+        self.deadline.set(None);
+        result
+    }
+
+    /**
+    * Run a query that isn't a transaction control statement
+    */
+    fn execute_query(&mut self, query: asl::Query) -> Result<String, QueryError> {
         match query {
             asl::Query::Select(q) => self.run_select(q),
+            asl::Query::ExplainAnalyze(q) => self.run_explain_analyze(q),
             asl::Query::Insert(q) => self.run_insert(q),
+            asl::Query::InsertSelect(q) => self.run_insert_select(q),
+            asl::Query::Copy(q) => self.run_copy(q),
+            asl::Query::Update(q) => self.run_update(q),
+            asl::Query::Delete(q) => self.run_delete(q),
             asl::Query::CreateTable(q) => self.run_create_table(q),
+            asl::Query::CreateTableLike(q) => self.run_create_table_like(q),
             asl::Query::DropTable(q) => self.run_drop_table(q),
+            asl::Query::CreateSequence(q) => self.run_create_sequence(q),
+            asl::Query::DropSequence(q) => self.run_drop_sequence(q),
+            asl::Query::AlterColumnType(q) => self.run_alter_column_type(q),
+            asl::Query::DropDatabase => self.run_drop_database(),
+            asl::Query::Describe(q) => self.run_describe(q),
+            asl::Query::CheckTable(q) => self.run_check_table(q),
+            asl::Query::CheckConsistency => self.run_check_consistency(),
+            asl::Query::RepairTable(q) => self.run_repair_table(q),
+            asl::Query::ShowConfig => self.run_show_config(),
+            asl::Query::ShowTableStatus(q) => self.run_show_table_status(q),
+            asl::Query::Begin => self.run_begin(),
+            asl::Query::Commit => self.run_commit(),
+            asl::Query::Rollback => self.run_rollback(),
+            asl::Query::Savepoint(q) => {
+                self.get_transaction()?.savepoint(&q.name);
+                Ok(format!("SAVEPOINT {}", q.name))
+            },
+            asl::Query::RollbackToSavepoint(q) => {
+                let discarded = self.get_transaction()?.rollback_to_savepoint(&q.name)?;
+                Ok(format!("ROLLBACK TO SAVEPOINT {}: discarded {} statements", q.name, discarded))
+            },
+            asl::Query::ReleaseSavepoint(q) => {
+                self.get_transaction()?.release_savepoint(&q.name)?;
+                Ok(format!("RELEASE SAVEPOINT {}", q.name))
+            },
         }
     }
-}
\ No newline at end of file
+
+    /**
+    Parse and run query. Inside a transaction, data modifications are staged until COMMIT. With
+    autocommit disabled, the first data modification outside a transaction begins one
+    */
+    pub fn run_query(&mut self, query: &str) -> Result<String, QueryError> {
+        self.warnings.borrow_mut().clear();
+        let mut query = crate::parse(query)?;
+        // NOW() and CURRENT_DATE take a single value for the whole statement, even when it's staged
+        // in a transaction
+        query.resolve_current_time(temporal::now_micros());
+        match &mut query {
+            asl::Query::Select(select) | asl::Query::ExplainAnalyze(select) => self.resolve_select_columns(select)?,
+            asl::Query::InsertSelect(insert_select) => self.resolve_select_columns(&mut insert_select.select)?,
+            _ => (),
+        }
+        if self.read_only && (query.modifies_data() || query.modifies_schema()) {
+            return Err(QueryError::ValidationError(String::from("database is read-only")))
+        }
+        self.resolve_next_values(&mut query)?;
+        if self.transaction.is_none() && !self.autocommit && query.modifies_data() {
+            self.transaction = Some(Transaction::new());
+        }
+        if self.transaction.is_some() {
+            if query.modifies_schema() {
+                return Err(QueryError::ValidationError(
+                    String::from("Schema changes are not allowed inside a transaction")))
+            }
+            if query.modifies_data() {
+                self.validate_staged_query(&query)?;
+                let transaction = self.get_transaction()?;
+                transaction.stage(query);
+                return Ok(format!("Staged statement {}", transaction.get_staged_count()))
+            }
+        }
+        self.execute(query)
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if self.closed {
+            return
+        }
+        if let Err(error) = self.flush() {
+            eprintln!("Warning: couldn't flush the database while dropping it: {:?}", error);
+            if self.get_buffered_rows() > 0 {
+                eprintln!("Warning: {} inserted rows couldn't be written and were lost", self.get_buffered_rows());
+            }
+        }
+    }
+}
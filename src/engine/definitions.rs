@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bincode;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use crate::engine::asl;
+use crate::engine::constraints::TableConstraint;
+use crate::engine::db::DatabaseDefinition;
+use crate::engine::errors::{PagingError, QueryError};
+
+// Prefix of versioned definition files. Files written before versioning start directly with the
+// serialized definition
+const DEFINITIONS_MAGIC: &[u8; 4] = b"CSDF";
+// Key of the version in definition files written as JSON
+const JSON_VERSION_KEY: &str = "version";
+// Version 1 added comments to tables and columns, version 2 added table constraints, version 3
+// added column defaults, version 4 added a checksum of the serialized definition to the header,
+// version 5 added column ids and version 6 added sequences
+pub const DEFINITIONS_VERSION: u32 = 6;
+// First version whose header has a checksum after the version
+const CHECKSUM_VERSION: u32 = 4;
+
+/**
+* Definition layout before versioning, when tables and columns had no comments
+*/
+#[derive(Deserialize)]
+struct DatabaseDefinitionV0 {
+    tables: HashMap<String, TableV0>,
+}
+
+#[derive(Deserialize)]
+struct TableV0 {
+    name: String,
+    columns: Vec<ColumnV0>,
+}
+
+#[derive(Deserialize)]
+struct ColumnV0 {
+    name: String,
+    column_type: asl::Type,
+}
+
+impl DatabaseDefinitionV0 {
+    /**
+    * Lowercase the names of the tables and columns. Files written before versioning may have been
+    * written before unquoted identifiers were folded to lowercase, and their mixed case names could
+    * only be reached with quoted identifiers. Names that only differ in case can't be told apart
+    * anymore, so they are rejected
+    */
+    fn fold_names(self) -> Result<DatabaseDefinitionV0, QueryError> {
+        let mut tables = HashMap::new();
+        for table in self.tables.into_values() {
+            let name = table.name.to_lowercase();
+            let mut column_names = HashSet::new();
+            let mut columns = Vec::new();
+            for column in table.columns {
+                let column_name = column.name.to_lowercase();
+                if !column_names.insert(column_name.clone()) {
+                    return Err(corrupted(format!(
+                        "The table {} has more than one column named {} once lowercased", table.name, column_name)))
+                }
+                columns.push(ColumnV0 { name: column_name, column_type: column.column_type });
+            }
+            if tables.contains_key(&name) {
+                return Err(corrupted(format!("More than one table is named {} once lowercased", name)))
+            }
+            tables.insert(name.clone(), TableV0 { name, columns });
+        }
+        Ok(DatabaseDefinitionV0 { tables })
+    }
+
+    fn migrate(self) -> DatabaseDefinitionV1 {
+        DatabaseDefinitionV1 {
+            tables: self.tables.into_iter()
+                .map(|(name, table)| (name, TableV1 {
+                    name: table.name,
+                    columns: table.columns.into_iter()
+                        .map(|column| ColumnV1 { name: column.name, column_type: column.column_type, comment: None })
+                        .collect(),
+                    comment: None,
+                }))
+                .collect()
+        }
+    }
+}
+
+/**
+* Definition layout of version 1, before table constraints
+*/
+#[derive(Deserialize)]
+struct DatabaseDefinitionV1 {
+    tables: HashMap<String, TableV1>,
+}
+
+#[derive(Deserialize)]
+struct TableV1 {
+    name: String,
+    columns: Vec<ColumnV1>,
+    comment: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ColumnV1 {
+    name: String,
+    column_type: asl::Type,
+    comment: Option<String>,
+}
+
+impl DatabaseDefinitionV1 {
+    fn migrate(self) -> DatabaseDefinitionV2 {
+        DatabaseDefinitionV2 {
+            tables: self.tables.into_iter()
+                .map(|(name, table)| (name, TableV2 {
+                    name: table.name,
+                    columns: table.columns,
+                    comment: table.comment,
+                    constraints: Vec::new(),
+                }))
+                .collect()
+        }
+    }
+}
+
+/**
+* Definition layout of version 2, before column defaults
+*/
+#[derive(Deserialize)]
+struct DatabaseDefinitionV2 {
+    tables: HashMap<String, TableV2>,
+}
+
+#[derive(Deserialize)]
+struct TableV2 {
+    name: String,
+    columns: Vec<ColumnV1>,
+    comment: Option<String>,
+    constraints: Vec<TableConstraint>,
+}
+
+impl DatabaseDefinitionV2 {
+    fn migrate(self) -> DatabaseDefinitionV3 {
+        DatabaseDefinitionV3 {
+            tables: self.tables.into_iter()
+                .map(|(name, table)| (name, TableV3 {
+                    name: table.name,
+                    columns: table.columns.into_iter()
+                        .map(|column| ColumnV3 {
+                            name: column.name,
+                            column_type: column.column_type,
+                            comment: column.comment,
+                            default: None,
+                        })
+                        .collect(),
+                    comment: table.comment,
+                    constraints: table.constraints,
+                }))
+                .collect()
+        }
+    }
+}
+
+/**
+* Definition layout of versions 3 and 4, before column ids
+*/
+#[derive(Deserialize)]
+struct DatabaseDefinitionV3 {
+    tables: HashMap<String, TableV3>,
+}
+
+#[derive(Deserialize)]
+struct TableV3 {
+    name: String,
+    columns: Vec<ColumnV3>,
+    comment: Option<String>,
+    constraints: Vec<TableConstraint>,
+}
+
+#[derive(Deserialize)]
+struct ColumnV3 {
+    name: String,
+    column_type: asl::Type,
+    comment: Option<String>,
+    default: Option<asl::ColumnDefault>,
+}
+
+impl DatabaseDefinitionV3 {
+    /**
+    * Items were stored with their fields in column position order, so ids matching the positions
+    * keep them readable without rewriting the table files
+    */
+    fn migrate(self) -> DatabaseDefinition {
+        DatabaseDefinition::from_tables(self.tables.into_iter()
+            .map(|(name, table)| {
+                let mut columns: Vec<asl::Column> = table.columns.into_iter()
+                    .map(|column| asl::Column {
+                        id: 0,
+                        name: column.name,
+                        column_type: column.column_type,
+                        comment: column.comment,
+                        default: column.default,
+                    })
+                    .collect();
+                asl::Column::assign_ids(&mut columns);
+                (name, asl::Table { name: table.name, columns, comment: table.comment, constraints: table.constraints })
+            })
+            .collect())
+    }
+}
+
+/**
+* Definition layout of version 5, before sequences
+*/
+#[derive(Deserialize)]
+struct DatabaseDefinitionV5 {
+    tables: BTreeMap<String, asl::Table>,
+}
+
+impl DatabaseDefinitionV5 {
+    fn migrate(self) -> DatabaseDefinition {
+        DatabaseDefinition::from_tables(self.tables)
+    }
+}
+
+/**
+* Check that no two columns of a table share an id, as their fields couldn't be told apart. Only
+* definitions edited by hand can have them
+*/
+fn check_column_ids(db_definition: DatabaseDefinition) -> Result<DatabaseDefinition, QueryError> {
+    for table in db_definition.get_tables() {
+        let mut ids = HashSet::new();
+        if let Some(column) = table.columns.iter().find(|column| !ids.insert(column.id)) {
+            return Err(corrupted(format!("The column id {} is used twice in {}", column.id, table.name)))
+        }
+    }
+    Ok(db_definition)
+}
+
+fn corrupted(error: impl std::fmt::Display) -> QueryError {
+    QueryError::from(PagingError::CorruptedData(format!("Definition file: {}", error)))
+}
+
+/**
+* Source of the serialized definition, so every layout can be read from both formats
+*/
+enum Serialized<'a> {
+    Bincode(&'a [u8]),
+    Json(serde_json::Value),
+}
+
+impl<'a> Serialized<'a> {
+    fn deserialize<T: DeserializeOwned>(self) -> Result<T, QueryError> {
+        match self {
+            Serialized::Bincode(bytes) => bincode::deserialize(bytes).map_err(corrupted),
+            Serialized::Json(value) => serde_json::from_value(value).map_err(corrupted),
+        }
+    }
+
+    /**
+    * Deserialize the layout of the given version, migrating it to the current one
+    */
+    fn migrate(self, version: u32) -> Result<DatabaseDefinition, QueryError> {
+        match version {
+            1 => Ok(self.deserialize::<DatabaseDefinitionV1>()?.migrate().migrate().migrate()),
+            2 => Ok(self.deserialize::<DatabaseDefinitionV2>()?.migrate().migrate()),
+            // Version 4 only changed the header
+            3 | 4 => Ok(self.deserialize::<DatabaseDefinitionV3>()?.migrate()),
+            5 => check_column_ids(self.deserialize::<DatabaseDefinitionV5>()?.migrate()),
+            DEFINITIONS_VERSION => check_column_ids(self.deserialize()?),
+            version => Err(QueryError::ValidationError(
+                format!("Unsupported definition file version {}, the newest supported is {}", version, DEFINITIONS_VERSION)))
+        }
+    }
+}
+
+/**
+* Reject definition files written by a newer version. Their header and layout may have changed in
+* any way, so this is checked before reading anything past the version
+*/
+fn check_version(version: u32) -> Result<(), QueryError> {
+    if version > DEFINITIONS_VERSION {
+        return Err(QueryError::ValidationError(format!(
+            "The database version is too new: the definition file has version {}, but the newest this \
+             build can read is {}. Upgrade csbase to open it", version, DEFINITIONS_VERSION)))
+    }
+    Ok(())
+}
+
+/**
+* CRC-32 (IEEE) of the bytes, to detect definition files that were partially written or modified
+*/
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut u32_bytes = [0u8; 4];
+    u32_bytes.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_be_bytes(u32_bytes)
+}
+
+/**
+* Serialize the definition after a header with the magic prefix, the current version and the checksum
+* of the serialized definition
+*/
+pub fn encode_definitions(db_definition: &DatabaseDefinition) -> Result<Vec<u8>, QueryError> {
+    let serialized = match bincode::serialize(db_definition) {
+        Ok(serialized) => serialized,
+        Err(error) => return Err(QueryError::ValidationError(format!("Can't serialize definitions: {}", error))),
+    };
+    let mut bytes = DEFINITIONS_MAGIC.to_vec();
+    bytes.extend_from_slice(&DEFINITIONS_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&checksum(&serialized).to_be_bytes());
+    bytes.extend(serialized);
+    Ok(bytes)
+}
+
+/**
+* Serialize the definition as pretty printed JSON, with the current version next to the tables, so it
+* can be read and edited by hand
+*/
+pub fn encode_definitions_json(db_definition: &DatabaseDefinition) -> Result<Vec<u8>, QueryError> {
+    let serialize_error = |error: serde_json::Error| QueryError::ValidationError(
+        format!("Can't serialize definitions: {}", error));
+    let mut value = serde_json::to_value(db_definition).map_err(serialize_error)?;
+    if let serde_json::Value::Object(fields) = &mut value {
+        fields.insert(String::from(JSON_VERSION_KEY), serde_json::Value::from(DEFINITIONS_VERSION));
+    }
+    let mut bytes = serde_json::to_vec_pretty(&value).map_err(serialize_error)?;
+    bytes.push(b'\n');
+    Ok(bytes)
+}
+
+/**
+* Deserialize a definition file written by any version and in either format, migrating older layouts
+* to the current one. JSON files start with an object, while binary files start with the magic
+* prefix, or with the table count when they were written before versioning
+*/
+pub fn decode_definitions(bytes: &[u8]) -> Result<DatabaseDefinition, QueryError> {
+    if bytes.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{') {
+        return decode_definitions_json(bytes)
+    }
+    if !bytes.starts_with(DEFINITIONS_MAGIC) {
+        let db_definition: DatabaseDefinitionV0 = Serialized::Bincode(bytes).deserialize()?;
+        return Ok(db_definition.fold_names()?.migrate().migrate().migrate().migrate())
+    }
+    let mut header_length = DEFINITIONS_MAGIC.len() + 4;
+    if bytes.len() < header_length {
+        return Err(corrupted("truncated header"))
+    }
+    let version = read_u32(bytes, DEFINITIONS_MAGIC.len());
+    check_version(version)?;
+    if version >= CHECKSUM_VERSION {
+        if bytes.len() < header_length + 4 {
+            return Err(corrupted("truncated header"))
+        }
+        let stored_checksum = read_u32(bytes, header_length);
+        header_length += 4;
+        let computed_checksum = checksum(&bytes[header_length..]);
+        if stored_checksum != computed_checksum {
+            return Err(corrupted(format!(
+                "checksum mismatch, the file stores {:08x} but its contents have {:08x}. It may have been \
+                 partially written", stored_checksum, computed_checksum)))
+        }
+    }
+    Serialized::Bincode(&bytes[header_length..]).migrate(version)
+}
+
+/**
+* Test if the definition file was written before versioning. Its table names are lowercased when
+* it's decoded, so the files of the tables may have to be renamed to match
+*/
+pub fn is_unversioned(bytes: &[u8]) -> bool {
+    bytes.iter().find(|byte| !byte.is_ascii_whitespace()) != Some(&b'{') && !bytes.starts_with(DEFINITIONS_MAGIC)
+}
+
+fn decode_definitions_json(bytes: &[u8]) -> Result<DatabaseDefinition, QueryError> {
+    let value: serde_json::Value = serde_json::from_slice(bytes).map_err(corrupted)?;
+    let version = match value.get(JSON_VERSION_KEY).and_then(|version| version.as_u64()) {
+        Some(version) if version <= u32::max_value() as u64 => version as u32,
+        _ => return Err(corrupted(format!("missing or invalid \"{}\"", JSON_VERSION_KEY))),
+    };
+    check_version(version)?;
+    Serialized::Json(value).migrate(version)
+}
@@ -14,6 +14,10 @@ pub enum QueryError  {
     Conflict(String),
     PagingError(String),
     ValidationError(String),
+    // The query ran for longer than the configured timeout
+    Timeout(String),
+    // Rows of INSERTs that already succeeded couldn't be written before the database was closed
+    LostRows(String),
 }
 
 impl fmt::Display for QueryError {
@@ -34,9 +38,28 @@ impl std::convert::From<PagingError> for QueryError {
     fn from(error: PagingError) -> Self {QueryError::PagingError(format!("{:?}", error))}
 }
 
+/**
+* Error of a batch insert. The records before written_records were stored before it failed, so only
+* the rest of the batch has to be written again
+*/
+#[derive(Debug)]
+pub struct InsertError {
+    pub written_records: usize,
+    pub error: QueryError,
+}
+
+impl std::convert::From<InsertError> for QueryError {
+    fn from(error: InsertError) -> Self {error.error}
+}
+
 #[derive(Debug)]
 pub enum SystemError {
     IOError(std::io::Error),
+    // A table of the definition can't be read from storage, found while starting the database
+    InvalidTable(String),
+    InvalidConfig(String),
+    // The definitions file exists but can't be decoded, e.g. because its checksum doesn't match
+    InvalidDefinitions(String),
 }
 
 impl fmt::Display for SystemError {
@@ -54,6 +77,7 @@ impl std::convert::From<std::io::Error> for SystemError {
 pub enum PagingError {
     NotEnoughSpace,
     InvalidSliceLength,
+    CorruptedData(String),
 }
 
 impl fmt::Display for PagingError {
@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+
+use crate::engine::asl;
+use crate::engine::errors::QueryError;
+use crate::engine::fs::evaluate_for_record;
+use crate::engine::pages::RowId;
+use crate::engine::rows::{Aggregation, Projection};
+use crate::engine::sort::ExternalSorter;
+
+pub const STAGE_COLUMNS: [&str; 4] = ["stage", "rows", "pages", "time_ms"];
+
+/**
+* Counters of a stage of a query run by EXPLAIN ANALYZE
+*/
+#[derive(Debug, Clone)]
+pub struct StageStats {
+    pub name: String,
+    // Rows that came out of the stage
+    pub rows: usize,
+    // Pages read from storage, only for scans of stored tables
+    pub pages: Option<usize>,
+    pub elapsed: Duration,
+}
+
+impl StageStats {
+    pub fn new(name: String) -> StageStats {
+        StageStats { name, rows: 0, pages: None, elapsed: Duration::default() }
+    }
+
+    /**
+    * Run f, adding the time it takes to the stage
+    */
+    pub fn time<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.elapsed += start.elapsed();
+        result
+    }
+
+    fn to_record(&self) -> asl::Record {
+        asl::Record { values: vec![
+            asl::Value::Str(self.name.clone()),
+            asl::Value::Int(self.rows as i32),
+            self.pages.map_or(asl::Value::Null, |pages| asl::Value::Int(pages as i32)),
+            asl::Value::Str(format!("{:.3}", self.elapsed.as_secs_f64() * 1000.0)),
+        ]}
+    }
+}
+
+/**
+* Build the rows of the EXPLAIN ANALYZE output, one per stage in execution order
+*/
+pub fn get_stage_records(stages: &[StageStats]) -> Vec<asl::Record> {
+    stages.iter().map(StageStats::to_record).collect()
+}
+
+/**
+* Instrumented run of a SELECT over a table: the records read by the scan go through the filter and
+* then the projection or the aggregation, and the sort of ORDER BY if there is one, counting the rows
+* each stage lets through and the time spent in it. The result rows are discarded
+*/
+pub struct SelectAnalysis<'a> {
+    table: &'a asl::Table,
+    condition: &'a Option<Box<asl::Expression>>,
+    projection: &'a Projection,
+    aggregation: Option<Aggregation<'a>>,
+    scan: StageStats,
+    filter: Option<StageStats>,
+    output: StageStats,
+    sort: Option<(ExternalSorter, StageStats)>,
+    start: Instant,
+}
+
+impl<'a> SelectAnalysis<'a> {
+    pub fn new(table: &'a asl::Table, condition: &'a Option<Box<asl::Expression>>,
+               projection: &'a Projection, sorter: Option<ExternalSorter>) -> SelectAnalysis<'a> {
+        let is_aggregate = projection.is_aggregate();
+        SelectAnalysis {
+            table,
+            condition,
+            projection,
+            aggregation: if is_aggregate { Some(projection.aggregate(table)) } else { None },
+            scan: StageStats::new(format!("Scan {}", table.name)),
+            filter: condition.as_ref().map(|condition| StageStats::new(format!("Filter {}", condition))),
+            output: StageStats::new(String::from(if is_aggregate { "Aggregate" } else { "Project" })),
+            sort: sorter.map(|sorter| (sorter, StageStats::new(String::from("Sort")))),
+            start: Instant::now(),
+        }
+    }
+
+    /**
+    * Pass a record read by the scan through the next stages
+    */
+    pub fn add(&mut self, row_id: RowId, record: asl::Record) -> Result<(), QueryError> {
+        self.scan.rows += 1;
+        if let Some(filter) = &mut self.filter {
+            let (table, condition) = (self.table, self.condition);
+            if !filter.time(|| evaluate_for_record(table, &record, condition))? {
+                return Ok(())
+            }
+            filter.rows += 1;
+        }
+        let (table, projection) = (self.table, self.projection);
+        match &mut self.aggregation {
+            Some(aggregation) => self.output.time(|| aggregation.add(&record))?,
+            None => {
+                let output = self.output.time(|| projection.project(table, row_id, &record))?;
+                self.output.rows += 1;
+                if let Some((sorter, sort)) = &mut self.sort {
+                    sort.time(|| {
+                        let keys = projection.get_sort_keys(table, row_id, &record, &output)?;
+                        sorter.add(keys, output)
+                    })?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /**
+    * Finish the aggregation, if any, and get the stages. The scan time is what's left of the total
+    * after the other stages, as reading the records is interleaved with them
+    */
+    pub fn finish(mut self, pages: Option<usize>) -> Result<Vec<StageStats>, QueryError> {
+        if let Some(aggregation) = self.aggregation.take() {
+            self.output.rows = self.output.time(|| aggregation.finish())?.len();
+        }
+        let sort = match self.sort.take() {
+            Some((sorter, mut sort)) => {
+                let spilled_runs = sorter.get_spilled_runs();
+                sort.rows = sort.time(|| -> Result<usize, QueryError> {
+                    let mut rows = 0;
+                    for record in sorter.finish()? {
+                        record?;
+                        rows += 1;
+                    }
+                    Ok(rows)
+                })?;
+                if spilled_runs > 0 {
+                    sort.name = format!("Sort ({} runs spilled)", spilled_runs);
+                }
+                Some(sort)
+            },
+            None => None,
+        };
+        let other_stages = self.output.elapsed
+            + self.filter.as_ref().map_or(Duration::default(), |filter| filter.elapsed)
+            + sort.as_ref().map_or(Duration::default(), |sort| sort.elapsed);
+        self.scan.elapsed = self.start.elapsed().checked_sub(other_stages).unwrap_or_default();
+        self.scan.pages = pages;
+        let mut stages = vec![self.scan];
+        stages.extend(self.filter);
+        stages.push(self.output);
+        stages.extend(sort);
+        Ok(stages)
+    }
+}
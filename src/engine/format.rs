@@ -0,0 +1,118 @@
+use crate::engine::asl;
+
+pub const DEFAULT_NULL_DISPLAY: &str = "NULL";
+pub const DEFAULT_MAX_ROWS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Csv,
+}
+
+/**
+* How query results are displayed. The settings only last for the session: they aren't stored with the
+* database, so every database that is opened starts from the defaults
+*/
+#[derive(Debug, Clone)]
+pub struct FormatSettings {
+    pub format: OutputFormat,
+    // Shown in place of NULL values, and read back as NULL by COPY FROM
+    pub null_display: String,
+    // Maximum number of rows kept in a SELECT result, None for no limit
+    pub max_rows: Option<usize>,
+    // Decimal places shown for Float values, None for the shortest representation that reads back as
+    // the same value
+    pub float_precision: Option<usize>,
+    // Show output longer than the terminal a screenful at a time in the REPL. It only applies when
+    // the REPL runs in a terminal, so piped output is never paged
+    pub pager: bool,
+}
+
+impl Default for FormatSettings {
+    fn default() -> FormatSettings {
+        FormatSettings {
+            format: OutputFormat::Table,
+            null_display: String::from(DEFAULT_NULL_DISPLAY),
+            max_rows: Some(DEFAULT_MAX_ROWS),
+            float_precision: None,
+            pager: true,
+        }
+    }
+}
+
+/**
+* Render a single value for output, replacing nulls with the configured null display string and
+* rounding floats to the configured precision
+*/
+fn display_value(value: &asl::Value, settings: &FormatSettings) -> String {
+    match (value, settings.float_precision) {
+        (asl::Value::Null, _) => settings.null_display.clone(),
+        (asl::Value::Float(value), Some(precision)) => format!("{:.*}", precision, value),
+        (value, _) => value.to_string(),
+    }
+}
+
+/**
+* Format the records using the output format in the settings
+*/
+pub fn format_records(columns: &[String], records: &[asl::Record], settings: &FormatSettings) -> String {
+    match settings.format {
+        OutputFormat::Table => format_table(columns, records, settings),
+        OutputFormat::Csv => format_csv(columns, records, settings),
+    }
+}
+
+/**
+* Format the records as an aligned table, followed by the row count
+*/
+pub fn format_table(columns: &[String], records: &[asl::Record], settings: &FormatSettings) -> String {
+    let rows: Vec<Vec<String>> = records.iter()
+        .map(|record| record.values.iter().map(|value| display_value(value, settings)).collect())
+        .collect();
+    let mut widths: Vec<usize> = columns.iter().map(|column| column.chars().count()).collect();
+    for row in &rows {
+        for (idx, field) in row.iter().enumerate() {
+            widths[idx] = widths[idx].max(field.chars().count());
+        }
+    }
+    let format_row = |fields: &Vec<String>| -> String {
+        fields.iter().enumerate()
+            .map(|(idx, field)| format!(" {}{} ", field, " ".repeat(widths[idx] - field.chars().count())))
+            .collect::<Vec<String>>()
+            .join("|")
+    };
+    let mut lines = vec![
+        format_row(&columns.to_vec()),
+        widths.iter().map(|width| "-".repeat(width + 2)).collect::<Vec<String>>().join("+"),
+    ];
+    lines.extend(rows.iter().map(format_row));
+    lines.push(format!("({} {})", rows.len(), if rows.len() == 1 { "row" } else { "rows" }));
+    lines.join("\n")
+}
+
+/**
+* Quote a CSV field if it contains a separator, a quote or a line break
+*/
+fn escape_csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/**
+* Format the records as CSV, with a header line containing the column names
+*/
+pub fn format_csv(columns: &[String], records: &[asl::Record], settings: &FormatSettings) -> String {
+    let mut lines = vec![
+        columns.iter().map(|column| escape_csv_field(column)).collect::<Vec<String>>().join(",")
+    ];
+    for record in records {
+        lines.push(record.values.iter()
+            .map(|value| escape_csv_field(&display_value(value, settings)))
+            .collect::<Vec<String>>()
+            .join(","));
+    }
+    lines.join("\n")
+}
@@ -1,25 +1,228 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
-use bincode;
 use shellexpand;
 
 use crate::engine::asl;
 use crate::engine::db;
-use crate::engine::errors::QueryError;
-use crate::config::config;
+use crate::engine::errors::{InsertError, QueryError, SystemError};
+use crate::engine::integrity::{check_page, IntegrityProblem, RepairReport};
+use crate::config::config::Config;
 use crate::engine::db::DatabaseDefinition;
-use crate::engine::pages::{ Item, Page, PAGE_SIZE };
+use crate::engine::definitions::{decode_definitions, encode_definitions, encode_definitions_json, is_unversioned};
+use crate::engine::pages::{ Item, Page, RowId, PAGE_SIZE };
+use crate::engine::storage::{PageChanges, ScanControl, StorageEngine};
+
+// Suffix of the name of the file that stores the pages of a table
+const TABLE_DATA_SUFFIX: &str = "_data.csbase";
+
+/**
+* Evaluate the condition against a single record, so that filtering happens while pages are being
+* scanned instead of after all records are loaded. The condition must evaluate to a Bool, e.g. a
+* comparison or a boolean column, and records where it's Null don't match
+*/
+pub fn evaluate_for_record(table: &asl::Table, record: &asl::Record,
+                           condition: &Option<Box<asl::Expression>>) -> Result<bool, QueryError> {
+    match condition {
+        Some(condition) => {
+            let identifier_values = get_identifier_values(table, record);
+            get_condition_result(condition.evaluate(Option::Some(&identifier_values))?)
+        }
+        None => Ok(true)
+    }
+}
+
+fn get_condition_result(value: asl::Value) -> Result<bool, QueryError> {
+    match value {
+        asl::Value::Bool(value) => Ok(value),
+        asl::Value::Null => Ok(false),
+        value => Err(QueryError::ValidationError(
+            format!("The condition must be a Bool value, but it's {:?}", value.get_type())))
+    }
+}
+
+/**
+* Evaluate once a condition that doesn't reference any column, e.g. WHERE 1 = 0, as every record
+* gets the same result. None when it references columns, so it has to be evaluated for each record
+*/
+pub fn evaluate_constant_condition(condition: &Option<Box<asl::Expression>>) -> Result<Option<bool>, QueryError> {
+    match condition {
+        Some(condition) if condition.get_identifiers().is_empty() => get_condition_result(condition.evaluate(None)?).map(Some),
+        Some(_) => Ok(None),
+        None => Ok(Some(true)),
+    }
+}
+
+/**
+* Map each column name of the table to its value in the record, to evaluate expressions on it
+*/
+pub fn get_identifier_values(table: &asl::Table, record: &asl::Record) -> HashMap<String, asl::Value> {
+    record.values.iter().enumerate()
+        .map(|(idx, value)| (table.columns[idx].name.clone(), value.clone()))
+        .collect()
+}
+
+/**
+* Fill the page buffer from the file, retrying short reads. Returns the number of bytes read, which is
+* smaller than PAGE_SIZE only at the end of the file
+*/
+fn read_page(file: &mut fs::File, page_buffer: &mut [u8; PAGE_SIZE]) -> std::io::Result<usize> {
+    let mut bytes_read = 0;
+    while bytes_read < PAGE_SIZE {
+        match file.read(&mut page_buffer[bytes_read..])? {
+            0 => break,
+            count => bytes_read += count,
+        }
+    }
+    Ok(bytes_read)
+}
+
+/**
+* The contents of a table file with the pages
+*/
+fn pages_to_bytes(pages: &[Page]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(pages.len() * PAGE_SIZE);
+    for page in pages {
+        bytes.extend_from_slice(&page.to_bytes());
+    }
+    bytes
+}
+
+/**
+* Iterator over the pages of a table file, reading one page at a time. A truncated page at the end
+* of the file is yielded as a PagingError, after which the iteration ends
+*/
+pub struct PageReader {
+    file: fs::File,
+    table_name: String,
+    page_buffer: [u8; PAGE_SIZE],
+    next_page_id: u32,
+    finished: bool,
+}
+
+impl Iterator for PageReader {
+    type Item = Result<Page, QueryError>;
+
+    fn next(&mut self) -> Option<Result<Page, QueryError>> {
+        if self.finished {
+            return None
+        }
+        let page_id = self.next_page_id;
+        self.next_page_id += 1;
+        match read_page(&mut self.file, &mut self.page_buffer) {
+            Ok(PAGE_SIZE) => Some(Ok(Page::from_bytes(&self.page_buffer))),
+            Ok(0) => {
+                self.finished = true;
+                None
+            },
+            Ok(bytes_read) => {
+                self.finished = true;
+                Some(Err(QueryError::PagingError(format!(
+                    "Truncated page {} of table {} with {} bytes", page_id, self.table_name, bytes_read))))
+            },
+            Err(error) => {
+                self.finished = true;
+                Some(Err(error.into()))
+            },
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct DBFileSystem {
     base_path: String,
+    definitions_file: String,
+    // When set, files are only opened for reading and every write fails
+    read_only: bool,
+    // When set, every write is synced to disk before returning
+    durable: bool,
+    json_definitions: bool,
+    // Percentage of each page filled by appended items
+    fill_factor: u8,
 }
 
 impl DBFileSystem {
     pub fn new() -> DBFileSystem {
-        DBFileSystem { base_path: shellexpand::tilde(&config::DB_PATH).to_string() }
+        DBFileSystem::with_config(&Config::default())
+    }
+
+    /**
+    * Open the database files without ever writing to them
+    */
+    pub fn read_only() -> DBFileSystem {
+        DBFileSystem { read_only: true, ..DBFileSystem::new() }
+    }
+
+    /**
+    * Use the files at the path and with the names of the config
+    */
+    pub fn with_config(config: &Config) -> DBFileSystem {
+        DBFileSystem {
+            base_path: shellexpand::tilde(&config.db_path).to_string(),
+            definitions_file: config.definitions_file.clone(),
+            read_only: config.read_only,
+            durable: config.durable,
+            json_definitions: config.json_definitions,
+            fill_factor: config.fill_factor,
+        }
+    }
+
+    /**
+    * Sync the written file to disk when the storage is durable
+    */
+    fn sync(&self, file: &fs::File) -> Result<(), QueryError> {
+        if self.durable {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Replace the file at the path with the bytes. They are written to a temporary file next to it that
+    * is then renamed over it, so a crash while writing leaves either the old or the new contents,
+    * never a mix of both. When the storage is durable, the directory is synced too so the rename
+    * survives a crash
+    */
+    pub fn atomic_write(&self, path: &str, bytes: &[u8]) -> Result<(), QueryError> {
+        self.check_writable()?;
+        let temp_path = self.write_temp_file(path, bytes)?;
+        self.rename_temp_files(&[(temp_path, String::from(path))])
+    }
+
+    /**
+    * Write the bytes to the temporary file that replaces the file at the path, returning its path
+    */
+    fn write_temp_file(&self, path: &str, bytes: &[u8]) -> Result<String, QueryError> {
+        let temp_path = format!("{}.tmp", path);
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(bytes)?;
+        self.sync(&file)?;
+        Ok(temp_path)
+    }
+
+    /**
+    * Rename each temporary file over the file it replaces, syncing its directory when the storage is
+    * durable
+    */
+    fn rename_temp_files(&self, renames: &[(String, String)]) -> Result<(), QueryError> {
+        for (temp_path, path) in renames {
+            fs::rename(temp_path, path)?;
+            if self.durable {
+                if let Some(directory) = Path::new(path).parent() {
+                    fs::File::open(directory)?.sync_all()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), QueryError> {
+        if self.read_only {
+            return Err(QueryError::ValidationError(String::from("database is read-only")))
+        }
+        Ok(())
     }
 
     fn get_path(&self, path: &str) -> String {
@@ -27,34 +230,168 @@ impl DBFileSystem {
     }
 
     fn get_table_data_path(&self, table: &asl::Table) -> String {
-        self.get_path(&format!("{}_data.csbase", table.name))
+        self.get_path(&format!("{}{}", table.name, TABLE_DATA_SUFFIX))
     }
 
     /**
-    * Read and deserialize the database definition file
+    * Rename the data files of tables whose names were lowercased when their definitions were
+    * migrated, so they match the new names. A read-only database can't rename them, so those tables
+    * are reported as missing
     */
-    pub fn load_definitions(&self) -> Result<db::DatabaseDefinition, QueryError> {
-        let mut file = fs::File::open(self.get_path(config::TABLE_DEFINITIONS_FILE))?;
+    fn rename_legacy_table_files(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        if self.read_only {
+            return Ok(())
+        }
+        let file_table_names = self.list_table_files()?;
+        for table in db_definition.get_tables() {
+            if Path::new(&self.get_table_data_path(table)).exists() {
+                continue
+            }
+            if let Some(file_table_name) = file_table_names.iter().find(|name| name.to_lowercase() == table.name) {
+                fs::rename(self.get_path(&format!("{}{}", file_table_name, TABLE_DATA_SUFFIX)),
+                           self.get_table_data_path(table))?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Ensure that the database path exists
+    */
+    pub fn ensure_base_path(&self) -> std::io::Result<()> {
+        fs::create_dir_all(&self.base_path)?;
+        Ok(())
+    }
+
+    /**
+    * Read the pages of the table file in order, for the features that scan the whole table
+    */
+    pub fn pages(&self, table: &asl::Table) -> Result<PageReader, QueryError> {
+        Ok(PageReader {
+            file: fs::File::open(self.get_table_data_path(table))?,
+            table_name: table.name.clone(),
+            page_buffer: [0; PAGE_SIZE],
+            next_page_id: 1,
+            finished: false,
+        })
+    }
+
+    fn get_page_offset(page_id: u32) -> u64 {
+        PAGE_SIZE as u64 * (page_id as u64 - 1)
+    }
+
+    /**
+    * Insert the items into the last page of the table file and the pages after it. The pages are
+    * filled in memory up to the fill factor and then each one is written once. A file that ends with
+    * a truncated page is rejected, as appending after it would misalign every later page. On failure
+    * the error counts the items of the pages written before it
+    */
+    fn append_items(&self, table: &asl::Table, items: &[Item]) -> Result<(), InsertError> {
+        let unwritten = |error: QueryError| InsertError { written_records: 0, error };
+        self.check_writable().map_err(unwritten)?;
+        if items.is_empty() {
+            return Ok(())
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(self.get_table_data_path(table))
+            .map_err(|error| unwritten(error.into()))?;
+        let file_length = file.metadata().map_err(|error| unwritten(error.into()))?.len();
+        let current_pages = file_length / PAGE_SIZE as u64;
+        if file_length % PAGE_SIZE as u64 != 0 {
+            return Err(unwritten(QueryError::PagingError(format!(
+                "Truncated page {} of table {} with {} bytes", current_pages + 1, table.name,
+                file_length % PAGE_SIZE as u64))))
+        }
+        let mut page = if current_pages > 0 {
+            let mut page_bytes = [0u8; PAGE_SIZE];
+            file.seek(SeekFrom::Start(PAGE_SIZE as u64 * (current_pages - 1)))
+                .and_then(|_| read_page(&mut file, &mut page_bytes))
+                .map_err(|error| unwritten(error.into()))?;
+            Page::from_bytes(&page_bytes)
+        } else {
+            Page::new(1)
+        };
+        let first_page_id = page.id;
+        // Each filled page along with the number of items stored once it's written
+        let mut pages = Vec::new();
+        for (idx, item) in items.iter().enumerate() {
+            if page.add_item_with_fill_factor(item, self.fill_factor).is_err() {
+                let mut new_page = Page::new(page.id + 1);
+                new_page.add_item(item).map_err(|error| unwritten(error.into()))?;
+                pages.push((std::mem::replace(&mut page, new_page), idx));
+            }
+        }
+        pages.push((page, items.len()));
+        file.seek(SeekFrom::Start(DBFileSystem::get_page_offset(first_page_id)))
+            .map_err(|error| unwritten(error.into()))?;
+        let mut written_records = 0;
+        for (page, stored_items) in pages {
+            file.write_all(&page.to_bytes()).map_err(|error| InsertError { written_records, error: error.into() })?;
+            written_records = stored_items;
+        }
+        // The pages are in the file even if they can't be synced, so writing them again would duplicate them
+        self.sync(&file).map_err(|error| InsertError { written_records, error })
+    }
+}
+
+impl StorageEngine for DBFileSystem {
+    fn initialize(&self) -> Result<(), SystemError> {
+        if !self.read_only {
+            self.ensure_base_path()?;
+        }
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        self.base_path.clone()
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        PathBuf::from(&self.base_path)
+    }
+
+    /**
+    * Read and deserialize the database definition file, migrating it if it was written by an older
+    * version
+    */
+    fn load_definitions(&self) -> Result<db::DatabaseDefinition, QueryError> {
+        let mut file = match fs::File::open(self.get_path(&self.definitions_file)) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound =>
+                return Err(QueryError::NotFound(String::from("Database definition"))),
+            Err(error) => return Err(QueryError::from(error)),
+        };
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
-        let db_definition: DatabaseDefinition = bincode::deserialize(buffer.as_slice()).unwrap();
-        print!("Loaded db definition: {:#?}", db_definition);
+        let db_definition = decode_definitions(&buffer)?;
+        if is_unversioned(&buffer) {
+            self.rename_legacy_table_files(&db_definition)?;
+        }
         Ok(db_definition)
     }
 
     /**
-    * Serialize and store the database definition
+    * Serialize and store the database definition, as JSON when the config asks for it. The file is
+    * replaced atomically, so a crash while writing leaves the previous definition intact
     */
-    pub fn store_definitions(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
-        let mut file = fs::File::create(self.get_path(config::TABLE_DEFINITIONS_FILE))?;
-        file.write_all(bincode::serialize(db_definition).unwrap().as_slice())?;
-        Ok(())
+    fn store_definitions(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        self.check_writable()?;
+        let bytes = if self.json_definitions {
+            encode_definitions_json(db_definition)?
+        } else {
+            encode_definitions(db_definition)?
+        };
+        self.atomic_write(&self.get_path(&self.definitions_file), &bytes)
     }
 
     /**
     * Create table files in the local filesystem
     */
-    pub fn create_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+    fn create_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        self.check_writable()?;
         fs::File::create(self.get_table_data_path(table))?;
         Ok(())
     }
@@ -62,86 +399,186 @@ impl DBFileSystem {
     /**
     * Delete table files from the local filesystem
     */
-    pub fn delete_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+    fn delete_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        self.check_writable()?;
         fs::remove_file(self.get_table_data_path(table))?;
         Ok(())
     }
 
     /**
-    * Ensure that the database path exists
+    * Remove the table data files and the definition file. Only the files named after the tables in
+    * the definition are removed, so other files in the base path survive
     */
-    pub fn ensure_base_path(&self) -> std::io::Result<()> {
-        fs::create_dir_all(&self.base_path)?;
+    fn delete_database(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        self.check_writable()?;
+        let mut paths: Vec<String> = db_definition.get_tables()
+            .map(|table| self.get_table_data_path(table))
+            .collect();
+        paths.push(self.get_path(&self.definitions_file));
+        for path in paths {
+            match fs::remove_file(&path) {
+                Ok(_) => (),
+                Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => (),
+                Err(error) => return Err(QueryError::from(error)),
+            }
+        }
         Ok(())
     }
 
     /**
-    * Insert a record into the last page of the table file
-    * Creates a new page if the current one is full
+    * Find the table data files in the base path by their suffix
+    */
+    fn list_table_files(&self) -> Result<Vec<String>, QueryError> {
+        let mut table_names = Vec::new();
+        for entry in fs::read_dir(&self.base_path)? {
+            let file_name = entry?.file_name();
+            if let Some(table_name) = file_name.to_str().and_then(|name| name.strip_suffix(TABLE_DATA_SUFFIX)) {
+                table_names.push(String::from(table_name));
+            }
+        }
+        Ok(table_names)
+    }
+
+    fn insert_record(&self, table: &asl::Table, record: &asl::Record) -> Result<(), QueryError> {
+        Ok(self.append_items(table, &[Item::from_record(table, record)])?)
+    }
+
+    fn insert_records(&self, table: &asl::Table, records: &[asl::Record]) -> Result<(), InsertError> {
+        let items: Vec<Item> = records.iter().map(|record| Item::from_record(table, record)).collect();
+        self.append_items(table, &items)
+    }
+
+    /**
+    * Rewrite in place only the pages of the table file that have changes
     */
-    pub fn insert_record(&self, table: &asl::Table, record: &asl::Record) -> Result<(), QueryError> {
+    fn apply_page_changes(&self, table: &asl::Table, page_changes: BTreeMap<u32, PageChanges>) -> Result<(), QueryError> {
+        self.check_writable()?;
         let mut file = fs::OpenOptions::new()
-            .create(true)
             .read(true)
             .write(true)
             .open(self.get_table_data_path(table))?;
-        let current_pages = file.metadata()?.len() / PAGE_SIZE as u64;
-        let item = Item::from_record(record);
-        let mut last_page_offset = if current_pages > 0 { PAGE_SIZE as u64 * (current_pages - 1) } else { 0 };
-        let mut last_page;
-        if current_pages > 0 {
-            file.seek(SeekFrom::Start(last_page_offset))?;
-            let mut page_bytes = [0u8; PAGE_SIZE];
-            file.read(&mut page_bytes)?;
-            last_page = Page::from_bytes(&page_bytes);
-            match last_page.add_item(&item) {
-                Ok(_) => (),
-                Err(_) => {
-                    let mut new_page = Page::new(last_page.id + 1);
-                    new_page.add_item(&item)?;
-                    last_page = new_page;
-                    last_page_offset += PAGE_SIZE as u64;
+        let mut page_buffer = [0u8; PAGE_SIZE];
+        let mut overflow_items = Vec::new();
+        for (page_id, changes) in page_changes {
+            let page_offset = DBFileSystem::get_page_offset(page_id);
+            file.seek(SeekFrom::Start(page_offset))?;
+            if read_page(&mut file, &mut page_buffer)? != PAGE_SIZE {
+                return Err(QueryError::NotFound(format!("Page {} of table {}", page_id, table.name)))
+            }
+            let (new_page, items) = Page::from_bytes(&page_buffer).apply_changes(&changes)?;
+            file.seek(SeekFrom::Start(page_offset))?;
+            file.write_all(&new_page.to_bytes())?;
+            overflow_items.extend(items);
+        }
+        self.sync(&file)?;
+        self.append_items(table, &overflow_items)?;
+        Ok(())
+    }
+
+    /**
+    * Call f with the row id of every record in the table file that matches the given condition
+    */
+    fn scan_records(&self, table: &asl::Table, condition: &Option<Box<asl::Expression>>,
+                    f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        for page in self.pages(table)? {
+            let page = page?;
+            for (slot, item) in page.items_iter()? {
+                let record = item?.to_record(table)?;
+                if evaluate_for_record(table, &record, condition)? {
+                    if f(RowId { page_id: page.id, slot }, record)? == ScanControl::Stop {
+                        return Ok(())
+                    }
                 }
             }
-        } else {
-            let mut new_page = Page::new(1);
-            new_page.add_item(&item)?;
-            last_page = new_page;
         }
-        file.seek(SeekFrom::Start(last_page_offset))?;
-        file.write(&last_page.to_bytes())?;
         Ok(())
     }
 
+    /**
+    * Write the pages to a new table file that atomically replaces the current one
+    */
+    fn replace_pages(&self, table: &asl::Table, pages: &[Page]) -> Result<(), QueryError> {
+        self.atomic_write(&self.get_table_data_path(table), &pages_to_bytes(pages))
+    }
 
     /**
-    * Find records in the table file that match the given condition
+    * Write the new file of every table before renaming any of them over the current ones, so a table
+    * that can't be written leaves all of them as they were. The renames themselves aren't atomic
+    * together, but they only fail if the directory itself can't be written
     */
-    pub fn select_records(&self, table: &asl::Table, columns: &Vec<String>,
-                          condition: &Option<Box<asl::Expression>>) -> Result<Vec<asl::Record>, QueryError> {
-        let mut file = fs::File::open(self.get_table_data_path(table))?;
-        let mut page_buffer = [0; PAGE_SIZE];
-        let mut records: Vec<asl::Record> = Vec::new();
-        while file.read(&mut page_buffer)? > 0 {
-            let page = Page::from_bytes(&page_buffer);
-            for item in page.get_items() {
-                let record = item.to_record(table);
-                let include_record = match condition {
-                    Some(condition) => {
-                        let identifier_values: HashMap<String, asl::Value> =
-                            record.values.iter().enumerate()
-                                .map(|(idx, value)| (table.columns[idx].name.clone(), value.clone()))
-                                .collect();
-                        condition.evaluate(Option::Some(&identifier_values))? == asl::Value::Bool(true)
+    fn replace_tables_pages(&self, tables: &[(&asl::Table, Vec<Page>)]) -> Result<(), QueryError> {
+        self.check_writable()?;
+        let mut renames = Vec::new();
+        for (table, pages) in tables {
+            let data_path = self.get_table_data_path(table);
+            match self.write_temp_file(&data_path, &pages_to_bytes(pages)) {
+                Ok(temp_path) => renames.push((temp_path, data_path)),
+                Err(error) => {
+                    for (temp_path, _) in renames {
+                        let _ = fs::remove_file(temp_path);
                     }
-                    None => true
-                };
-                if include_record {
-                    records.push(record);
+                    let _ = fs::remove_file(format!("{}.tmp", data_path));
+                    return Err(error)
                 }
             }
         }
-        Ok(records)
+        self.rename_temp_files(&renames)
+    }
+
+    fn read_page(&self, table: &asl::Table, page_idx: usize) -> Result<Option<Page>, QueryError> {
+        let data_path = self.get_table_data_path(table);
+        let mut file = match fs::File::open(&data_path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Err(QueryError::NotFound(
+                format!("The data file {} of table {}", data_path, table.name))),
+            Err(error) => return Err(error.into()),
+        };
+        file.seek(SeekFrom::Start(page_idx as u64 * PAGE_SIZE as u64))?;
+        let mut page_buffer = [0u8; PAGE_SIZE];
+        match read_page(&mut file, &mut page_buffer)? {
+            0 => Ok(None),
+            PAGE_SIZE => Ok(Some(Page::from_bytes(&page_buffer))),
+            _ => Err(QueryError::NotFound(format!("Page {} of table {}", page_idx + 1, table.name))),
+        }
     }
-}
 
+    fn check_table(&self, table: &asl::Table) -> Result<Vec<IntegrityProblem>, QueryError> {
+        let mut problems = Vec::new();
+        for (expected_page_id, page) in (1..).zip(self.pages(table)?) {
+            match page {
+                Ok(page) => problems.extend(check_page(expected_page_id, &page, table)),
+                Err(QueryError::PagingError(description)) => problems.push(IntegrityProblem {
+                    page_id: expected_page_id,
+                    offset: None,
+                    description,
+                }),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(problems)
+    }
+
+    /**
+    * Rebuild the table file from its readable records, which keep their row ids. The original file is
+    * copied to a backup file and then replaced atomically
+    */
+    fn repair_table(&self, table: &asl::Table) -> Result<RepairReport, QueryError> {
+        self.check_writable()?;
+        let data_path = self.get_table_data_path(table);
+        let mut report = RepairReport::default();
+        let mut bytes = Vec::new();
+        for (page_id, page) in (1..).zip(self.pages(table)?) {
+            let new_page = match page {
+                Ok(page) => report.add_page(page_id, &page, table),
+                Err(QueryError::PagingError(_)) => report.add_lost_page(page_id),
+                Err(error) => return Err(error),
+            };
+            bytes.extend_from_slice(&new_page.to_bytes());
+        }
+        let backup_path = format!("{}.bak", data_path);
+        fs::copy(&data_path, &backup_path)?;
+        self.atomic_write(&data_path, &bytes)?;
+        report.backup_path = Some(backup_path);
+        Ok(report)
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::engine::asl;
+use crate::engine::pages::Page;
+
+#[derive(Debug)]
+pub struct IntegrityProblem {
+    pub page_id: u32,
+    pub offset: Option<usize>,
+    pub description: String,
+}
+
+impl fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "page {}, offset {}: {}", self.page_id, offset, self.description),
+            None => write!(f, "page {}: {}", self.page_id, self.description),
+        }
+    }
+}
+
+/**
+* A mismatch between the table definitions and the table files in the storage
+*/
+#[derive(Debug, PartialEq)]
+pub enum Inconsistency {
+    // A table is defined, but its data file doesn't exist
+    MissingDataFile(String),
+    // A data file exists for a table that isn't defined
+    OrphanDataFile(String),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inconsistency::MissingDataFile(table) =>
+                write!(f, "The table {} is defined but its data file is missing", table),
+            Inconsistency::OrphanDataFile(table) =>
+                write!(f, "There is a data file for {}, but no table with that name is defined", table),
+        }
+    }
+}
+
+/**
+* Verify that every item in the page decodes to a record with the same number of fields as the table.
+* expected_page_id is the id implied by the page position, which is reported instead of the stored
+* id since the stored one may be corrupted too
+*/
+pub fn check_page(expected_page_id: u32, page: &Page, table: &asl::Table) -> Vec<IntegrityProblem> {
+    let mut problems = Vec::new();
+    let problem = |offset: Option<usize>, description: String| {
+        IntegrityProblem { page_id: expected_page_id, offset, description }
+    };
+    if page.id != expected_page_id {
+        problems.push(problem(None, format!("Stored page id is {}", page.id)));
+    }
+    if let Err(error) = page.validate_header() {
+        problems.push(problem(None, format!("{:?}", error)));
+        return problems
+    }
+    for (offset, size) in page.get_item_offset_and_sizes().into_iter().flatten() {
+        if let Err(description) = decode_item(page, offset, size, table) {
+            problems.push(problem(Some(offset), description));
+        }
+    }
+    problems
+}
+
+/**
+* Decode the item at offset into a record, checking that it has one field per table column
+*/
+fn decode_item(page: &Page, offset: usize, size: usize, table: &asl::Table) -> Result<asl::Record, String> {
+    let item = page.get_item(offset, size).map_err(|error| format!("{:?}", error))?;
+    if item.get_number_of_fields() != table.columns.len() {
+        return Err(format!("Item has {} fields but the table has {} columns",
+                           item.get_number_of_fields(), table.columns.len()))
+    }
+    item.to_record(table).map_err(|error| format!("{:?}", error))
+}
+
+/**
+* Rebuild the page with the items that can be decoded, each one in its slot so its row id doesn't
+* change, leaving tombstones in the slots of the items that can't. Returns the new page and the
+* number of items lost, or None if the page header itself is broken, in which case the whole page
+* is lost
+*/
+pub fn recover_page(page: &Page, table: &asl::Table) -> Option<(Page, usize)> {
+    if page.validate_header().is_err() {
+        return None
+    }
+    let mut lost_items = HashMap::new();
+    for (slot, item) in page.get_item_offset_and_sizes().into_iter().enumerate() {
+        if let Some((offset, size)) = item {
+            if decode_item(page, offset, size, table).is_err() {
+                lost_items.insert(slot, None);
+            }
+        }
+    }
+    let (new_page, _) = page.apply_changes(&lost_items).ok()?;
+    Some((new_page, lost_items.len()))
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub recovered_records: usize,
+    pub lost_records: usize,
+    pub lost_pages: usize,
+    pub backup_path: Option<String>,
+}
+
+impl RepairReport {
+    /**
+    * Add the recovery results of a page to this report, returning the page rebuilt with its
+    * recovered records. page_id is the id implied by the page position, which the rebuilt page gets,
+    * and a lost page is replaced by an empty one, so the records of the next pages keep their row ids
+    */
+    pub fn add_page(&mut self, page_id: u32, page: &Page, table: &asl::Table) -> Page {
+        match recover_page(page, table) {
+            Some((mut new_page, lost_records)) => {
+                self.recovered_records += new_page.get_item_count();
+                self.lost_records += lost_records;
+                new_page.id = page_id;
+                new_page
+            },
+            None => self.add_lost_page(page_id),
+        }
+    }
+
+    /**
+    * Count a page that couldn't be read, returning the empty page that replaces it
+    */
+    pub fn add_lost_page(&mut self, page_id: u32) -> Page {
+        self.lost_pages += 1;
+        Page::new(page_id)
+    }
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recovered {} records. Lost {} records and {} pages",
+               self.recovered_records, self.lost_records, self.lost_pages)?;
+        if let Some(backup_path) = &self.backup_path {
+            write!(f, ". The original data was backed up to {}", backup_path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashSet;
+
+use crate::engine::asl;
+use crate::engine::errors::QueryError;
+use crate::engine::fs::evaluate_for_record;
+
+/**
+* The tables in FROM of a SELECT, each with the name its columns are qualified with: its alias, or
+* else its own name
+*/
+pub struct FromTables<'a> {
+    tables: Vec<(String, &'a asl::Table)>,
+}
+
+impl<'a> FromTables<'a> {
+    /**
+    * Pair the first table of FROM and the joined tables, in order, with their qualifiers, which
+    * must be unique
+    */
+    pub fn new(query: &asl::SelectQuery, tables: Vec<&'a asl::Table>) -> Result<FromTables<'a>, QueryError> {
+        let qualifiers = query.alias.iter().chain(query.table.iter()).take(1).map(String::as_str)
+            .chain(query.joins.iter().map(|join| join.get_qualifier()));
+        let tables: Vec<(String, &asl::Table)> = qualifiers.map(String::from).zip(tables).collect();
+        let mut seen_qualifiers = HashSet::new();
+        for (qualifier, _) in &tables {
+            if !seen_qualifiers.insert(qualifier) {
+                return Err(QueryError::ValidationError(format!(
+                    "The table {} appears more than once in FROM. Give it another name with AS", qualifier)))
+            }
+        }
+        Ok(FromTables { tables })
+    }
+
+    /**
+    * Build the table the rows of the join have: the columns of every table, in order, named
+    * qualifier.column
+    */
+    pub fn get_joined_table(&self) -> asl::Table {
+        let names: Vec<&str> = self.tables.iter().map(|(qualifier, _)| qualifier.as_str()).collect();
+        let mut columns: Vec<asl::Column> = self.tables.iter()
+            .flat_map(|(qualifier, table)| table.columns.iter().map(move |column| asl::Column {
+                id: 0,
+                name: format!("{}.{}", qualifier, column.name),
+                column_type: column.column_type.clone(),
+                comment: None,
+                default: None,
+            }))
+            .collect();
+        asl::Column::assign_ids(&mut columns);
+        asl::Table { name: names.join(" JOIN "), columns, comment: None, constraints: Vec::new() }
+    }
+
+    /**
+    * Resolve a column name against the first tables_count tables. Qualified names keep their
+    * qualifier, and unqualified ones get the qualifier of the only table with that column. Without
+    * joins, columns are named like in their table, so qualified names lose their qualifier. Names
+    * that don't match any column are left alone, as they may be aliases of result columns
+    */
+    fn resolve(&self, name: &str, tables_count: usize) -> Result<Option<String>, QueryError> {
+        let tables = &self.tables[..tables_count];
+        let is_joined = self.tables.len() > 1;
+        if let Some(dot_idx) = name.find('.') {
+            let (qualifier, column) = (&name[..dot_idx], &name[dot_idx + 1..]);
+            if tables.iter().any(|(table_qualifier, _)| table_qualifier == qualifier) {
+                return Ok(if is_joined { None } else { Some(String::from(column)) })
+            }
+        }
+        let mut matches = tables.iter().filter(|(_, table)| table.get_column(name).is_some());
+        match (matches.next(), matches.next()) {
+            (Some(_), Some(_)) => Err(QueryError::ValidationError(
+                format!("The column {} is ambiguous. Qualify it with the name of its table", name))),
+            (Some((qualifier, _)), None) if is_joined => Ok(Some(format!("{}.{}", qualifier, name))),
+            _ => Ok(None),
+        }
+    }
+
+    /**
+    * Replace table.* with the qualified columns of the table, in definition order
+    */
+    fn expand_qualified_all(&self, items: Vec<asl::SelectItem>) -> Result<Vec<asl::SelectItem>, QueryError> {
+        let mut expanded_items = Vec::new();
+        for item in items {
+            let qualifier = match &item.expression {
+                asl::SelectExpression::QualifiedAll(qualifier) => qualifier,
+                _ => {
+                    expanded_items.push(item);
+                    continue
+                },
+            };
+            let table = match self.tables.iter().find(|(table_qualifier, _)| table_qualifier == qualifier) {
+                Some((_, table)) => table,
+                None => return Err(unknown_qualified_all_error(qualifier)),
+            };
+            expanded_items.extend(table.columns.iter().map(|column| asl::SelectItem {
+                expression: asl::SelectExpression::Expression(Box::new(
+                    asl::Expression::Identifier(format!("{}.{}", qualifier, column.name)))),
+                alias: None,
+            }));
+        }
+        Ok(expanded_items)
+    }
+
+    /**
+    * Qualify the column names of the query with the table they belong to, so they match the columns
+    * of the joined table. Plain selected columns keep their name as the name of the result column,
+    * like the columns table.* is expanded to. ON conditions can only reference the tables joined up
+    * to them
+    */
+    pub fn resolve_identifiers(&self, query: &mut asl::SelectQuery) -> Result<(), QueryError> {
+        let tables_count = self.tables.len();
+        let mut aliases = HashSet::new();
+        if let asl::SelectColumns::Items(items) = &mut query.columns {
+            *items = self.expand_qualified_all(std::mem::take(items))?;
+            for item in items {
+                if let Some(alias) = &item.alias {
+                    aliases.insert(alias.clone());
+                }
+                match &mut item.expression {
+                    asl::SelectExpression::Expression(expression) => {
+                        if let (asl::Expression::Identifier(name), None) = (expression.as_ref(), &item.alias) {
+                            let column_name = name.rsplit('.').next().unwrap_or(name);
+                            if column_name != name || self.resolve(name, tables_count)?.is_some() {
+                                item.alias = Some(String::from(column_name));
+                            }
+                        }
+                        expression.rename_identifiers(&mut |name| self.resolve(name, tables_count))?;
+                    },
+                    expression => if let Some(expression) = expression.get_aggregated_expression_mut() {
+                        expression.rename_identifiers(&mut |name| self.resolve(name, tables_count))?;
+                    },
+                }
+            }
+        }
+        if let Some(condition) = &mut query.condition {
+            condition.rename_identifiers(&mut |name| self.resolve(name, tables_count))?;
+        }
+        for expression in &mut query.group_by {
+            expression.rename_identifiers(&mut |name| self.resolve(name, tables_count))?;
+        }
+        for order_by in &mut query.order_by {
+            order_by.expression.rename_identifiers(&mut |name| {
+                if aliases.contains(name) { Ok(None) } else { self.resolve(name, tables_count) }
+            })?;
+        }
+        for (idx, join) in query.joins.iter_mut().enumerate() {
+            if let Some(condition) = &mut join.condition {
+                condition.rename_identifiers(&mut |name| self.resolve(name, idx + 2))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn unknown_qualified_all_error(qualifier: &str) -> QueryError {
+    QueryError::ValidationError(format!("The table {} of {}.* isn't in FROM", qualifier, qualifier))
+}
+
+// Number of row pairs a join combines between checks of the query deadline
+const DEADLINE_CHECK_ROWS: usize = 1024;
+
+/**
+* Nested loop join of the records of the first table with the records of the joined tables, which are
+* held in memory. Stops with a ValidationError once the join would produce more than max_rows rows,
+* so a join without a selective condition can't exhaust the memory. A single record of the first table
+* can be combined with many rows, so the query deadline is checked while combining them too
+*/
+pub struct NestedLoopJoin<'a> {
+    joined_table: &'a asl::Table,
+    joins: &'a [asl::Join],
+    // Records of each joined table
+    inner_records: Vec<Vec<asl::Record>>,
+    max_rows: usize,
+    produced_rows: usize,
+    // Fails with a Timeout error once the query is past its deadline
+    check_deadline: &'a dyn Fn() -> Result<(), QueryError>,
+    // Row pairs combined so far, to check the deadline every DEADLINE_CHECK_ROWS of them
+    combined_rows: usize,
+}
+
+impl<'a> NestedLoopJoin<'a> {
+    pub fn new(joined_table: &'a asl::Table, joins: &'a [asl::Join], inner_records: Vec<Vec<asl::Record>>,
+               max_rows: usize, check_deadline: &'a dyn Fn() -> Result<(), QueryError>) -> NestedLoopJoin<'a> {
+        NestedLoopJoin { joined_table, joins, inner_records, max_rows, produced_rows: 0, check_deadline, combined_rows: 0 }
+    }
+
+    /**
+    * Get the rows of the join for a record of the first table
+    */
+    pub fn join(&mut self, record: asl::Record) -> Result<Vec<asl::Record>, QueryError> {
+        let mut rows = vec![record];
+        for (join, inner_records) in self.joins.iter().zip(&self.inner_records) {
+            let mut joined_rows = Vec::new();
+            for row in &rows {
+                for inner_record in inner_records {
+                    self.combined_rows += 1;
+                    if self.combined_rows % DEADLINE_CHECK_ROWS == 0 {
+                        (self.check_deadline)()?;
+                    }
+                    let mut values = row.values.clone();
+                    values.extend(inner_record.values.iter().cloned());
+                    let joined_row = asl::Record { values };
+                    // The joined table has the columns of every table, so the row only needs the
+                    // ones it has so far
+                    if evaluate_for_record(self.joined_table, &joined_row, &join.condition)? {
+                        if self.produced_rows + joined_rows.len() >= self.max_rows {
+                            return Err(QueryError::ValidationError(format!(
+                                "The join produces more than {} rows. Add a more selective ON condition or \
+                                raise the join row limit", self.max_rows)))
+                        }
+                        joined_rows.push(joined_row);
+                    }
+                }
+            }
+            rows = joined_rows;
+        }
+        self.produced_rows += rows.len();
+        Ok(rows)
+    }
+}
+
+/**
+* Get a warning for every join without an ON condition, which combines every pair of rows and is
+* usually a mistake. CROSS JOIN asks for that explicitly, so it isn't warned about
+*/
+pub fn get_join_warnings(query: &asl::SelectQuery) -> Vec<String> {
+    query.joins.iter()
+        .filter(|join| join.condition.is_none() && join.kind != asl::JoinKind::Cross)
+        .map(|join| format!(
+            "JOIN {} has no ON condition, so it combines every pair of rows. Use CROSS JOIN if that's intended",
+            join.table))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn joined_table() -> asl::Table {
+        let column = |id: u32, name: &str| asl::Column {
+            id, name: String::from(name), column_type: asl::Type::Int, comment: None, default: None };
+        asl::Table {
+            name: String::from("joined"),
+            columns: vec![column(0, "a"), column(1, "b")],
+            comment: None,
+            constraints: Vec::new(),
+        }
+    }
+
+    fn cross_join() -> Vec<asl::Join> {
+        vec![asl::Join { table: String::from("b"), alias: None, condition: None, kind: asl::JoinKind::Cross }]
+    }
+
+    fn inner_records(count: i32) -> Vec<Vec<asl::Record>> {
+        vec![(0..count).map(|value| asl::Record { values: vec![asl::Value::Int(value)] }).collect()]
+    }
+
+    #[test]
+    fn deadline_is_checked_while_joining_a_record() {
+        let table = joined_table();
+        let joins = cross_join();
+        let checks = Cell::new(0);
+        let check_deadline = || {
+            checks.set(checks.get() + 1);
+            match checks.get() {
+                1 => Ok(()),
+                _ => Err(QueryError::Timeout(String::from("Past the deadline"))),
+            }
+        };
+        let mut join = NestedLoopJoin::new(&table, &joins, inner_records(3000), usize::max_value(), &check_deadline);
+        match join.join(asl::Record { values: vec![asl::Value::Int(1)] }) {
+            Err(QueryError::Timeout(_)) => (),
+            result => panic!("The join should time out, but got {:?}", result.map(|rows| rows.len())),
+        }
+        assert_eq!(checks.get(), 2);
+    }
+
+    #[test]
+    fn deadline_is_checked_every_few_combined_rows() {
+        let table = joined_table();
+        let joins = cross_join();
+        let checks = Cell::new(0);
+        let check_deadline = || {
+            checks.set(checks.get() + 1);
+            Ok(())
+        };
+        let mut join = NestedLoopJoin::new(&table, &joins, inner_records(1000), usize::max_value(), &check_deadline);
+        for value in 0..3 {
+            assert_eq!(join.join(asl::Record { values: vec![asl::Value::Int(value)] }).unwrap().len(), 1000);
+        }
+        assert_eq!(checks.get(), 3000 / DEADLINE_CHECK_ROWS);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use serde_json;
+
+use crate::engine::asl;
+use crate::engine::errors::QueryError;
+
+/**
+* Convert a JSON value to a value of the column type. Numbers must fit the column type, and strings
+* are cast like in CAST, so they can hold dates, timestamps and numbers
+*/
+fn decode_json_value(column: &asl::Column, value: serde_json::Value) -> Result<asl::Value, QueryError> {
+    let invalid_value = |value: &serde_json::Value| QueryError::ValidationError(format!(
+        "Can't load {} into the column {} of type {:?}", value, column.name, column.column_type));
+    match (value, &column.column_type) {
+        (serde_json::Value::Null, _) => Ok(asl::Value::Null),
+        (serde_json::Value::Bool(value), asl::Type::Bool) => Ok(asl::Value::Bool(value)),
+        (serde_json::Value::Number(number), asl::Type::Int) => match number.as_i64() {
+            Some(value) if value >= i32::min_value() as i64 && value <= i32::max_value() as i64 =>
+                Ok(asl::Value::Int(value as i32)),
+            _ => Err(invalid_value(&serde_json::Value::Number(number))),
+        },
+        (serde_json::Value::Number(number), asl::Type::Float) => match number.as_f64() {
+            Some(value) => asl::finite_float(value as f32),
+            None => Err(invalid_value(&serde_json::Value::Number(number))),
+        },
+        (serde_json::Value::String(value), column_type) => asl::Value::Str(value).cast(column_type),
+        (value, _) => Err(invalid_value(&value)),
+    }
+}
+
+/**
+* Convert a JSON object to a record of the table, matching its keys to the columns by name. Columns
+* without a key get their default, or Null
+*/
+fn decode_json_record(table: &asl::Table,
+                      object: serde_json::Map<String, serde_json::Value>) -> Result<asl::Record, QueryError> {
+    let mut values: Vec<asl::Value> = table.columns.iter()
+        .map(|column| column.default.as_ref().map_or(asl::Value::Null, |default| default.evaluate()))
+        .collect();
+    for (key, value) in object {
+        let idx = match table.columns.iter().position(|column| column.name == key) {
+            Some(idx) => idx,
+            None => return Err(QueryError::ValidationError(
+                format!("The column {} doesn't exist in {}", key, table.name))),
+        };
+        values[idx] = decode_json_value(&table.columns[idx], value)?;
+    }
+    Ok(asl::Record { values })
+}
+
+/**
+* Read the JSON file, which must be an array of objects, and call f with every object decoded as a
+* record of the table. The whole array is parsed before the first record is decoded
+*/
+pub fn read_json(table: &asl::Table, path: &str,
+                 f: &mut dyn FnMut(asl::Record) -> Result<(), QueryError>) -> Result<(), QueryError> {
+    let objects: Vec<serde_json::Value> = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|error| QueryError::ValidationError(format!("{} isn't a JSON array: {}", path, error)))?;
+    for (idx, object) in objects.into_iter().enumerate() {
+        let record = match object {
+            serde_json::Value::Object(object) => decode_json_record(table, object),
+            value => Err(QueryError::ValidationError(format!("Expected an object but found {}", value))),
+        };
+        let record = record.map_err(|error| match error {
+            QueryError::ValidationError(message) =>
+                QueryError::ValidationError(format!("Object {}: {}", idx + 1, message)),
+            error => error,
+        })?;
+        f(record)?;
+    }
+    Ok(())
+}
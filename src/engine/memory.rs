@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::config::config::Config;
+use crate::engine::asl;
+use crate::engine::db::DatabaseDefinition;
+use crate::engine::errors::{QueryError, SystemError};
+use crate::engine::fs::evaluate_for_record;
+use crate::engine::integrity::{check_page, IntegrityProblem, RepairReport};
+use crate::engine::pages::{ Item, Page, RowId };
+use crate::engine::storage::{PageChanges, ScanControl, StorageEngine};
+
+/**
+* Storage backend that keeps the table pages in memory instead of files. Records are encoded into
+* pages exactly like the file backend does, so both behave the same way
+*/
+pub struct InMemoryStorage {
+    db_definition: RefCell<Option<DatabaseDefinition>>,
+    tables: RefCell<HashMap<String, Vec<Page>>>,
+    // Percentage of each page filled by appended items
+    fill_factor: u8,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> InMemoryStorage {
+        InMemoryStorage::with_config(&Config::default())
+    }
+
+    /**
+    * Fill the pages up to the fill factor of the config, like the file backend
+    */
+    pub fn with_config(config: &Config) -> InMemoryStorage {
+        InMemoryStorage {
+            db_definition: RefCell::new(None),
+            tables: RefCell::new(HashMap::new()),
+            fill_factor: config.fill_factor,
+        }
+    }
+
+    /**
+    * Take the pages of every table, by table name
+    */
+    pub fn into_tables(self) -> HashMap<String, Vec<Page>> {
+        self.tables.into_inner()
+    }
+
+    /**
+    * Insert an item into the last page of the table, creating a new page if the current one is filled
+    * up to the fill factor
+    */
+    fn append_item(&self, pages: &mut Vec<Page>, item: &Item) -> Result<(), QueryError> {
+        let next_page_id = match pages.last_mut() {
+            Some(last_page) => match last_page.add_item_with_fill_factor(item, self.fill_factor) {
+                Ok(_) => return Ok(()),
+                Err(_) => last_page.id + 1,
+            },
+            None => 1,
+        };
+        let mut new_page = Page::new(next_page_id);
+        new_page.add_item(item)?;
+        pages.push(new_page);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for InMemoryStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let page_counts: HashMap<String, usize> = self.tables.borrow().iter()
+            .map(|(name, pages)| (name.clone(), pages.len()))
+            .collect();
+        f.debug_struct("InMemoryStorage").field("pages", &page_counts).finish()
+    }
+}
+
+impl StorageEngine for InMemoryStorage {
+    fn initialize(&self) -> Result<(), SystemError> {
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        String::from("in-memory")
+    }
+
+    fn load_definitions(&self) -> Result<DatabaseDefinition, QueryError> {
+        match self.db_definition.borrow().as_ref() {
+            Some(db_definition) => Ok(db_definition.clone()),
+            None => Err(QueryError::NotFound(String::from("Database definition")))
+        }
+    }
+
+    fn store_definitions(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        *self.db_definition.borrow_mut() = Some(db_definition.clone());
+        Ok(())
+    }
+
+    fn create_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        self.tables.borrow_mut().insert(table.name.clone(), Vec::new());
+        Ok(())
+    }
+
+    fn delete_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        match self.tables.borrow_mut().remove(&table.name) {
+            Some(_) => Ok(()),
+            None => Err(QueryError::NotFound(table.name.clone()))
+        }
+    }
+
+    fn delete_database(&self, _db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        self.tables.borrow_mut().clear();
+        *self.db_definition.borrow_mut() = None;
+        Ok(())
+    }
+
+    fn list_table_files(&self) -> Result<Vec<String>, QueryError> {
+        Ok(self.tables.borrow().keys().cloned().collect())
+    }
+
+    fn insert_record(&self, table: &asl::Table, record: &asl::Record) -> Result<(), QueryError> {
+        let mut tables = self.tables.borrow_mut();
+        let pages = tables.entry(table.name.clone()).or_insert_with(Vec::new);
+        self.append_item(pages, &Item::from_record(table, record))
+    }
+
+    fn apply_page_changes(&self, table: &asl::Table, page_changes: BTreeMap<u32, PageChanges>) -> Result<(), QueryError> {
+        let mut tables = self.tables.borrow_mut();
+        let pages = match tables.get_mut(&table.name) {
+            Some(pages) => pages,
+            None => return Err(QueryError::NotFound(table.name.clone()))
+        };
+        let mut overflow_items = Vec::new();
+        for (page_id, changes) in page_changes {
+            let page = match pages.iter_mut().find(|page| page.id == page_id) {
+                Some(page) => page,
+                None => return Err(QueryError::NotFound(format!("Page {} of table {}", page_id, table.name)))
+            };
+            let (new_page, items) = page.apply_changes(&changes)?;
+            *page = new_page;
+            overflow_items.extend(items);
+        }
+        for item in overflow_items {
+            self.append_item(pages, &item)?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Call f with the row id of every record in the table that matches the given condition. Pages are
+    * copied one at a time, so f may write to the storage while the scan is running
+    */
+    fn scan_records(&self, table: &asl::Table, condition: &Option<Box<asl::Expression>>,
+                    f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        if !self.tables.borrow().contains_key(&table.name) {
+            return Err(QueryError::NotFound(table.name.clone()))
+        }
+        let mut page_idx = 0;
+        loop {
+            let page = match self.tables.borrow().get(&table.name).and_then(|pages| pages.get(page_idx)) {
+                Some(page) => page.clone(),
+                None => break,
+            };
+            for (slot, item) in page.items_iter()? {
+                let record = item?.to_record(table)?;
+                if evaluate_for_record(table, &record, condition)? {
+                    if f(RowId { page_id: page.id, slot }, record)? == ScanControl::Stop {
+                        return Ok(())
+                    }
+                }
+            }
+            page_idx += 1;
+        }
+        Ok(())
+    }
+
+    fn replace_pages(&self, table: &asl::Table, pages: &[Page]) -> Result<(), QueryError> {
+        self.tables.borrow_mut().insert(table.name.clone(), pages.to_vec());
+        Ok(())
+    }
+
+    fn read_page(&self, table: &asl::Table, page_idx: usize) -> Result<Option<Page>, QueryError> {
+        match self.tables.borrow().get(&table.name) {
+            Some(pages) => Ok(pages.get(page_idx).cloned()),
+            None => Err(QueryError::NotFound(table.name.clone()))
+        }
+    }
+
+    fn check_table(&self, table: &asl::Table) -> Result<Vec<IntegrityProblem>, QueryError> {
+        let tables = self.tables.borrow();
+        let pages = match tables.get(&table.name) {
+            Some(pages) => pages,
+            None => return Err(QueryError::NotFound(table.name.clone()))
+        };
+        Ok(pages.iter().enumerate()
+            .flat_map(|(idx, page)| check_page(idx as u32 + 1, page, table))
+            .collect())
+    }
+
+    fn repair_table(&self, table: &asl::Table) -> Result<RepairReport, QueryError> {
+        let mut tables = self.tables.borrow_mut();
+        let pages = match tables.get_mut(&table.name) {
+            Some(pages) => pages,
+            None => return Err(QueryError::NotFound(table.name.clone()))
+        };
+        let mut report = RepairReport::default();
+        *pages = (1..).zip(pages.iter())
+            .map(|(page_id, page)| report.add_page(page_id, page, table))
+            .collect();
+        Ok(report)
+    }
+}
@@ -1,6 +1,21 @@
 pub mod asl;
+pub mod catalog;
+pub mod constraints;
+pub mod csv;
 pub mod db;
+pub mod definitions;
 pub mod errors;
+pub mod explain;
+pub mod format;
 pub mod fs;
+pub mod integrity;
+pub mod join;
+pub mod json;
+pub mod memory;
 pub mod pages;
+pub mod rows;
+pub mod sort;
+pub mod storage;
+pub mod temporal;
+pub mod transaction;
 pub mod utils;
\ No newline at end of file
@@ -1,5 +1,7 @@
 use bit_vec::BitVec;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fmt;
 use std::mem;
 
 use crate::engine::asl;
@@ -9,7 +11,10 @@ use crate::engine::utils::copy_bytes_into;
 pub const PAGE_SIZE: usize = 8 * 1024;
 const USIZE_SIZE: usize = mem::size_of::<usize>();
 const U32_SIZE: usize = mem::size_of::<u32>();
-const PAGE_DATA_SIZE: usize = PAGE_SIZE - U32_SIZE - USIZE_SIZE*2;
+pub const PAGE_HEADER_SIZE: usize = U32_SIZE + USIZE_SIZE*2;
+const PAGE_DATA_SIZE: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
+// Slot directory entry of a deleted item. Items are never empty, so no live item has size 0
+const TOMBSTONE: (usize, usize) = (0, 0);
 
 /**
 * TryFrom trait copied from the std lib and implemented specifically for the page data size
@@ -24,6 +29,64 @@ fn try_page_from_slice(slice: &[u8]) -> Result<&[u8; PAGE_DATA_SIZE], PagingErro
     }
 }
 
+/**
+* Stable location of a record in a table: the id of its page and its index in the page slot directory.
+* Slots of deleted records are kept as tombstones, so the other records of the page keep their slot
+* when the page is rewritten. A record only moves when an update makes it too big for its page
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RowId {
+    pub page_id: u32,
+    pub slot: usize,
+}
+
+impl fmt::Display for RowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({},{})", self.page_id, self.slot)
+    }
+}
+
+/**
+* The header at the start of every page, which is enough to know how the page space is used without
+* decoding its items
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct PageHeader {
+    free_space_start: usize,
+    free_space_end: usize,
+}
+
+impl PageHeader {
+    pub fn from_bytes(bytes: &[u8; PAGE_HEADER_SIZE]) -> PageHeader {
+        PageHeader {
+            free_space_start: usize::from_be_bytes(bytes[U32_SIZE..U32_SIZE + USIZE_SIZE].try_into().unwrap()),
+            free_space_end: usize::from_be_bytes(bytes[U32_SIZE + USIZE_SIZE..PAGE_HEADER_SIZE].try_into().unwrap()),
+        }
+    }
+
+    pub fn get_free_space_start(&self) -> usize {
+        self.free_space_start
+    }
+
+    pub fn get_free_space_end(&self) -> usize {
+        self.free_space_end
+    }
+
+    /**
+    * Number of slots in the slot directory, including the tombstones of deleted items
+    */
+    pub fn get_slot_count(&self) -> usize {
+        self.free_space_start / (USIZE_SIZE*2)
+    }
+
+    /**
+    * Bytes between the slot directory and the items. Corrupted headers report no free space
+    */
+    pub fn get_free_space(&self) -> usize {
+        self.free_space_end.saturating_sub(self.free_space_start)
+    }
+}
+
 #[derive(Clone)]
 pub struct Page {
     pub id: u32,
@@ -57,6 +120,10 @@ impl Page {
         }
     }
 
+    pub fn get_header(&self) -> PageHeader {
+        PageHeader { free_space_start: self.free_space_start, free_space_end: self.free_space_end }
+    }
+
     /**
     * Return a byte representation of this page
     */
@@ -73,53 +140,217 @@ impl Page {
         bytes
     }
 
-    fn get_item_offset_and_sizes(&self) -> Vec<(usize, usize)> {
-        let mut item_offsets = Vec::new();
-        let mut current_offset = 0;
-        while current_offset < self.free_space_start {
-            let size_offset = current_offset + USIZE_SIZE;
-            let size_end = size_offset + USIZE_SIZE;
-            item_offsets.push((
-                usize::from_be_bytes(self.data[current_offset..size_offset].try_into().unwrap()),
-                usize::from_be_bytes(self.data[size_offset..size_end].try_into().unwrap()),
-            ));
-            current_offset = size_end;
+    /**
+    * Check that the free space pointers in the page header are consistent
+    */
+    pub fn validate_header(&self) -> Result<(), PagingError> {
+        if self.free_space_start > self.free_space_end || self.free_space_end > PAGE_DATA_SIZE
+            || self.free_space_start % (USIZE_SIZE*2) != 0 {
+            Err(PagingError::CorruptedData(format!(
+                "Invalid free space pointers {}..{}", self.free_space_start, self.free_space_end)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /**
+    * Read the slot directory of the page, with None for the tombstones of deleted items. The header
+    * must be validated first
+    */
+    pub fn get_item_offset_and_sizes(&self) -> Vec<Option<(usize, usize)>> {
+        self.slots().map(|slot| if slot == TOMBSTONE { None } else { Some(slot) }).collect()
+    }
+
+    /**
+    * Number of items in the page, not counting the tombstones. The header must be validated first
+    */
+    pub fn get_item_count(&self) -> usize {
+        self.slots().filter(|slot| *slot != TOMBSTONE).count()
+    }
+
+    /**
+    * Iterate over the offset and size of each item in the slot directory, reading them as needed.
+    * The header must be validated first
+    */
+    fn slots(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.free_space_start).step_by(USIZE_SIZE*2).map(move |slot_offset| {
+            let size_offset = slot_offset + USIZE_SIZE;
+            (
+                usize::from_be_bytes(self.data[slot_offset..size_offset].try_into().unwrap()),
+                usize::from_be_bytes(self.data[size_offset..size_offset + USIZE_SIZE].try_into().unwrap()),
+            )
+        })
+    }
+
+    /**
+    * Decode the item stored at offset, checking that it lies within the page item area
+    */
+    pub fn get_item(&self, offset: usize, size: usize) -> Result<Item, PagingError> {
+        if offset < self.free_space_end || offset.checked_add(size).map_or(true, |end| end > PAGE_DATA_SIZE) {
+            return Err(PagingError::CorruptedData(format!("Item of size {} out of page bounds", size)))
         }
-        item_offsets
+        Item::from_page_data(&self.data[offset..offset + size])
+    }
+
+    /**
+    * Iterate over the items of the page in slot order along with their slot, skipping tombstones and
+    * decoding each item only when it's reached, so scans don't build a Vec of every item of the page
+    */
+    pub fn items_iter(&self) -> Result<impl Iterator<Item = (usize, Result<Item, PagingError>)> + '_, PagingError> {
+        self.validate_header()?;
+        Ok(self.slots().enumerate()
+            .filter(|(_, slot)| *slot != TOMBSTONE)
+            .map(move |(slot, (offset, size))| (slot, self.get_item(offset, size))))
     }
 
-    pub fn get_items(&self) -> Vec<Item> {
-        self.get_item_offset_and_sizes().iter().map(
-            |(offset, size)| {
-                Item::from_page_data(&self.data[*offset..*offset + *size])
+    /**
+    * Build a copy of this page with the changes applied, packing its items at the end of the page
+    * again. changes maps a slot to its new item, or to None to delete the item. Every item keeps its
+    * slot, and deleted items leave a tombstone. Returns the new page and the items that no longer fit
+    * in it, whose slots become tombstones too
+    */
+    pub fn apply_changes(&self, changes: &HashMap<usize, Option<Item>>) -> Result<(Page, Vec<Item>), PagingError> {
+        self.validate_header()?;
+        let mut new_page = Page::new(self.id);
+        let mut overflow_items = Vec::new();
+        for (slot, (offset, size)) in self.slots().enumerate() {
+            let item = match changes.get(&slot) {
+                Some(Some(changed_item)) => changed_item.clone(),
+                Some(None) => {
+                    new_page.push_slot(TOMBSTONE);
+                    continue
+                },
+                None if (offset, size) == TOMBSTONE => {
+                    new_page.push_slot(TOMBSTONE);
+                    continue
+                },
+                None => self.get_item(offset, size)?,
+            };
+            let item_data = item.to_page_data();
+            if item_data.len() + USIZE_SIZE*2 > new_page.get_free_space() {
+                new_page.push_slot(TOMBSTONE);
+                overflow_items.push(item);
+            } else {
+                let item_offset = new_page.push_item_data(&item_data);
+                new_page.push_slot((item_offset, item_data.len()));
             }
-        ).collect()
+        }
+        Ok((new_page, overflow_items))
     }
 
     fn get_free_space(&self) -> usize {
         self.free_space_end - self.free_space_start
     }
 
-    pub fn add_item(&mut self, item: &Item) -> Result<(), PagingError> {
+    /**
+    * Copy the item data right before the items already in the page, returning its offset. There
+    * must be enough free space for it
+    */
+    fn push_item_data(&mut self, item_data: &[u8]) -> usize {
+        let item_offset = self.free_space_end - item_data.len();
+        copy_bytes_into(&mut self.data, item_data, item_offset);
+        self.free_space_end = item_offset;
+        item_offset
+    }
+
+    /**
+    * Append an entry to the slot directory. There must be enough free space for it
+    */
+    fn push_slot(&mut self, (offset, size): (usize, usize)) {
+        copy_bytes_into(&mut self.data, &offset.to_be_bytes(), self.free_space_start);
+        copy_bytes_into(&mut self.data, &size.to_be_bytes(), self.free_space_start + USIZE_SIZE);
+        self.free_space_start += USIZE_SIZE*2;
+    }
+
+    /**
+    * Add an item to the page, reusing the slot of a deleted item if there is one. Returns the slot
+    * of the item
+    */
+    pub fn add_item(&mut self, item: &Item) -> Result<usize, PagingError> {
+        self.add_item_with_fill_factor(item, 100)
+    }
+
+    /**
+    * Add an item to the page like add_item, but only while the page stays filled up to fill_factor
+    * percent of its space, so the rest is left for updates that grow the items already in it. An empty
+    * page takes any item that fits, so items larger than the fill factor allows can still be stored
+    */
+    pub fn add_item_with_fill_factor(&mut self, item: &Item, fill_factor: u8) -> Result<usize, PagingError> {
         let item_data = item.to_page_data();
-        let item_size = item_data.len();
-        if item_size + USIZE_SIZE*2 > self.get_free_space() {
-            Err(PagingError::NotEnoughSpace)
+        let free_slot = self.validate_header().ok()
+            .and_then(|_| self.slots().position(|slot| slot == TOMBSTONE));
+        let slot_size = if free_slot.is_some() { 0 } else { USIZE_SIZE*2 };
+        let reserved_space = if self.free_space_start == 0 {
+            0
         } else {
-            let item_offset = &self.free_space_end - item_size;
-            copy_bytes_into(&mut self.data, &item_offset.to_be_bytes(), self.free_space_start);
-            copy_bytes_into(&mut self.data, &item_size.to_be_bytes(), self.free_space_start + USIZE_SIZE);
-            self.free_space_start += USIZE_SIZE*2;
-            copy_bytes_into(&mut self.data, item_data.as_slice(), item_offset);
-            self.free_space_end = item_offset;
-            Ok(())
+            PAGE_DATA_SIZE * (100 - fill_factor.min(100) as usize) / 100
+        };
+        if item_data.len() + slot_size + reserved_space > self.get_free_space() {
+            return Err(PagingError::NotEnoughSpace)
+        }
+        let item_offset = self.push_item_data(&item_data);
+        match free_slot {
+            Some(slot) => {
+                let slot_offset = slot * USIZE_SIZE*2;
+                copy_bytes_into(&mut self.data, &item_offset.to_be_bytes(), slot_offset);
+                copy_bytes_into(&mut self.data, &item_data.len().to_be_bytes(), slot_offset + USIZE_SIZE);
+                Ok(slot)
+            },
+            None => {
+                self.push_slot((item_offset, item_data.len()));
+                Ok(self.free_space_start / (USIZE_SIZE*2) - 1)
+            },
+        }
+    }
+}
+
+// Set in the stored number of fields of items whose string lengths are encoded as varints. Items
+// written before varints were introduced store every length as a full usize and don't have it set
+const VARINT_LENGTHS_FLAG: usize = 1 << (USIZE_SIZE * 8 - 1);
+// A u64 needs at most 10 groups of 7 bits
+const MAX_VARINT_SIZE: usize = 10;
+
+/**
+* Encode the value in groups of 7 bits, least significant first, where the high bit of each byte
+* tells whether more bytes follow. Lengths under 128 take a single byte
+*/
+fn encode_varint(mut value: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes
         }
+        bytes.push(byte | 0x80);
     }
 }
 
+/**
+* Decode a varint from the start of bytes, returning the value and the number of bytes it took
+*/
+fn decode_varint(bytes: &[u8]) -> Result<(usize, usize), PagingError> {
+    let mut value: usize = 0;
+    for (idx, byte) in bytes.iter().take(MAX_VARINT_SIZE).enumerate() {
+        let group = (*byte & 0x7f) as usize;
+        let shift = idx * 7;
+        if shift >= USIZE_SIZE * 8 || (group << shift) >> shift != group {
+            return Err(PagingError::CorruptedData(String::from("Varint length overflows usize")))
+        }
+        value |= group << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, idx + 1))
+        }
+    }
+    Err(PagingError::CorruptedData(String::from("Truncated varint length")))
+}
+
 #[derive(Clone, Debug)]
 pub struct Item {
     number_of_fields: usize,
+    // Whether the string lengths are varints or full usize values
+    varint_lengths: bool,
     null_map: BitVec,
     field_data: Vec<u8>,
 }
@@ -135,44 +366,77 @@ impl Item {
     /**
     * Build an item from page data
     */
-    pub fn from_page_data(page_data: &[u8]) -> Item {
-        let number_of_fields = usize::from_be_bytes(page_data[0..USIZE_SIZE].try_into().unwrap());
+    pub fn from_page_data(page_data: &[u8]) -> Result<Item, PagingError> {
+        if page_data.len() < USIZE_SIZE {
+            return Err(PagingError::CorruptedData(String::from("Item is too short")))
+        }
+        let stored_number_of_fields = usize::from_be_bytes(page_data[0..USIZE_SIZE].try_into().unwrap());
+        let number_of_fields = stored_number_of_fields & !VARINT_LENGTHS_FLAG;
         let null_map_length = Item::get_null_map_length(&number_of_fields);
-        Item {
+        if null_map_length > page_data.len() - USIZE_SIZE {
+            return Err(PagingError::CorruptedData(
+                format!("Invalid number of fields {}", number_of_fields)))
+        }
+        Ok(Item {
             number_of_fields,
+            varint_lengths: stored_number_of_fields & VARINT_LENGTHS_FLAG != 0,
             null_map: BitVec::from_bytes(&page_data[USIZE_SIZE..USIZE_SIZE + null_map_length]),
             field_data: page_data[USIZE_SIZE + null_map_length..page_data.len()].to_vec(),
+        })
+    }
+
+    /**
+    * Get the number of fields stored in this item
+    */
+    pub fn get_number_of_fields(&self) -> usize {
+        self.number_of_fields
+    }
+
+    /**
+    * Get the bytes of the field that starts at offset, failing if they are out of the field data bounds
+    */
+    fn get_field_bytes(&self, offset: usize, size: usize) -> Result<&[u8], PagingError> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.field_data.len() => Ok(&self.field_data[offset..end]),
+            _ => Err(PagingError::CorruptedData(
+                format!("Field of size {} at offset {} exceeds the item data", size, offset)))
         }
     }
 
     /**
-    * Build an item from a record
+    * Build an item from a record of the table. The fields and their null map bits are ordered by
+    * column id, so the item still decodes after the columns of the table are reordered
     */
-    pub fn from_record(record: &asl::Record) -> Item {
+    pub fn from_record(table: &asl::Table, record: &asl::Record) -> Item {
         let number_of_fields = record.values.len();
         let mut null_map = BitVec::from_elem(number_of_fields, false);
         let mut field_data = Vec::new();
-        for (idx, value) in record.values.iter().enumerate() {
+        for (idx, position) in table.get_physical_order().into_iter().enumerate() {
+            let value = &record.values[position];
             let value_bytes = match value {
-                asl::Value::Str(s) => {
-                    let mut size_bytes = s.len().to_be_bytes().to_vec();
-                    size_bytes.extend(value.to_be_bytes());
+                asl::Value::Str(_) | asl::Value::Bytes(_) => {
+                    let value_bytes = value.to_be_bytes();
+                    let mut size_bytes = encode_varint(value_bytes.len());
+                    size_bytes.extend(value_bytes);
                     Some(size_bytes)
                 },
                 asl::Value::Int(_) => Some(value.to_be_bytes()),
                 asl::Value::Float(_) => Some(value.to_be_bytes()),
                 asl::Value::Bool(_) => Some(value.to_be_bytes()),
+                asl::Value::Timestamp(_) => Some(value.to_be_bytes()),
+                asl::Value::Date(_) => Some(value.to_be_bytes()),
                 asl::Value::Null => None,
             };
             if let Some(bytes) = value_bytes {
                 field_data.extend(bytes);
             }
-            if let asl::Value::Null = value {
+            if value.is_null() {
                 null_map.set(idx, true);
             }
         }
         Item {
             number_of_fields,
+            varint_lengths: true,
             null_map,
             field_data,
         }
@@ -183,48 +447,285 @@ impl Item {
     */
     pub fn to_page_data(&self) -> Vec<u8> {
         let mut page_data = Vec::new();
-        page_data.extend(&self.number_of_fields.to_be_bytes());
+        let stored_number_of_fields = if self.varint_lengths {
+            self.number_of_fields | VARINT_LENGTHS_FLAG
+        } else {
+            self.number_of_fields
+        };
+        page_data.extend(&stored_number_of_fields.to_be_bytes());
         page_data.extend(&self.null_map.to_bytes());
         page_data.extend(&self.field_data);
         page_data
     }
 
     /**
-    * Build a record from this item data
+    * Build a record from this item data, putting each field, stored in column id order, at the
+    * position of its column
     */
-    pub fn to_record(&self, table: &asl::Table) -> asl::Record {
-        let mut values = Vec::new();
+    pub fn to_record(&self, table: &asl::Table) -> Result<asl::Record, PagingError> {
+        let mut values = vec![asl::Value::Null; table.columns.len()];
         let mut offset = 0;
-        for (idx, column) in table.columns.iter().enumerate() {
+        for (idx, position) in table.get_physical_order().into_iter().enumerate() {
+            let column = &table.columns[position];
             let is_null_value = if let Some(is_null) = self.null_map.get(idx) {
                 is_null
             } else {
                 false
             };
-            if is_null_value {
-                values.push(asl::Value::Null);
-            } else {
+            if !is_null_value {
                 let size = match column.column_type {
-                    asl::Type::Str => {
-                        let size = usize::from_be_bytes(self.field_data[offset..offset + USIZE_SIZE].try_into().unwrap());
+                    asl::Type::Str | asl::Type::Bytes if self.varint_lengths => {
+                        let (size, size_length) = decode_varint(self.field_data.get(offset..).unwrap_or(&[]))?;
+                        offset += size_length;
+                        Some(size)
+                    },
+                    asl::Type::Str | asl::Type::Bytes => {
+                        let size_bytes = self.get_field_bytes(offset, USIZE_SIZE)?;
+                        let size = usize::from_be_bytes(size_bytes.try_into().unwrap());
                         offset += USIZE_SIZE;
                         Some(size)
                     },
                     asl::Type::Int => Some(mem::size_of::<i32>()),
                     asl::Type::Float => Some(mem::size_of::<f32>()),
                     asl::Type::Bool => Some(mem::size_of::<u8>()),
+                    asl::Type::Timestamp => Some(mem::size_of::<i64>()),
+                    asl::Type::Date => Some(mem::size_of::<i32>()),
                     _ => None,
                 };
                 if let Some(size) = size {
-                    let next_offset = offset + size;
-                    let bytes = &self.field_data[offset..next_offset];
-                    values.push(asl::Value::from_be_bytes(bytes.to_vec(),
-                                                          &column.column_type));
-                    offset = next_offset;
+                    let bytes = self.get_field_bytes(offset, size)?;
+                    values[position] = asl::Value::from_be_bytes(bytes.to_vec(), &column.column_type)?;
+                    offset += size;
                 }
             }
 
         }
-        asl::Record { values }
+        Ok(asl::Record { values })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(id: u32, name: &str, column_type: asl::Type) -> asl::Column {
+        asl::Column { id, name: String::from(name), column_type, comment: None, default: None }
+    }
+
+    fn table() -> asl::Table {
+        asl::Table {
+            name: String::from("items"),
+            columns: vec![column(0, "id", asl::Type::Int), column(1, "name", asl::Type::Str)],
+            comment: None,
+            constraints: Vec::new(),
+        }
+    }
+
+    /**
+    * Build an item of the table with the given field data, where neither field is null
+    */
+    fn item(varint_lengths: bool, field_data: Vec<u8>) -> Item {
+        Item { number_of_fields: 2, varint_lengths, null_map: BitVec::from_elem(2, false), field_data }
+    }
+
+    /**
+    * Field data of the table with the id 7 followed by the given length bytes and string bytes
+    */
+    fn field_data(length_bytes: &[u8], string_bytes: &[u8]) -> Vec<u8> {
+        let mut field_data = 7i32.to_be_bytes().to_vec();
+        field_data.extend(length_bytes);
+        field_data.extend(string_bytes);
+        field_data
+    }
+
+    fn assert_corrupted<T: fmt::Debug>(result: Result<T, PagingError>) {
+        assert!(matches!(result, Err(PagingError::CorruptedData(_))), "Expected corrupted data, but got {:?}", result);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in &[0, 1, 127, 128, 300, 16_383, 16_384, usize::MAX >> 1, usize::MAX] {
+            let bytes = encode_varint(*value);
+            assert_eq!(decode_varint(&bytes).unwrap(), (*value, bytes.len()));
+        }
+    }
+
+    #[test]
+    fn truncated_varints_are_rejected() {
+        assert_corrupted(decode_varint(&[]));
+        assert_corrupted(decode_varint(&[0x80]));
+        let bytes = encode_varint(usize::MAX);
+        for length in 0..bytes.len() {
+            assert_corrupted(decode_varint(&bytes[..length]));
+        }
+    }
+
+    #[test]
+    fn oversized_varints_are_rejected() {
+        // Too many bytes for a usize, even if the extra ones are zero
+        assert_corrupted(decode_varint(&[0x80; MAX_VARINT_SIZE + 1]));
+        // The last group has bits past the end of a usize
+        let mut bytes = vec![0xff; MAX_VARINT_SIZE - 1];
+        bytes.push(0x7f);
+        assert_corrupted(decode_varint(&bytes));
+    }
+
+    #[test]
+    fn varint_lengths_past_the_field_data_are_rejected() {
+        let table = table();
+        assert_corrupted(item(true, field_data(&[0x80], &[])).to_record(&table));
+        assert_corrupted(item(true, field_data(&encode_varint(10), b"abc")).to_record(&table));
+        assert_corrupted(item(true, field_data(&encode_varint(usize::MAX), b"abc")).to_record(&table));
+        assert_corrupted(item(true, field_data(&[], &[])).to_record(&table));
+    }
+
+    #[test]
+    fn legacy_lengths_past_the_field_data_are_rejected() {
+        let table = table();
+        assert_corrupted(item(false, field_data(&10usize.to_be_bytes(), b"abc")).to_record(&table));
+        assert_corrupted(item(false, field_data(&usize::MAX.to_be_bytes(), b"abc")).to_record(&table));
+        // The length itself is cut short
+        assert_corrupted(item(false, field_data(&3usize.to_be_bytes()[..USIZE_SIZE - 1], &[])).to_record(&table));
+        let record = item(false, field_data(&3usize.to_be_bytes(), b"abc")).to_record(&table).unwrap();
+        assert!(matches!(&record.values[1], asl::Value::Str(value) if value == "abc"));
+    }
+
+    fn record(id: i32, name: &str) -> asl::Record {
+        asl::Record { values: vec![asl::Value::Int(id), asl::Value::Str(String::from(name))] }
+    }
+
+    fn get_record(page: &Page, slot: usize) -> Option<asl::Record> {
+        let (offset, size) = page.get_item_offset_and_sizes()[slot]?;
+        Some(page.get_item(offset, size).unwrap().to_record(&table()).unwrap())
+    }
+
+    #[test]
+    fn empty_strings_and_nulls_round_trip_distinctly() {
+        let table = table();
+        let empty = asl::Value::Str(String::new());
+        let records = vec![
+            asl::Record { values: vec![asl::Value::Int(1), empty.clone()] },
+            asl::Record { values: vec![asl::Value::Int(2), asl::Value::Null] },
+            asl::Record { values: vec![asl::Value::Null, empty.clone()] },
+            asl::Record { values: vec![asl::Value::Null, asl::Value::Null] },
+            record(5, "x"),
+        ];
+        let mut page = Page::new(1);
+        for record in &records {
+            page.add_item(&Item::from_record(&table, record)).unwrap();
+        }
+        // Through the stored bytes of the page, like a table file
+        let page = Page::from_bytes(&page.to_bytes());
+        for (slot, record) in records.iter().enumerate() {
+            let stored_record = get_record(&page, slot).unwrap();
+            for (stored_value, value) in stored_record.values.iter().zip(&record.values) {
+                assert_eq!(stored_value.is_null(), value.is_null(), "Slot {}: {:?} became {:?}", slot, value, stored_value);
+            }
+            assert_eq!(stored_record, *record);
+        }
+        assert!(matches!(&get_record(&page, 0).unwrap().values[1], asl::Value::Str(value) if value.is_empty()));
+    }
+
+    #[test]
+    fn applying_changes_keeps_the_slots_and_reclaims_the_space() {
+        let table = table();
+        let mut page = Page::new(1);
+        for id in 0..4 {
+            page.add_item(&Item::from_record(&table, &record(id, &"x".repeat(100)))).unwrap();
+        }
+        let free_space = page.get_header().get_free_space();
+        let mut changes = HashMap::new();
+        changes.insert(1, None);
+        changes.insert(2, Some(Item::from_record(&table, &record(2, "short"))));
+        let (new_page, overflow_items) = page.apply_changes(&changes).unwrap();
+
+        assert!(overflow_items.is_empty());
+        assert_eq!(new_page.get_item_offset_and_sizes().len(), 4);
+        assert!(get_record(&new_page, 1).is_none());
+        assert_eq!(get_record(&new_page, 0), Some(record(0, &"x".repeat(100))));
+        assert_eq!(get_record(&new_page, 2), Some(record(2, "short")));
+        assert_eq!(get_record(&new_page, 3), Some(record(3, &"x".repeat(100))));
+        assert!(new_page.get_header().get_free_space() > free_space + 200);
+        // The items are packed at the end of the page again, so the rebuilt page has no gaps
+        let used_space: usize = new_page.get_item_offset_and_sizes().into_iter().flatten().map(|(_, size)| size).sum();
+        assert_eq!(new_page.get_header().get_free_space_end() + used_space, PAGE_DATA_SIZE);
+    }
+
+    #[test]
+    fn new_items_reuse_the_slots_of_deleted_ones() {
+        let table = table();
+        let mut page = Page::new(1);
+        for id in 0..3 {
+            page.add_item(&Item::from_record(&table, &record(id, "item"))).unwrap();
+        }
+        let mut changes = HashMap::new();
+        changes.insert(1, None);
+        let (mut page, _) = page.apply_changes(&changes).unwrap();
+        assert_eq!(page.add_item(&Item::from_record(&table, &record(3, "item"))).unwrap(), 1);
+        assert_eq!(get_record(&page, 2), Some(record(2, "item")));
+    }
+
+    /**
+    * Add copies of the item to a new page until it reports it's full, returning the page and the
+    * number of items it took
+    */
+    fn fill_page(item: &Item, fill_factor: u8) -> (Page, usize) {
+        let mut page = Page::new(1);
+        let mut items = 0;
+        loop {
+            match page.add_item_with_fill_factor(item, fill_factor) {
+                Ok(_) => items += 1,
+                Err(PagingError::NotEnoughSpace) => return (page, items),
+                Err(error) => panic!("Unexpected error {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn page_is_full_at_the_fill_factor() {
+        let item = Item::from_record(&table(), &record(1, &"x".repeat(100)));
+        let item_space = item.to_page_data().len() + USIZE_SIZE*2;
+        for fill_factor in &[50u8, 70, 90] {
+            let (page, items) = fill_page(&item, *fill_factor);
+            let used_space = PAGE_DATA_SIZE - page.get_header().get_free_space();
+            let allowed_space = PAGE_DATA_SIZE * *fill_factor as usize / 100;
+            assert!(used_space <= allowed_space, "{} bytes used at fill factor {}", used_space, fill_factor);
+            assert!(used_space + item_space > allowed_space, "The page took only {} items at fill factor {}", items, fill_factor);
+            // The space left is still there for add_item
+            let mut page = page;
+            assert!(page.add_item(&item).is_ok());
+        }
+        let (page, _) = fill_page(&item, 100);
+        assert!(page.get_header().get_free_space() < item_space);
+    }
+
+    #[test]
+    fn empty_page_takes_an_item_larger_than_the_fill_factor_allows() {
+        let item = Item::from_record(&table(), &record(1, &"x".repeat(PAGE_DATA_SIZE / 2)));
+        let (_, items) = fill_page(&item, 10);
+        assert_eq!(items, 1);
+    }
+
+    #[test]
+    fn truncated_and_mutated_items_never_panic() {
+        let table = table();
+        let record = asl::Record { values: vec![asl::Value::Int(7), asl::Value::Str("x".repeat(200))] };
+        let page_data = Item::from_record(&table, &record).to_page_data();
+        for length in 0..page_data.len() {
+            if let Ok(item) = Item::from_page_data(&page_data[..length]) {
+                assert!(item.to_record(&table).is_err(), "An item cut to {} bytes decoded", length);
+            }
+        }
+        // Overwrite a byte of the item with pseudo-random values, from a fixed seed so failures repeat
+        let mut seed: u32 = 12345;
+        for _ in 0..10_000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let mut mutated = page_data.clone();
+            let idx = (seed >> 8) as usize % mutated.len();
+            mutated[idx] = (seed >> 16) as u8;
+            if let Ok(item) = Item::from_page_data(&mutated) {
+                let _ = item.to_record(&table);
+            }
+        }
+    }
+}
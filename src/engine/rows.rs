@@ -0,0 +1,725 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use crate::engine::asl;
+use crate::engine::db::ROWID_COLUMN;
+use crate::engine::errors::QueryError;
+use crate::engine::fs::{evaluate_for_record, get_identifier_values};
+use crate::engine::join::unknown_qualified_all_error;
+use crate::engine::pages::RowId;
+use crate::engine::sort::SortedRecords;
+use crate::engine::storage::StorageEngine;
+
+/**
+* A row of a query result, with the names of the result columns to look values up by name
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    columns: Rc<Vec<String>>,
+    values: Vec<asl::Value>,
+}
+
+impl Row {
+    /**
+    * Wrap a record of the table, so its values can be looked up by column name
+    */
+    pub fn from_record(table: &asl::Table, record: asl::Record) -> Row {
+        let columns = table.columns.iter().map(|column| column.name.clone()).collect();
+        Row { columns: Rc::new(columns), values: record.values }
+    }
+
+    pub fn get_columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn get_values(&self) -> &[asl::Value] {
+        &self.values
+    }
+
+    /**
+    * Get the value of the named result column
+    */
+    pub fn get(&self, column: &str) -> Result<&asl::Value, QueryError> {
+        match self.columns.iter().position(|name| name == column) {
+            Some(idx) => Ok(&self.values[idx]),
+            None => Err(QueryError::ValidationError(format!("The column {} isn't in the result", column)))
+        }
+    }
+
+    /**
+    * Get the value of the named column converted by f, which returns None when the value doesn't have
+    * the expected type. Null values are returned as None
+    */
+    fn get_typed<'a, T>(&'a self, column: &str, expected_type: asl::Type,
+                        f: impl FnOnce(&'a asl::Value) -> Option<T>) -> Result<Option<T>, QueryError> {
+        let value = self.get(column)?;
+        if value.is_null() {
+            return Ok(None)
+        }
+        match f(value) {
+            Some(value) => Ok(Some(value)),
+            None => Err(QueryError::ValidationError(format!(
+                "The column {} is not {:?}, it's {:?}", column, expected_type, value.get_type())))
+        }
+    }
+
+    pub fn get_i32(&self, column: &str) -> Result<Option<i32>, QueryError> {
+        self.get_typed(column, asl::Type::Int, |value| match value {
+            asl::Value::Int(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    pub fn get_f32(&self, column: &str) -> Result<Option<f32>, QueryError> {
+        self.get_typed(column, asl::Type::Float, |value| match value {
+            asl::Value::Float(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    pub fn get_bool(&self, column: &str) -> Result<Option<bool>, QueryError> {
+        self.get_typed(column, asl::Type::Bool, |value| match value {
+            asl::Value::Bool(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    pub fn get_str(&self, column: &str) -> Result<Option<&str>, QueryError> {
+        self.get_typed(column, asl::Type::Str, |value| match value {
+            asl::Value::Str(value) => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn get_bytes(&self, column: &str) -> Result<Option<&[u8]>, QueryError> {
+        self.get_typed(column, asl::Type::Bytes, |value| match value {
+            asl::Value::Bytes(value) => Some(value.as_slice()),
+            _ => None,
+        })
+    }
+}
+
+/**
+* An ORDER BY key resolved against the result columns: a result column by its index, for keys that
+* give the position of the column, or an expression over the record and the result columns
+*/
+#[derive(Debug, Clone)]
+enum SortKey {
+    Column(usize),
+    Expression(Box<asl::Expression>),
+}
+
+impl SortKey {
+    fn evaluate(&self, identifier_values: &HashMap<String, asl::Value>,
+                output: &asl::Record) -> Result<asl::Value, QueryError> {
+        match self {
+            SortKey::Column(idx) => Ok(output.values[*idx].clone()),
+            SortKey::Expression(expression) => expression.evaluate(Some(identifier_values)),
+        }
+    }
+}
+
+/**
+* The result columns of a SELECT resolved against its table: their names and the expressions that
+* compute them from each record
+*/
+#[derive(Debug, Clone)]
+pub struct Projection {
+    columns: Vec<String>,
+    expressions: Vec<asl::SelectExpression>,
+    group_by: Vec<Box<asl::Expression>>,
+    distinct: bool,
+    // Each key with whether it sorts in descending order
+    order_by: Vec<(SortKey, bool)>,
+}
+
+impl Projection {
+    /**
+    * Resolve the selected columns, checking that they only reference columns of the table. Without a
+    * table, for a SELECT without FROM, they can't reference any column. When there are aggregates or
+    * GROUP BY, the other expressions can only reference the columns the records are grouped by, as
+    * there is no single record to take the values of other columns from
+    */
+    pub fn new(table: Option<&asl::Table>, columns: &asl::SelectColumns,
+               group_by: &[Box<asl::Expression>]) -> Result<Projection, QueryError> {
+        let items = match (columns, table) {
+            (asl::SelectColumns::All, Some(table)) => table.columns.iter()
+                .map(|column| asl::SelectItem {
+                    expression: asl::SelectExpression::Expression(Box::new(asl::Expression::Identifier(column.name.clone()))),
+                    alias: None,
+                })
+                .collect(),
+            (asl::SelectColumns::All, None) => return Err(QueryError::ValidationError(
+                String::from("SELECT * needs a FROM clause"))),
+            (asl::SelectColumns::Items(items), _) => {
+                let mut expanded_items = Vec::new();
+                for item in items {
+                    match (&item.expression, table) {
+                        (asl::SelectExpression::QualifiedAll(name), Some(table)) if *name == table.name =>
+                            expanded_items.extend(table.columns.iter().map(|column| asl::SelectItem {
+                                expression: asl::SelectExpression::Expression(Box::new(
+                                    asl::Expression::Identifier(column.name.clone()))),
+                                alias: None,
+                            })),
+                        (asl::SelectExpression::QualifiedAll(name), _) => return Err(unknown_qualified_all_error(name)),
+                        _ => expanded_items.push(item.clone()),
+                    }
+                }
+                expanded_items
+            },
+        };
+        for expression in group_by {
+            for identifier in expression.get_identifiers() {
+                match table {
+                    Some(table) if table.get_column(identifier).is_some() => (),
+                    Some(table) => return Err(QueryError::ValidationError(
+                        format!("The column {} of GROUP BY doesn't exist in {}", identifier, table.name))),
+                    None => return Err(QueryError::ValidationError(String::from("GROUP BY needs a FROM clause"))),
+                }
+            }
+        }
+        let grouped_columns: Vec<&String> = group_by.iter()
+            .filter_map(|expression| match expression.as_ref() {
+                asl::Expression::Identifier(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let is_aggregate = !group_by.is_empty() || items.iter().any(|item| item.expression.is_aggregate());
+        for item in &items {
+            if let Some(expression) = item.expression.get_aggregated_expression() {
+                for identifier in expression.get_identifiers() {
+                    match table {
+                        Some(table) if table.get_column(identifier).is_some() || identifier == ROWID_COLUMN => (),
+                        Some(table) => return Err(QueryError::ValidationError(
+                            format!("The column {} doesn't exist in {}", identifier, table.name))),
+                        None => return Err(QueryError::ValidationError(
+                            format!("The column {} can't be selected without a FROM clause", identifier))),
+                    }
+                }
+            }
+            if let asl::SelectExpression::Expression(expression) = &item.expression {
+                for identifier in expression.get_identifiers() {
+                    let table = match table {
+                        Some(table) => table,
+                        None => return Err(QueryError::ValidationError(
+                            format!("The column {} can't be selected without a FROM clause", identifier))),
+                    };
+                    if table.get_column(identifier).is_none() && identifier != ROWID_COLUMN {
+                        return Err(QueryError::ValidationError(
+                            format!("The column {} doesn't exist in {}", identifier, table.name)))
+                    }
+                    if is_aggregate && group_by.is_empty() {
+                        return Err(QueryError::ValidationError(format!(
+                            "The column {} must be used in an aggregate function, as there is no GROUP BY",
+                            identifier)))
+                    }
+                    if is_aggregate && !grouped_columns.contains(&identifier) {
+                        return Err(QueryError::ValidationError(format!(
+                            "The column {} must be in GROUP BY or used in an aggregate function", identifier)))
+                    }
+                }
+            }
+        }
+        Ok(Projection {
+            columns: items.iter().map(|item| item.get_name()).collect(),
+            expressions: items.into_iter().map(|item| item.expression).collect(),
+            group_by: group_by.to_vec(),
+            distinct: false,
+            order_by: Vec::new(),
+        })
+    }
+
+    /**
+    * Drop the result rows equal to an earlier one, with NULLs equal to each other
+    */
+    pub fn with_distinct(mut self, distinct: bool) -> Projection {
+        self.distinct = distinct;
+        self
+    }
+
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /**
+    * Sort the result rows by the ORDER BY keys. They can reference the result columns by name or by
+    * their position, starting at 1, and unless the projection aggregates the records, the columns of
+    * the table. The single result row of a SELECT without FROM isn't sorted
+    */
+    pub fn with_order_by(mut self, table: Option<&asl::Table>, order_by: &[asl::OrderBy]) -> Result<Projection, QueryError> {
+        let mut sort_keys = Vec::new();
+        for key in order_by {
+            if let asl::Expression::Value(asl::Value::Int(position)) = key.expression.as_ref() {
+                if *position < 1 || *position as usize > self.columns.len() {
+                    return Err(QueryError::ValidationError(format!(
+                        "ORDER BY position {} is out of range, it must be between 1 and {}", position, self.columns.len())))
+                }
+                sort_keys.push((SortKey::Column(*position as usize - 1), key.descending));
+                continue
+            }
+            for identifier in key.expression.get_identifiers() {
+                if self.columns.iter().any(|column| column == identifier) {
+                    continue
+                }
+                match table {
+                    Some(table) if !self.is_aggregate() => {
+                        if table.get_column(identifier).is_none() && identifier != ROWID_COLUMN {
+                            return Err(QueryError::ValidationError(
+                                format!("The column {} of ORDER BY doesn't exist in {}", identifier, table.name)))
+                        }
+                    },
+                    _ => return Err(QueryError::ValidationError(
+                        format!("The column {} of ORDER BY must be a result column", identifier))),
+                }
+            }
+            sort_keys.push((SortKey::Expression(key.expression.clone()), key.descending));
+        }
+        self.order_by = sort_keys;
+        Ok(self)
+    }
+
+    pub fn get_columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /**
+    * Test if the projection aggregates the records into a single row, or a row per group with GROUP BY
+    */
+    pub fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty() || self.expressions.iter().any(|expression| expression.is_aggregate())
+    }
+
+    /**
+    * Test if the result rows of a table scan have to be sorted as they are produced. The rows of an
+    * aggregation are sorted after it's finished
+    */
+    pub fn is_sorted(&self) -> bool {
+        !self.order_by.is_empty() && !self.is_aggregate()
+    }
+
+    pub fn get_sort_descending(&self) -> Vec<bool> {
+        self.order_by.iter().map(|(_, descending)| *descending).collect()
+    }
+
+    /**
+    * Compute the ORDER BY keys of a record and its result row. Result column names take precedence
+    * over the table columns with the same name
+    */
+    pub fn get_sort_keys(&self, table: &asl::Table, row_id: RowId, record: &asl::Record,
+                         output: &asl::Record) -> Result<Vec<asl::Value>, QueryError> {
+        let mut identifier_values = get_row_values(table, row_id, record);
+        identifier_values.extend(self.columns.iter().cloned().zip(output.values.iter().cloned()));
+        self.order_by.iter()
+            .map(|(key, _)| key.evaluate(&identifier_values, output))
+            .collect()
+    }
+
+    /**
+    * Sort the result rows of an aggregation by the ORDER BY keys, which can only reference result
+    * columns. There is a row per group at most, so they are sorted in memory
+    */
+    pub fn sort_aggregated(&self, records: Vec<asl::Record>) -> Result<Vec<asl::Record>, QueryError> {
+        if self.order_by.is_empty() {
+            return Ok(records)
+        }
+        let mut keyed_records = Vec::new();
+        for record in records {
+            let identifier_values: HashMap<String, asl::Value> =
+                self.columns.iter().cloned().zip(record.values.iter().cloned()).collect();
+            let keys = self.order_by.iter()
+                .map(|(key, _)| key.evaluate(&identifier_values, &record))
+                .collect::<Result<Vec<asl::Value>, QueryError>>()?;
+            keyed_records.push((keys, record));
+        }
+        keyed_records.sort_by(|(keys1, _), (keys2, _)| {
+            for ((key1, key2), (_, descending)) in keys1.iter().zip(keys2).zip(&self.order_by) {
+                let ordering = key1.sort_cmp(key2);
+                let ordering = if *descending { ordering.reverse() } else { ordering };
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering
+                }
+            }
+            std::cmp::Ordering::Equal
+        });
+        Ok(keyed_records.into_iter().map(|(_, record)| record).collect())
+    }
+
+    /**
+    * Get the type of each result column, checking the operand types with the given mode
+    */
+    pub fn get_types(&self, table: Option<&asl::Table>, mode: asl::TypeMode) -> Result<Vec<asl::Type>, QueryError> {
+        let mut column_types: HashMap<String, asl::Type> = HashMap::new();
+        if let Some(table) = table {
+            column_types.extend(table.columns.iter().map(|column| (column.name.clone(), column.column_type.clone())));
+            column_types.entry(String::from(ROWID_COLUMN)).or_insert(asl::Type::Str);
+        }
+        self.expressions.iter()
+            .map(|expression| match expression {
+                asl::SelectExpression::Expression(expression) => expression.infer_type(&column_types, mode),
+                asl::SelectExpression::CountAll => Ok(asl::Type::Int),
+                asl::SelectExpression::CountDistinct(expression) => {
+                    expression.infer_type(&column_types, mode)?;
+                    Ok(asl::Type::Int)
+                },
+                asl::SelectExpression::Min(argument) | asl::SelectExpression::Max(argument) => {
+                    let argument_type = argument.infer_type(&column_types, mode)?;
+                    if argument_type == asl::Type::Bool {
+                        return Err(unordered_aggregate_error(expression))
+                    }
+                    Ok(argument_type)
+                },
+                asl::SelectExpression::QualifiedAll(name) => Err(unknown_qualified_all_error(name)),
+            })
+            .collect()
+    }
+
+    /**
+    * Compute the result row of a record. Only valid for projections without aggregates
+    */
+    pub fn project(&self, table: &asl::Table, row_id: RowId, record: &asl::Record) -> Result<asl::Record, QueryError> {
+        let identifier_values = get_row_values(table, row_id, record);
+        let mut values = Vec::new();
+        for expression in &self.expressions {
+            values.push(match expression {
+                asl::SelectExpression::Expression(expression) => expression.evaluate(Some(&identifier_values))?,
+                expression => return Err(QueryError::ValidationError(
+                    format!("{} can't be computed for a single record", expression))),
+            });
+        }
+        Ok(asl::Record { values })
+    }
+
+    /**
+    * Compute the single result row of a SELECT without FROM, evaluating the expressions without a
+    * record. COUNT(*) counts that one row, COUNT(DISTINCT) its value unless it's NULL, and MIN and
+    * MAX are its value
+    */
+    pub fn evaluate(&self) -> Result<asl::Record, QueryError> {
+        let mut values = Vec::new();
+        for expression in &self.expressions {
+            values.push(match expression {
+                asl::SelectExpression::Expression(expression) => expression.evaluate(None)?,
+                asl::SelectExpression::CountAll => asl::Value::Int(1),
+                asl::SelectExpression::CountDistinct(expression) =>
+                    asl::Value::Int(if expression.evaluate(None)?.is_null() { 0 } else { 1 }),
+                asl::SelectExpression::Min(argument) | asl::SelectExpression::Max(argument) =>
+                    check_ordered(expression, argument.evaluate(None)?)?,
+                asl::SelectExpression::QualifiedAll(name) => return Err(unknown_qualified_all_error(name)),
+            });
+        }
+        Ok(asl::Record { values })
+    }
+
+    /**
+    * Start aggregating the records of the table into the result rows of the projection
+    */
+    pub fn aggregate<'a>(&'a self, table: &'a asl::Table) -> Aggregation<'a> {
+        Aggregation { projection: self, table, groups: Vec::new(), group_indexes: HashMap::new() }
+    }
+}
+
+/**
+* Values of the columns of a record by name, along with its rowid unless a column has that name
+*/
+fn get_row_values(table: &asl::Table, row_id: RowId, record: &asl::Record) -> HashMap<String, asl::Value> {
+    let mut identifier_values = get_identifier_values(table, record);
+    identifier_values.entry(String::from(ROWID_COLUMN)).or_insert_with(|| asl::Value::Str(row_id.to_string()));
+    identifier_values
+}
+
+fn unordered_aggregate_error(expression: &asl::SelectExpression) -> QueryError {
+    QueryError::ValidationError(format!("{} can't be computed over Bool values, as they aren't ordered", expression))
+}
+
+/**
+* Check that a value MIN or MAX is computed over can be ordered
+*/
+fn check_ordered(expression: &asl::SelectExpression, value: asl::Value) -> Result<asl::Value, QueryError> {
+    match value {
+        asl::Value::Bool(_) => Err(unordered_aggregate_error(expression)),
+        value => Ok(value),
+    }
+}
+
+/**
+* Running state of an expression of the projection in a group
+*/
+#[derive(Clone)]
+enum AggregateState {
+    // Expressions outside aggregates and COUNT(*), which are computed from the first record and the
+    // number of records of the group
+    Empty,
+    // The different non NULL values seen, for COUNT(DISTINCT)
+    Distinct(HashSet<asl::GroupKey>),
+    // The lowest or highest non NULL value seen, for MIN and MAX
+    Extreme(Option<asl::Value>),
+}
+
+impl AggregateState {
+    fn new(expression: &asl::SelectExpression) -> AggregateState {
+        match expression {
+            asl::SelectExpression::CountDistinct(_) => AggregateState::Distinct(HashSet::new()),
+            asl::SelectExpression::Min(_) | asl::SelectExpression::Max(_) => AggregateState::Extreme(None),
+            asl::SelectExpression::Expression(_) | asl::SelectExpression::CountAll
+                | asl::SelectExpression::QualifiedAll(_) => AggregateState::Empty,
+        }
+    }
+
+    /**
+    * Update the state with a record of the group. MIN and MAX compare the values with the order
+    * used to sort rows
+    */
+    fn add(&mut self, expression: &asl::SelectExpression,
+           identifier_values: &HashMap<String, asl::Value>) -> Result<(), QueryError> {
+        let argument = match expression.get_aggregated_expression() {
+            Some(argument) => argument,
+            None => return Ok(()),
+        };
+        let value = argument.evaluate(Some(identifier_values))?;
+        if value.is_null() {
+            return Ok(())
+        }
+        match self {
+            AggregateState::Distinct(values) => {
+                values.insert(asl::GroupKey(vec![value]));
+            },
+            AggregateState::Extreme(extreme) => {
+                let value = check_ordered(expression, value)?;
+                let wanted = match expression {
+                    asl::SelectExpression::Min(_) => std::cmp::Ordering::Less,
+                    _ => std::cmp::Ordering::Greater,
+                };
+                let is_extreme = match extreme {
+                    Some(extreme) => value.sort_cmp(extreme) == wanted,
+                    None => true,
+                };
+                if is_extreme {
+                    *extreme = Some(value);
+                }
+            },
+            AggregateState::Empty => (),
+        }
+        Ok(())
+    }
+}
+
+/**
+* Running state of a group of records
+*/
+struct Group {
+    // The first record of the group
+    record: asl::Record,
+    count: usize,
+    // The state of each expression of the projection
+    states: Vec<AggregateState>,
+}
+
+/**
+* Running state of the aggregates of a projection while the records are scanned. Records with the
+* same GROUP BY values, NULLs included, are counted in the same group. Without GROUP BY all the
+* records are a single group
+*/
+pub struct Aggregation<'a> {
+    projection: &'a Projection,
+    table: &'a asl::Table,
+    // The groups in the order they were first seen
+    groups: Vec<Group>,
+    group_indexes: HashMap<asl::GroupKey, usize>,
+}
+
+impl<'a> Aggregation<'a> {
+    pub fn add(&mut self, record: &asl::Record) -> Result<(), QueryError> {
+        let needs_values = !self.projection.group_by.is_empty() || self.projection.expressions.iter()
+            .any(|expression| expression.get_aggregated_expression().is_some());
+        let identifier_values = if needs_values { get_identifier_values(self.table, record) } else { HashMap::new() };
+        let key = asl::GroupKey(self.projection.group_by.iter()
+            .map(|expression| expression.evaluate(Some(&identifier_values)))
+            .collect::<Result<Vec<asl::Value>, QueryError>>()?);
+        let idx = match self.group_indexes.get(&key) {
+            Some(idx) => *idx,
+            None => {
+                self.group_indexes.insert(key, self.groups.len());
+                self.groups.push(Group {
+                    record: record.clone(),
+                    count: 0,
+                    states: self.projection.expressions.iter().map(AggregateState::new).collect(),
+                });
+                self.groups.len() - 1
+            },
+        };
+        let group = &mut self.groups[idx];
+        group.count += 1;
+        for (expression, state) in self.projection.expressions.iter().zip(&mut group.states) {
+            state.add(expression, &identifier_values)?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Build the result rows, one per group. The expressions outside aggregates can only reference
+    * grouped columns, so they are evaluated with the first record of the group. Without GROUP BY
+    * there is always a row, even if there were no records
+    */
+    pub fn finish(self) -> Result<Vec<asl::Record>, QueryError> {
+        if self.groups.is_empty() && self.projection.group_by.is_empty() {
+            let states: Vec<AggregateState> = self.projection.expressions.iter().map(AggregateState::new).collect();
+            return Ok(vec![self.get_group_record(HashMap::new(), 0, &states)?])
+        }
+        self.groups.iter()
+            .map(|group| self.get_group_record(get_identifier_values(self.table, &group.record), group.count, &group.states))
+            .collect()
+    }
+
+    fn get_group_record(&self, identifier_values: HashMap<String, asl::Value>, count: usize,
+                        states: &[AggregateState]) -> Result<asl::Record, QueryError> {
+        let mut values = Vec::new();
+        for (expression, state) in self.projection.expressions.iter().zip(states) {
+            values.push(match (expression, state) {
+                (asl::SelectExpression::Expression(expression), _) => expression.evaluate(Some(&identifier_values))?,
+                (asl::SelectExpression::CountAll, _) => asl::Value::Int(count as i32),
+                (_, AggregateState::Distinct(distinct_values)) => asl::Value::Int(distinct_values.len() as i32),
+                (_, AggregateState::Extreme(extreme)) => extreme.clone().unwrap_or(asl::Value::Null),
+                (_, AggregateState::Empty) => asl::Value::Null,
+            });
+        }
+        Ok(asl::Record { values })
+    }
+}
+
+fn get_seen_rows(projection: &Projection) -> Option<HashSet<asl::Record>> {
+    if projection.is_distinct() { Some(HashSet::new()) } else { None }
+}
+
+/**
+* Iterator over the rows of a SELECT that reads the table one page at a time, so only the rows of a
+* single page are held in memory. The iteration ends after the first error
+*/
+pub struct RowIterator<'a> {
+    // The storage and the table the rows are read from, or None when they are computed up front,
+    // e.g. for catalog tables and aggregates
+    source: Option<(&'a dyn StorageEngine, Cow<'a, asl::Table>)>,
+    condition: Option<Box<asl::Expression>>,
+    projection: Projection,
+    columns: Rc<Vec<String>>,
+    next_page_idx: usize,
+    pending: VecDeque<asl::Record>,
+    // Result rows merged from a sort as they are read, for queries with ORDER BY
+    sorted: Option<SortedRecords>,
+    // The rows returned so far, for SELECT DISTINCT
+    seen: Option<HashSet<asl::Record>>,
+    finished: bool,
+}
+
+impl<'a> RowIterator<'a> {
+    /**
+    * Iterate over the records of the table stored in the storage that match the condition
+    */
+    pub fn scan(storage: &'a dyn StorageEngine, table: Cow<'a, asl::Table>, condition: Option<Box<asl::Expression>>,
+                projection: Projection) -> RowIterator<'a> {
+        RowIterator {
+            source: Some((storage, table)),
+            condition,
+            seen: get_seen_rows(&projection),
+            columns: Rc::new(projection.get_columns().to_vec()),
+            projection,
+            next_page_idx: 0,
+            pending: VecDeque::new(),
+            sorted: None,
+            finished: false,
+        }
+    }
+
+    /**
+    * Iterate over result rows that are already computed
+    */
+    pub fn from_rows(projection: Projection, rows: Vec<asl::Record>) -> RowIterator<'a> {
+        RowIterator {
+            source: None,
+            condition: None,
+            columns: Rc::new(projection.get_columns().to_vec()),
+            projection,
+            next_page_idx: 0,
+            pending: rows.into_iter().collect(),
+            sorted: None,
+            seen: None,
+            finished: false,
+        }
+    }
+
+    /**
+    * Iterate over the result rows of a sort, reading them back from its runs one at a time
+    */
+    pub fn from_sorted(projection: Projection, sorted: SortedRecords) -> RowIterator<'a> {
+        RowIterator {
+            source: None,
+            condition: None,
+            seen: get_seen_rows(&projection),
+            columns: Rc::new(projection.get_columns().to_vec()),
+            projection,
+            next_page_idx: 0,
+            pending: VecDeque::new(),
+            sorted: Some(sorted),
+            finished: false,
+        }
+    }
+
+    /**
+    * Read the next page into the pending rows, keeping the records that match the condition, or the
+    * next row of the sort. Returns false when there are no rows left
+    */
+    fn read_next_page(&mut self) -> Result<bool, QueryError> {
+        if let Some(sorted) = &mut self.sorted {
+            return match sorted.next() {
+                Some(record) => {
+                    self.pending.push_back(record?);
+                    Ok(true)
+                },
+                None => Ok(false),
+            }
+        }
+        let (storage, table) = match &self.source {
+            Some(source) => source,
+            None => return Ok(false),
+        };
+        let page = match storage.read_page(table, self.next_page_idx)? {
+            Some(page) => page,
+            None => return Ok(false),
+        };
+        self.next_page_idx += 1;
+        for (slot, item) in page.items_iter()? {
+            let record = item?.to_record(table)?;
+            if evaluate_for_record(table, &record, &self.condition)? {
+                let row_id = RowId { page_id: page.id, slot };
+                self.pending.push_back(self.projection.project(table, row_id, &record)?);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<'a> Iterator for RowIterator<'a> {
+    type Item = Result<Row, QueryError>;
+
+    fn next(&mut self) -> Option<Result<Row, QueryError>> {
+        while !self.finished {
+            if let Some(record) = self.pending.pop_front() {
+                if let Some(seen) = &mut self.seen {
+                    if !seen.insert(record.clone()) {
+                        continue
+                    }
+                }
+                return Some(Ok(Row { columns: Rc::clone(&self.columns), values: record.values }))
+            }
+            match self.read_next_page() {
+                Ok(true) => (),
+                Ok(false) => self.finished = true,
+                Err(error) => {
+                    self.finished = true;
+                    return Some(Err(error))
+                }
+            }
+        }
+        None
+    }
+}
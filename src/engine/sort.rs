@@ -0,0 +1,320 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+
+use bincode;
+
+use crate::engine::asl;
+use crate::engine::errors::QueryError;
+
+// Numbers the run files, so concurrent sorts of the same process don't share a file name
+static NEXT_RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+// The sort keys of a record along with its values
+type SortEntry = (Vec<asl::Value>, Vec<asl::Value>);
+
+/**
+* Approximate memory used by the values, counting the inline size of each value and the bytes of
+* strings and binary data
+*/
+fn estimate_size(values: &[asl::Value]) -> usize {
+    values.iter()
+        .map(|value| mem::size_of::<asl::Value>() + match value {
+            asl::Value::Str(value) => value.len(),
+            asl::Value::Bytes(value) => value.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/**
+* Compare the keys of two entries, reversing the keys sorted in descending order
+*/
+fn compare_keys(keys1: &[asl::Value], keys2: &[asl::Value], descending: &[bool]) -> Ordering {
+    for ((key1, key2), descending) in keys1.iter().zip(keys2).zip(descending) {
+        let ordering = key1.sort_cmp(key2);
+        let ordering = if *descending { ordering.reverse() } else { ordering };
+        if ordering != Ordering::Equal {
+            return ordering
+        }
+    }
+    Ordering::Equal
+}
+
+fn sort_error(error: bincode::Error) -> QueryError {
+    QueryError::ValidationError(format!("Can't spill the rows being sorted: {}", error))
+}
+
+/**
+* Sort records by their ORDER BY keys keeping at most about memory_limit bytes of them in memory.
+* When the buffer grows past the limit, it's sorted and written to a run file in temp_dir, and the
+* runs are merged when the sorted records are read. Equal keys keep the order the records were
+* added in. The run files are removed when the sorter or its sorted records are dropped, so they
+* are cleaned up after errors too
+*/
+pub struct ExternalSorter {
+    descending: Vec<bool>,
+    memory_limit: usize,
+    temp_dir: PathBuf,
+    buffer: Vec<SortEntry>,
+    buffer_size: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter {
+    pub fn new(descending: Vec<bool>, memory_limit: usize, temp_dir: PathBuf) -> ExternalSorter {
+        ExternalSorter { descending, memory_limit, temp_dir, buffer: Vec::new(), buffer_size: 0, runs: Vec::new() }
+    }
+
+    pub fn add(&mut self, keys: Vec<asl::Value>, record: asl::Record) -> Result<(), QueryError> {
+        self.buffer_size += estimate_size(&keys) + estimate_size(&record.values);
+        self.buffer.push((keys, record.values));
+        if self.buffer_size > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Number of sorted runs written to disk so far
+    */
+    pub fn get_spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn sort_buffer(&mut self) {
+        let descending = &self.descending;
+        self.buffer.sort_by(|(keys1, _), (keys2, _)| compare_keys(keys1, keys2, descending));
+    }
+
+    /**
+    * Sort the buffer and write it to a new run file, each entry prefixed by its length
+    */
+    fn spill(&mut self) -> Result<(), QueryError> {
+        self.sort_buffer();
+        let path = self.temp_dir.join(format!(
+            "sort_{}_{}.tmp", std::process::id(), NEXT_RUN_ID.fetch_add(1, atomic::Ordering::SeqCst)));
+        // Registered before writing, so a partially written run is removed too
+        self.runs.push(path.clone());
+        let mut writer = BufWriter::new(fs::File::create(&path)?);
+        for entry in self.buffer.drain(..) {
+            let bytes = bincode::serialize(&entry).map_err(sort_error)?;
+            writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+            writer.write_all(&bytes)?;
+        }
+        writer.flush()?;
+        self.buffer_size = 0;
+        Ok(())
+    }
+
+    /**
+    * Get the records in order. Without spilled runs they are sorted in memory, otherwise the runs
+    * and the records still in the buffer are merged as they are read
+    */
+    pub fn finish(mut self) -> Result<SortedRecords, QueryError> {
+        self.sort_buffer();
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<SortEntry, QueryError>>>> = Vec::new();
+        // The runs stay with the sorter until every one is open, so they are removed if one can't be
+        for path in &self.runs {
+            sources.push(Box::new(RunReader { reader: BufReader::new(fs::File::open(path)?) }));
+        }
+        let runs = mem::replace(&mut self.runs, Vec::new());
+        sources.push(Box::new(mem::replace(&mut self.buffer, Vec::new()).into_iter().map(Ok)));
+        let mut sorted_records = SortedRecords {
+            descending: mem::replace(&mut self.descending, Vec::new()),
+            heads: Vec::new(),
+            sources,
+            runs,
+        };
+        for idx in 0..sorted_records.sources.len() {
+            let head = sorted_records.sources[idx].next().transpose()?;
+            sorted_records.heads.push(head);
+        }
+        Ok(sorted_records)
+    }
+}
+
+fn remove_runs(runs: &[PathBuf]) {
+    for path in runs {
+        match fs::remove_file(path) {
+            Ok(_) => (),
+            Err(ref error) if error.kind() == ErrorKind::NotFound => (),
+            Err(error) => eprintln!("Warning: couldn't remove the sort run {}: {}", path.display(), error),
+        }
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        remove_runs(&self.runs);
+    }
+}
+
+/**
+* Reads back the entries of a run file in the order they were written
+*/
+struct RunReader {
+    reader: BufReader<fs::File>,
+}
+
+impl RunReader {
+    fn read_entry(&mut self) -> Result<Option<SortEntry>, QueryError> {
+        let mut length_bytes = [0u8; 4];
+        match self.reader.read_exact(&mut length_bytes) {
+            Ok(_) => (),
+            Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+        let mut bytes = vec![0u8; u32::from_be_bytes(length_bytes) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(Some(bincode::deserialize(&bytes).map_err(sort_error)?))
+    }
+}
+
+impl Iterator for RunReader {
+    type Item = Result<SortEntry, QueryError>;
+
+    fn next(&mut self) -> Option<Result<SortEntry, QueryError>> {
+        self.read_entry().transpose()
+    }
+}
+
+/**
+* Iterator over the sorted records, merging the sorted runs. On equal keys the record of the earliest
+* run comes first, as runs are written in the order the records were added
+*/
+pub struct SortedRecords {
+    descending: Vec<bool>,
+    sources: Vec<Box<dyn Iterator<Item = Result<SortEntry, QueryError>>>>,
+    // Next entry of each source, None once the source is exhausted
+    heads: Vec<Option<SortEntry>>,
+    runs: Vec<PathBuf>,
+}
+
+impl Iterator for SortedRecords {
+    type Item = Result<asl::Record, QueryError>;
+
+    fn next(&mut self) -> Option<Result<asl::Record, QueryError>> {
+        let mut next_idx: Option<usize> = None;
+        for (idx, head) in self.heads.iter().enumerate() {
+            if let Some((keys, _)) = head {
+                let is_smaller = match next_idx.and_then(|next_idx| self.heads[next_idx].as_ref()) {
+                    Some((next_keys, _)) => compare_keys(keys, next_keys, &self.descending) == Ordering::Less,
+                    None => true,
+                };
+                if is_smaller {
+                    next_idx = Some(idx);
+                }
+            }
+        }
+        let next_idx = next_idx?;
+        let next_head = match self.sources[next_idx].next().transpose() {
+            Ok(next_head) => next_head,
+            Err(error) => {
+                self.heads.clear();
+                return Some(Err(error))
+            },
+        };
+        let (_, values) = mem::replace(&mut self.heads[next_idx], next_head)?;
+        Some(Ok(asl::Record { values }))
+    }
+}
+
+impl Drop for SortedRecords {
+    fn drop(&mut self) {
+        // The readers must be closed before removing their files on some platforms
+        self.sources.clear();
+        remove_runs(&self.runs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    static NEXT_DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+    /**
+    * Create an empty directory for the runs of a test
+    */
+    fn temp_dir() -> PathBuf {
+        let dir_id = NEXT_DIR_ID.fetch_add(1, atomic::Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("csbase-sort-test-{}-{}", process::id(), dir_id));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn run_files(dir: &PathBuf) -> Vec<PathBuf> {
+        fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect()
+    }
+
+    /**
+    * Sorter that spills to dir after a few records, with the records (key, position) added for the
+    * keys 9, 8, ..., 0 and then again, so every key has two records
+    */
+    fn spilled_sorter(dir: &PathBuf) -> ExternalSorter {
+        let mut sorter = ExternalSorter::new(vec![false], 4 * mem::size_of::<asl::Value>(), dir.clone());
+        for position in 0..20 {
+            let key = asl::Value::Int(9 - position % 10);
+            sorter.add(vec![key.clone()], asl::Record { values: vec![key, asl::Value::Int(position)] }).unwrap();
+        }
+        sorter
+    }
+
+    #[test]
+    fn small_memory_limit_spills_and_merges_the_runs() {
+        let dir = temp_dir();
+        let sorter = spilled_sorter(&dir);
+        assert!(sorter.get_spilled_runs() > 1, "Expected several runs, but got {}", sorter.get_spilled_runs());
+        assert_eq!(run_files(&dir).len(), sorter.get_spilled_runs());
+
+        let records: Vec<asl::Record> = sorter.finish().unwrap().collect::<Result<_, _>>().unwrap();
+        let expected: Vec<asl::Record> = (0..20)
+            .map(|idx| asl::Record { values: vec![asl::Value::Int(idx / 2), asl::Value::Int(9 - idx / 2 + idx % 2 * 10)] })
+            .collect();
+        assert_eq!(records, expected);
+        assert!(run_files(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn runs_are_removed_when_reading_a_run_fails() {
+        let dir = temp_dir();
+        let sorter = spilled_sorter(&dir);
+        for path in run_files(&dir) {
+            // A length prefix larger than the rest of the file
+            fs::write(path, [0xff; 8]).unwrap();
+        }
+        assert!(sorter.finish().is_err());
+        assert!(run_files(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn runs_are_removed_when_a_run_is_missing() {
+        let dir = temp_dir();
+        let sorter = spilled_sorter(&dir);
+        let runs = run_files(&dir);
+        fs::remove_file(&runs[runs.len() / 2]).unwrap();
+        assert!(sorter.finish().is_err());
+        assert!(run_files(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn runs_are_removed_when_the_sorted_records_are_dropped_early() {
+        let dir = temp_dir();
+        let mut sorted_records = spilled_sorter(&dir).finish().unwrap();
+        assert!(sorted_records.next().unwrap().is_ok());
+        drop(sorted_records);
+        assert!(run_files(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
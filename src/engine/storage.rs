@@ -0,0 +1,215 @@
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use crate::engine::asl;
+use crate::engine::db::DatabaseDefinition;
+use crate::engine::errors::{InsertError, QueryError, SystemError};
+use crate::engine::integrity::{IntegrityProblem, RepairReport};
+use crate::engine::pages::{Item, Page, RowId};
+
+/**
+* Changes to the items of a page by slot, where None deletes the item in that slot
+*/
+pub type PageChanges = HashMap<usize, Option<Item>>;
+
+/**
+* Returned by scan callbacks to tell whether the scan should go on to the next record
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanControl {
+    Continue,
+    Stop,
+}
+
+/**
+* Group the changes by the page they affect, sorted by page id
+*/
+fn group_by_page(changes: Vec<(RowId, Option<Item>)>) -> BTreeMap<u32, PageChanges> {
+    let mut page_changes: BTreeMap<u32, PageChanges> = BTreeMap::new();
+    for (row_id, item) in changes {
+        page_changes.entry(row_id.page_id).or_insert_with(HashMap::new).insert(row_id.slot, item);
+    }
+    page_changes
+}
+
+/**
+* Access to the concrete type of a storage, so the database can take back the parts of the
+* StagedStorage it installed for a commit
+*/
+pub trait AsAny {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/**
+* Storage backend used by the database to persist definitions and table records.
+* The query engine only talks to storage through this trait
+*/
+pub trait StorageEngine: Debug + AsAny {
+    /**
+    * Prepare the backend for use, e.g. creating the base directory
+    */
+    fn initialize(&self) -> Result<(), SystemError>;
+
+    /**
+    * Describe where the data is stored, e.g. the resolved base path of the database files
+    */
+    fn location(&self) -> String;
+
+    /**
+    * Directory for temporary files, like the runs of a sort that doesn't fit in memory
+    */
+    fn temp_path(&self) -> PathBuf {
+        std::env::temp_dir()
+    }
+
+    /**
+    * Persist any buffered writes. Backends that write through on every call have nothing to do
+    */
+    fn flush(&self) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    /**
+    * Read the stored database definition
+    */
+    fn load_definitions(&self) -> Result<DatabaseDefinition, QueryError>;
+
+    /**
+    * Store the database definition
+    */
+    fn store_definitions(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError>;
+
+    /**
+    * Create the storage for a new table
+    */
+    fn create_table_files(&self, table: &asl::Table) -> Result<(), QueryError>;
+
+    /**
+    * Delete the storage of a table
+    */
+    fn delete_table_files(&self, table: &asl::Table) -> Result<(), QueryError>;
+
+    /**
+    * Delete the files of every table in the definition and the definition itself. Files that don't
+    * belong to the database are left untouched
+    */
+    fn delete_database(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError>;
+
+    /**
+    * Get the names of the tables that have a data file in the storage, whether they are defined or not
+    */
+    fn list_table_files(&self) -> Result<Vec<String>, QueryError>;
+
+    /**
+    * Insert a record into the table
+    */
+    fn insert_record(&self, table: &asl::Table, record: &asl::Record) -> Result<(), QueryError>;
+
+    /**
+    * Insert a batch of records into the table, in order. On failure the error tells how many of the
+    * first records were stored
+    */
+    fn insert_records(&self, table: &asl::Table, records: &[asl::Record]) -> Result<(), InsertError> {
+        for (written_records, record) in records.iter().enumerate() {
+            self.insert_record(table, record).map_err(|error| InsertError { written_records, error })?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Call f with the row id of every record in the table that matches the given condition, in
+    * storage order. Records are not accumulated, and the scan ends as soon as f returns
+    * ScanControl::Stop or an error
+    */
+    fn scan_records(&self, table: &asl::Table, condition: &Option<Box<asl::Expression>>,
+                    f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError>;
+
+    /**
+    * Find records in the table that match the given condition, along with their row ids
+    */
+    fn select_records(&self, table: &asl::Table, _columns: &Vec<String>,
+                      condition: &Option<Box<asl::Expression>>) -> Result<Vec<(RowId, asl::Record)>, QueryError> {
+        let mut records = Vec::new();
+        self.scan_records(table, condition, &mut |row_id, record| {
+            records.push((row_id, record));
+            Ok(ScanControl::Continue)
+        })?;
+        Ok(records)
+    }
+
+    /**
+    * Apply the changes to the pages of the table. Only the pages in page_changes are read and written.
+    * Items that no longer fit in their page are moved to the end of the table
+    */
+    fn apply_page_changes(&self, table: &asl::Table, page_changes: BTreeMap<u32, PageChanges>) -> Result<(), QueryError>;
+
+    /**
+    * Replace the records at the given row ids
+    */
+    fn update_records(&self, table: &asl::Table, updates: &[(RowId, asl::Record)]) -> Result<(), QueryError> {
+        let changes = updates.iter().map(|(row_id, record)| (*row_id, Some(Item::from_record(table, record)))).collect();
+        self.apply_page_changes(table, group_by_page(changes))
+    }
+
+    /**
+    * Delete the records at the given row ids
+    */
+    fn delete_records(&self, table: &asl::Table, row_ids: &[RowId]) -> Result<(), QueryError> {
+        let changes = row_ids.iter().map(|row_id| (*row_id, None)).collect();
+        self.apply_page_changes(table, group_by_page(changes))
+    }
+
+    /**
+    * Replace every page of the table with the given ones at once, like when the pages a transaction
+    * wrote are installed
+    */
+    fn replace_pages(&self, table: &asl::Table, pages: &[Page]) -> Result<(), QueryError>;
+
+    /**
+    * Replace the pages of several tables like replace_pages, when a transaction that wrote all of them
+    * is committed. Storages that can fail while writing should leave every table as it was if any of
+    * them can't be replaced
+    */
+    fn replace_tables_pages(&self, tables: &[(&asl::Table, Vec<Page>)]) -> Result<(), QueryError> {
+        for (table, pages) in tables {
+            self.replace_pages(table, pages)?;
+        }
+        Ok(())
+    }
+
+    /**
+    * Read the page at page_idx, counting from 0, or None past the last page of the table
+    */
+    fn read_page(&self, table: &asl::Table, page_idx: usize) -> Result<Option<Page>, QueryError>;
+
+    /**
+    * Check that the table can be read: its files exist and its first page, if any, decodes
+    */
+    fn check_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        if let Some(page) = self.read_page(table, 0)? {
+            for (_, item) in page.items_iter()? {
+                item?.to_record(table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    * Scan every page of the table and report the pages and items that can't be decoded.
+    * Nothing is modified
+    */
+    fn check_table(&self, table: &asl::Table) -> Result<Vec<IntegrityProblem>, QueryError>;
+
+    /**
+    * Rebuild the table keeping only the records that can be decoded, skipping broken pages and items
+    */
+    fn repair_table(&self, table: &asl::Table) -> Result<RepairReport, QueryError>;
+}
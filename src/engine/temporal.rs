@@ -0,0 +1,172 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::engine::errors::QueryError;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const MICROS_PER_DAY: i64 = SECONDS_PER_DAY * MICROS_PER_SECOND;
+
+/**
+* Get the current wall-clock time as microseconds since the Unix epoch, in UTC
+*/
+pub fn now_micros() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_micros() as i64,
+        Err(error) => -(error.duration().as_micros() as i64),
+    }
+}
+
+/**
+* Count the days from 1970-01-01 to the given date of the proleptic Gregorian calendar
+*/
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/**
+* Get the year, month and day of the date that is the given number of days from 1970-01-01
+*/
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/**
+* Get the date of a timestamp, as days since 1970-01-01
+*/
+pub fn date_of_timestamp(micros: i64) -> i32 {
+    micros.div_euclid(MICROS_PER_DAY) as i32
+}
+
+/**
+* Get the timestamp of the midnight that starts the date
+*/
+pub fn timestamp_of_date(days: i32) -> i64 {
+    days as i64 * MICROS_PER_DAY
+}
+
+/**
+* Format a date as YYYY-MM-DD
+*/
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/**
+* Format a timestamp as YYYY-MM-DD HH:MM:SS, followed by the microseconds when there are any
+*/
+pub fn format_timestamp(micros: i64) -> String {
+    let seconds = micros.div_euclid(MICROS_PER_SECOND);
+    let fraction = micros.rem_euclid(MICROS_PER_SECOND);
+    let second_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+    let mut text = format!("{} {:02}:{:02}:{:02}", format_date(date_of_timestamp(micros)),
+                           second_of_day / 3600, second_of_day % 3600 / 60, second_of_day % 60);
+    if fraction > 0 {
+        text.push_str(&format!(".{:06}", fraction));
+    }
+    text
+}
+
+/**
+* Parse the digits of a date or time component, which must have exactly the given length
+*/
+fn parse_component(text: &str, length: usize) -> Option<i64> {
+    if text.len() != length || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None
+    }
+    text.parse().ok()
+}
+
+/**
+* Parse a date like 2024-01-31 into the days since 1970-01-01
+*/
+fn parse_date_days(text: &str) -> Option<i64> {
+    let mut parts = text.splitn(3, '-');
+    let year = parse_component(parts.next()?, 4)?;
+    let month = parse_component(parts.next()?, 2)?;
+    let day = parse_component(parts.next()?, 2)?;
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return None
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+/**
+* Parse a time like 13:45:00 or 13:45:00.25 into microseconds since midnight
+*/
+fn parse_time(text: &str) -> Option<i64> {
+    let (time, fraction) = match text.find('.') {
+        Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+        None => (text, None),
+    };
+    let mut parts = time.splitn(3, ':');
+    let hour = parse_component(parts.next()?, 2)?;
+    let minute = parse_component(parts.next()?, 2)?;
+    let second = parse_component(parts.next()?, 2)?;
+    if hour > 23 || minute > 59 || second > 59 {
+        return None
+    }
+    let micros = match fraction {
+        Some(fraction) if fraction.len() >= 1 && fraction.len() <= 6 =>
+            parse_component(fraction, fraction.len())? * 10i64.pow(6 - fraction.len() as u32),
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(((hour * 60 + minute) * 60 + second) * MICROS_PER_SECOND + micros)
+}
+
+/**
+* Parse a timestamp written as YYYY-MM-DD, YYYY-MM-DD HH:MM:SS or with up to 6 fractional digits of
+* seconds. A T can separate the date and the time. Timestamps are always in UTC
+*/
+pub fn parse_timestamp(text: &str) -> Result<i64, QueryError> {
+    let text = text.trim();
+    let invalid_timestamp = || QueryError::ValidationError(format!("Invalid timestamp '{}'", text));
+    let (date, time) = match text.find(|c| c == ' ' || c == 'T') {
+        Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+        None => (text, None),
+    };
+    let days = parse_date_days(date).ok_or_else(invalid_timestamp)?;
+    let micros = match time {
+        Some(time) => parse_time(time).ok_or_else(invalid_timestamp)?,
+        None => 0,
+    };
+    Ok(days * MICROS_PER_DAY + micros)
+}
+
+/**
+* Parse a date written as YYYY-MM-DD into the days since 1970-01-01
+*/
+pub fn parse_date(text: &str) -> Result<i32, QueryError> {
+    match parse_date_days(text.trim()) {
+        Some(days) if days >= i32::min_value() as i64 && days <= i32::max_value() as i64 => Ok(days as i32),
+        _ => Err(QueryError::ValidationError(format!("Invalid date '{}'", text.trim()))),
+    }
+}
@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+use std::path::PathBuf;
+
+use crate::engine::asl;
+use crate::engine::db::DatabaseDefinition;
+use crate::engine::errors::{InsertError, QueryError, SystemError};
+use crate::engine::integrity::{IntegrityProblem, RepairReport};
+use crate::engine::memory::InMemoryStorage;
+use crate::engine::pages::{Page, RowId};
+use crate::engine::storage::{PageChanges, ScanControl, StorageEngine};
+
+/**
+* A transaction that defers writes: data modification statements are staged in order and only applied
+* to the storage on commit. Reads inside the transaction see the committed data only
+*/
+#[derive(Debug)]
+pub struct Transaction {
+    staged_queries: Vec<asl::Query>,
+    // Savepoint names along with the number of queries staged when each one was set
+    savepoints: Vec<(String, usize)>,
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction { staged_queries: Vec::new(), savepoints: Vec::new() }
+    }
+
+    pub fn stage(&mut self, query: asl::Query) {
+        self.staged_queries.push(query);
+    }
+
+    pub fn get_staged_count(&self) -> usize {
+        self.staged_queries.len()
+    }
+
+    /**
+    * Consume the transaction, returning the staged queries in the order they must be applied
+    */
+    pub fn into_staged_queries(self) -> Vec<asl::Query> {
+        self.staged_queries
+    }
+
+    /**
+    * Mark the current position of the staging buffer. Setting an existing savepoint name again moves
+    * it to the current position
+    */
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints.retain(|(savepoint_name, _)| savepoint_name != name);
+        self.savepoints.push((String::from(name), self.staged_queries.len()));
+    }
+
+    fn get_savepoint_idx(&self, name: &str) -> Result<usize, QueryError> {
+        match self.savepoints.iter().rposition(|(savepoint_name, _)| savepoint_name == name) {
+            Some(idx) => Ok(idx),
+            None => Err(QueryError::NotFound(format!("Savepoint {}", name)))
+        }
+    }
+
+    /**
+    * Discard the queries staged after the savepoint, along with the savepoints set after it. The
+    * savepoint itself is kept, so it can be rolled back to again
+    */
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<usize, QueryError> {
+        let idx = self.get_savepoint_idx(name)?;
+        let staged_count = self.savepoints[idx].1;
+        let discarded = self.staged_queries.len() - staged_count;
+        self.staged_queries.truncate(staged_count);
+        self.savepoints.truncate(idx + 1);
+        Ok(discarded)
+    }
+
+    /**
+    * Remove the savepoint and the ones set after it, keeping all the staged queries
+    */
+    pub fn release_savepoint(&mut self, name: &str) -> Result<(), QueryError> {
+        let idx = self.get_savepoint_idx(name)?;
+        self.savepoints.truncate(idx);
+        Ok(())
+    }
+}
+
+/**
+* Storage a transaction is committed through, so none of its writes reach the underlying storage until
+* all its queries succeeded. The first write to a table copies its pages to the staged storage, and
+* from then on the table is read and written there. Tables that aren't written are read from the
+* underlying storage. Schema changes aren't allowed in a transaction, so they are rejected
+*/
+#[derive(Debug)]
+pub struct StagedStorage {
+    storage: Box<dyn StorageEngine>,
+    staged: InMemoryStorage,
+    // Tables whose pages were copied to the staged storage
+    staged_tables: RefCell<HashSet<String>>,
+}
+
+impl StagedStorage {
+    pub fn new(storage: Box<dyn StorageEngine>, staged: InMemoryStorage) -> StagedStorage {
+        StagedStorage { storage, staged, staged_tables: RefCell::new(HashSet::new()) }
+    }
+
+    /**
+    * Take the underlying storage and the staged pages out when the transaction ends, leaving empty
+    * in-memory storages behind. Nothing can be staged afterwards
+    */
+    pub fn take_parts(&mut self) -> (Box<dyn StorageEngine>, InMemoryStorage) {
+        self.staged_tables.borrow_mut().clear();
+        let storage = mem::replace(&mut self.storage, Box::new(InMemoryStorage::new()));
+        (storage, mem::replace(&mut self.staged, InMemoryStorage::new()))
+    }
+
+    fn is_staged(&self, table: &asl::Table) -> bool {
+        self.staged_tables.borrow().contains(&table.name)
+    }
+
+    /**
+    * Get the storage the table is read from
+    */
+    fn get_storage(&self, table: &asl::Table) -> &dyn StorageEngine {
+        if self.is_staged(table) { &self.staged } else { self.storage.as_ref() }
+    }
+
+    /**
+    * Copy the pages of the table to the staged storage before its first write
+    */
+    fn stage_table(&self, table: &asl::Table) -> Result<&dyn StorageEngine, QueryError> {
+        if !self.is_staged(table) {
+            let mut pages = Vec::new();
+            while let Some(page) = self.storage.read_page(table, pages.len())? {
+                pages.push(page);
+            }
+            self.staged.replace_pages(table, &pages)?;
+            self.staged_tables.borrow_mut().insert(table.name.clone());
+        }
+        Ok(&self.staged)
+    }
+}
+
+fn schema_change_error() -> QueryError {
+    QueryError::ValidationError(String::from("Schema changes are not allowed inside a transaction"))
+}
+
+impl StorageEngine for StagedStorage {
+    fn initialize(&self) -> Result<(), SystemError> {
+        self.storage.initialize()
+    }
+
+    fn location(&self) -> String {
+        self.storage.location()
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        self.storage.temp_path()
+    }
+
+    fn load_definitions(&self) -> Result<DatabaseDefinition, QueryError> {
+        self.storage.load_definitions()
+    }
+
+    fn store_definitions(&self, _db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        Err(schema_change_error())
+    }
+
+    fn create_table_files(&self, _table: &asl::Table) -> Result<(), QueryError> {
+        Err(schema_change_error())
+    }
+
+    fn delete_table_files(&self, _table: &asl::Table) -> Result<(), QueryError> {
+        Err(schema_change_error())
+    }
+
+    fn delete_database(&self, _db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        Err(schema_change_error())
+    }
+
+    fn list_table_files(&self) -> Result<Vec<String>, QueryError> {
+        self.storage.list_table_files()
+    }
+
+    fn insert_record(&self, table: &asl::Table, record: &asl::Record) -> Result<(), QueryError> {
+        self.stage_table(table)?.insert_record(table, record)
+    }
+
+    fn insert_records(&self, table: &asl::Table, records: &[asl::Record]) -> Result<(), InsertError> {
+        self.stage_table(table)
+            .map_err(|error| InsertError { written_records: 0, error })?
+            .insert_records(table, records)
+    }
+
+    fn scan_records(&self, table: &asl::Table, condition: &Option<Box<asl::Expression>>,
+                    f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        self.get_storage(table).scan_records(table, condition, f)
+    }
+
+    fn apply_page_changes(&self, table: &asl::Table, page_changes: BTreeMap<u32, PageChanges>) -> Result<(), QueryError> {
+        self.stage_table(table)?.apply_page_changes(table, page_changes)
+    }
+
+    fn replace_pages(&self, table: &asl::Table, pages: &[Page]) -> Result<(), QueryError> {
+        self.stage_table(table)?.replace_pages(table, pages)
+    }
+
+    fn read_page(&self, table: &asl::Table, page_idx: usize) -> Result<Option<Page>, QueryError> {
+        self.get_storage(table).read_page(table, page_idx)
+    }
+
+    fn check_table(&self, table: &asl::Table) -> Result<Vec<IntegrityProblem>, QueryError> {
+        self.get_storage(table).check_table(table)
+    }
+
+    fn repair_table(&self, _table: &asl::Table) -> Result<RepairReport, QueryError> {
+        Err(schema_change_error())
+    }
+}
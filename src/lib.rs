@@ -0,0 +1,23 @@
+#[macro_use] extern crate lalrpop_util;
+
+extern crate bit_vec;
+extern crate lalrpop;
+
+pub mod config;
+pub mod engine;
+pub mod repl;
+
+lalrpop_mod!(pub sql_grammar, "/grammar/sql_grammar.rs"); // synthesized by LALRPOP
+
+thread_local! {
+    // Building the parser compiles the regexes of the lexer, which takes longer than parsing most
+    // statements, so it's built once per thread
+    static PARSER: sql_grammar::QueryParser = sql_grammar::QueryParser::new();
+}
+
+/**
+* Parse a SQL statement into its syntax tree without running it, so it can be inspected or transformed
+*/
+pub fn parse(sql: &str) -> Result<engine::asl::Query, engine::errors::QueryError> {
+    PARSER.with(|parser| Ok(parser.parse(sql)?))
+}
@@ -1,27 +1,68 @@
-#[macro_use] extern crate lalrpop_util;
-
-extern crate bit_vec;
-extern crate lalrpop;
-
-mod config;
-mod engine;
-
+use std::env;
 use std::io;
+use std::process;
 
-lalrpop_mod!(pub sql_grammar, "/grammar/sql_grammar.rs"); // synthesized by LALRPOP
-
+use csbase::{config, engine, repl};
 
 fn main() {
-    let mut database = engine::db::Database::new();
-    database.bootstrap().expect("Error while starting the database");
+    let in_memory = env::args().any(|arg| arg == "--in-memory");
+    let read_only = env::args().any(|arg| arg == "--readonly");
+    let allow_drop_database = env::args().any(|arg| arg == "--allow-drop-database");
+    let strict_types = env::args().any(|arg| arg == "--strict-types");
+    let skip_startup_check = env::args().any(|arg| arg == "--skip-startup-check");
+    let json_definitions = env::args().any(|arg| arg == "--json-definitions");
+    let order_by_primary_key = env::args().any(|arg| arg == "--order-by-primary-key");
+    let mut database = match (in_memory, read_only) {
+        (true, false) => engine::db::Database::in_memory(),
+        (true, true) => engine::db::Database::in_memory().read_only(),
+        (false, _) => engine::db::Database::with_config(config::config::Config {
+            read_only,
+            json_definitions,
+            ..config::config::Config::default()
+        }),
+    };
+    if allow_drop_database {
+        database = database.allow_drop_database();
+    }
+    if strict_types {
+        database = database.strict_types();
+    }
+    if skip_startup_check {
+        database = database.skip_startup_check();
+    }
+    if order_by_primary_key {
+        database = database.default_order_by_primary_key();
+    }
+    if let Err(error) = database.bootstrap() {
+        eprintln!("Error while starting the database: {}", error);
+        process::exit(1);
+    }
     loop {
         print!("SQL> ");
         let mut command = String::new();
-        io::stdin().read_line(&mut command).expect("Failed to read command");
-        let result = match database.run_query(&command) {
-            Ok(result) => result,
-            Err(e) => format!("{:?}", e)
+        if io::stdin().read_line(&mut command).expect("Failed to read command") == 0 {
+            break
+        }
+        // A blank line isn't a query, so it's skipped instead of reported as a parse error
+        if command.trim().is_empty() {
+            continue
+        }
+        let is_meta_command = repl::meta_commands::is_meta_command(&command);
+        let result = if is_meta_command {
+            repl::meta_commands::run_meta_command(&mut database, &command)
+        } else {
+            database.run_query(&command)
+        };
+        match result {
+            Ok(result) => repl::pager::print_paged(&result, database.format_settings().pager)
+                .expect("Failed to print the result"),
+            Err(e) => println!("{:?}", e),
         };
-        println!("{:?}", result);
+        // Meta-commands don't clear the warnings, so \warnings can show them after the query
+        let warnings_count = database.get_warnings().len();
+        if !is_meta_command && warnings_count > 0 {
+            println!("{} warning{}. Use \\warnings to show them", warnings_count, if warnings_count == 1 { "" } else { "s" });
+        }
     }
+    database.close().expect("Error while closing the database");
 }
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use crate::engine::db::Database;
+use crate::engine::errors::QueryError;
+use crate::engine::format::OutputFormat;
+
+/**
+* Test if the input is a REPL meta-command (e.g. \pset) rather than a SQL query
+*/
+pub fn is_meta_command(command: &str) -> bool {
+    command.trim_start().starts_with('\\')
+}
+
+/**
+* Remove the surrounding single quotes from a meta-command argument, if present
+*/
+fn unquote(argument: &str) -> &str {
+    if argument.len() >= 2 && argument.starts_with('\'') && argument.ends_with('\'') {
+        &argument[1..argument.len() - 1]
+    } else {
+        argument
+    }
+}
+
+/**
+* Run \pset <option> <value>, which changes how query results are displayed for the rest of the
+* session. The settings aren't persisted, so they are back to their defaults in the next session
+*/
+fn run_pset(database: &mut Database, arguments: &str) -> Result<String, QueryError> {
+    let mut parts = arguments.splitn(2, char::is_whitespace);
+    let option = parts.next().unwrap_or("");
+    let value = unquote(parts.next().unwrap_or("").trim());
+    let settings = database.format_settings_mut();
+    match option {
+        "null" => {
+            settings.null_display = String::from(value);
+            Ok(format!("Null display is \"{}\".", value))
+        },
+        "format" => {
+            settings.format = match value {
+                "table" => OutputFormat::Table,
+                "csv" => OutputFormat::Csv,
+                _ => return Err(QueryError::ValidationError(
+                    format!("Unknown output format '{}'. Allowed values: table, csv", value)))
+            };
+            Ok(format!("Output format is {}.", value))
+        },
+        "max_rows" => {
+            let max_rows: usize = value.parse().map_err(|_| QueryError::ValidationError(
+                format!("Invalid max_rows '{}'. Expected a number, 0 for no limit", value)))?;
+            settings.max_rows = if max_rows == 0 { None } else { Some(max_rows) };
+            Ok(format!("Max rows is {}.", max_rows))
+        },
+        "float_precision" => {
+            if value == "default" {
+                settings.float_precision = None;
+                return Ok(String::from("Float precision is the shortest exact representation."))
+            }
+            let precision: usize = value.parse().map_err(|_| QueryError::ValidationError(format!(
+                "Invalid float_precision '{}'. Expected a number of decimal places or default", value)))?;
+            settings.float_precision = Some(precision);
+            Ok(format!("Float precision is {}.", precision))
+        },
+        "pager" => {
+            settings.pager = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(QueryError::ValidationError(
+                    format!("Invalid pager value '{}'. Allowed values: on, off", value)))
+            };
+            Ok(format!("Pager is {}.", value))
+        },
+        _ => Err(QueryError::ValidationError(format!("Unknown \\pset option '{}'", option)))
+    }
+}
+
+/**
+* Run \autocommit on|off. With autocommit off, writes are staged until an explicit COMMIT
+*/
+fn run_autocommit(database: &mut Database, arguments: &str) -> Result<String, QueryError> {
+    let autocommit = match arguments {
+        "on" => true,
+        "off" => false,
+        _ => return Err(QueryError::ValidationError(
+            format!("Invalid autocommit value '{}'. Allowed values: on, off", arguments)))
+    };
+    database.set_autocommit(autocommit)?;
+    Ok(format!("Autocommit is {}.", arguments))
+}
+
+/**
+* Run \timeout <milliseconds>|off, which limits how long each query can run
+*/
+fn run_timeout(database: &mut Database, arguments: &str) -> Result<String, QueryError> {
+    if arguments == "off" {
+        database.set_query_timeout(None);
+        return Ok(String::from("Query timeout is off."))
+    }
+    let milliseconds: u64 = arguments.parse().map_err(|_| QueryError::ValidationError(
+        format!("Invalid timeout '{}'. Expected a number of milliseconds or off", arguments)))?;
+    database.set_query_timeout(Some(Duration::from_millis(milliseconds)));
+    Ok(format!("Query timeout is {} ms.", milliseconds))
+}
+
+/**
+* Run \batch <rows>|off, which buffers single-row INSERTs and writes them every <rows> rows per table
+*/
+fn run_batch(database: &mut Database, arguments: &str) -> Result<String, QueryError> {
+    if arguments == "off" {
+        database.set_insert_batch_rows(None)?;
+        return Ok(String::from("Insert batching is off."))
+    }
+    let rows: usize = match arguments.parse() {
+        Ok(rows) if rows > 0 => rows,
+        _ => return Err(QueryError::ValidationError(
+            format!("Invalid batch size '{}'. Expected a positive number of rows or off", arguments))),
+    };
+    database.set_insert_batch_rows(Some(rows))?;
+    Ok(format!("Inserts are written every {} rows.", rows))
+}
+
+/**
+* Run \page <table> <page id>, which shows the header and slot directory of a page of the table
+*/
+fn run_page(database: &Database, arguments: &str) -> Result<String, QueryError> {
+    let mut parts = arguments.split_whitespace();
+    let (table, page_id) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(table), Some(page_id), None) => (table, page_id),
+        _ => return Err(QueryError::ValidationError(String::from("Usage: \\page <table> <page id>"))),
+    };
+    let page_id: usize = page_id.parse().map_err(|_| QueryError::ValidationError(
+        format!("Invalid page id '{}'. Expected a number starting at 1", page_id)))?;
+    database.dump_page(table, page_id)
+}
+
+/**
+* Run \warnings, which shows the warnings of the last query
+*/
+fn run_warnings(database: &Database) -> Result<String, QueryError> {
+    let warnings = database.get_warnings();
+    if warnings.is_empty() {
+        return Ok(String::from("No warnings."))
+    }
+    Ok(warnings.iter().map(|warning| format!("Warning: {}", warning)).collect::<Vec<String>>().join("\n"))
+}
+
+/**
+* Parse and run a REPL meta-command
+*/
+pub fn run_meta_command(database: &mut Database, command: &str) -> Result<String, QueryError> {
+    let command = command.trim();
+    let mut parts = command.splitn(2, char::is_whitespace);
+    let name = parts.next().unwrap_or("");
+    let arguments = parts.next().unwrap_or("").trim();
+    match name {
+        "\\pset" => run_pset(database, arguments),
+        "\\autocommit" => run_autocommit(database, arguments),
+        "\\batch" => run_batch(database, arguments),
+        "\\page" => run_page(database, arguments),
+        "\\timeout" => run_timeout(database, arguments),
+        "\\warnings" => run_warnings(database),
+        _ => Err(QueryError::ValidationError(format!("Unknown meta-command {}", name)))
+    }
+}
@@ -0,0 +1,2 @@
+pub mod meta_commands;
+pub mod pager;
@@ -0,0 +1,57 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+// Lines assumed when the height of the terminal can't be found
+const DEFAULT_TERMINAL_HEIGHT: usize = 24;
+
+/**
+* Get the number of lines of the terminal from the LINES environment variable, or else from stty,
+* which reads the size of the terminal stdin is attached to
+*/
+fn get_terminal_height() -> usize {
+    if let Some(lines) = env::var("LINES").ok().and_then(|lines| lines.parse().ok()) {
+        return lines
+    }
+    let output = Command::new("stty").arg("size").stdin(Stdio::inherit()).stderr(Stdio::null()).output();
+    output.ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|size| size.split_whitespace().next().and_then(|rows| rows.parse().ok()))
+        .filter(|rows| *rows > 0)
+        .unwrap_or(DEFAULT_TERMINAL_HEIGHT)
+}
+
+/**
+* Print the output of a command. When paging is enabled, both stdin and stdout are a terminal and
+* the output doesn't fit in it, it's printed a screenful at a time, waiting for Enter before each
+* one. Entering q skips the rest of the output
+*/
+pub fn print_paged(output: &str, enabled: bool) -> io::Result<()> {
+    if !enabled || !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        println!("{}", output);
+        return Ok(())
+    }
+    let lines: Vec<&str> = output.lines().collect();
+    // The last line of the screen is left for the prompt to continue
+    let page_lines = get_terminal_height().saturating_sub(1).max(1);
+    if lines.len() <= page_lines {
+        println!("{}", output);
+        return Ok(())
+    }
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for (idx, page) in lines.chunks(page_lines).enumerate() {
+        if idx > 0 {
+            write!(stdout, "-- More -- (Enter to continue, q to stop) ")?;
+            stdout.flush()?;
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 || answer.trim() == "q" {
+                break
+            }
+        }
+        for line in page {
+            writeln!(stdout, "{}", line)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::fs;
+
+use csbase::engine::asl::Value;
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+
+use common::{for_each_backend, int, open_file_database, rows, run, select_values, string, TempDir};
+
+fn create_items(database: &mut Database) {
+    run(database, "CREATE TABLE items (id INT, name STRING, price FLOAT, PRIMARY KEY (id))");
+    run(database, "INSERT INTO items (id, name, price) VALUES (1, 'pen', 1.5)");
+    run(database, "INSERT INTO items (id, name, price) VALUES (2, 'book', 12.0)");
+    run(database, "INSERT INTO items (id, name, price) VALUES (3, 'lamp', 30.25)");
+}
+
+#[test]
+fn insert_and_select() {
+    for_each_backend(|database| {
+        create_items(database);
+        assert_eq!(select_values(database, "SELECT id, name, price FROM items"), rows(vec![
+            vec![int(1), string("pen"), Value::Float(1.5)],
+            vec![int(2), string("book"), Value::Float(12.0)],
+            vec![int(3), string("lamp"), Value::Float(30.25)],
+        ]));
+        assert_eq!(select_values(database, "SELECT name FROM items WHERE price > 10.0"),
+                   rows(vec![vec![string("book")], vec![string("lamp")]]));
+    });
+}
+
+#[test]
+fn update() {
+    for_each_backend(|database| {
+        create_items(database);
+        run(database, "UPDATE items SET name = 'big book', price = price * 2.0 WHERE id = 2");
+        assert_eq!(select_values(database, "SELECT id, name, price FROM items WHERE id = 2"),
+                   rows(vec![vec![int(2), string("big book"), Value::Float(24.0)]]));
+        assert_eq!(select_values(database, "SELECT id FROM items WHERE price < 10.0"), rows(vec![vec![int(1)]]));
+    });
+}
+
+#[test]
+fn delete() {
+    for_each_backend(|database| {
+        create_items(database);
+        run(database, "DELETE FROM items WHERE id = 2");
+        assert_eq!(select_values(database, "SELECT id FROM items"), rows(vec![vec![int(1)], vec![int(3)]]));
+        run(database, "DELETE FROM items");
+        assert!(select_values(database, "SELECT id FROM items").is_empty());
+    });
+}
+
+#[test]
+fn nulls_round_trip() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE notes (id INT, title STRING, score INT, body STRING, PRIMARY KEY (id))");
+        run(database, "INSERT INTO notes (id, title, score, body) VALUES (1, NULL, 5, NULL)");
+        run(database, "INSERT INTO notes (id, title) VALUES (2, 'only title')");
+        run(database, "INSERT INTO notes (id, title, score, body) VALUES (3, 'all', 7, 'text')");
+        run(database, "UPDATE notes SET score = NULL WHERE id = 3");
+        assert_eq!(select_values(database, "SELECT id, title, score, body FROM notes"), rows(vec![
+            vec![int(1), Value::Null, int(5), Value::Null],
+            vec![int(2), string("only title"), Value::Null, Value::Null],
+            vec![int(3), string("all"), Value::Null, string("text")],
+        ]));
+    });
+}
+
+#[test]
+fn file_backend_keeps_rows_after_reopening() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    run(&mut database, "CREATE TABLE notes (id INT, title STRING, PRIMARY KEY (id))");
+    run(&mut database, "INSERT INTO notes (id, title) VALUES (1, NULL)");
+    run(&mut database, "INSERT INTO notes (id, title) VALUES (2, 'kept')");
+    database.close().expect("Error while closing the database");
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id, title FROM notes"),
+               rows(vec![vec![int(1), Value::Null], vec![int(2), string("kept")]]));
+}
+
+#[test]
+fn select_from_a_table_that_was_never_inserted_into() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT, name STRING)");
+        assert_eq!(select_values(database, "SELECT id, name FROM items"), rows(vec![]));
+        assert_eq!(select_values(database, "SELECT COUNT(*) FROM items"), rows(vec![vec![int(0)]]));
+        assert_eq!(database.table_size("items").unwrap().page_count, 0);
+    });
+}
+
+#[test]
+fn appending_after_a_truncated_page_is_rejected() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_items(&mut database);
+    let data_path = dir.path.join("items_data.csbase");
+    let mut data = fs::read(&data_path).unwrap();
+    data.extend(&[0u8; 100]);
+    fs::write(&data_path, &data).unwrap();
+
+    match database.run_query("INSERT INTO items (id, name, price) VALUES (4, 'desk', 80.0)") {
+        Err(QueryError::PagingError(message)) => assert!(message.contains("Truncated page"), "{}", message),
+        result => panic!("The insert should be rejected, but got {:?}", result),
+    }
+    assert!(database.run_query("SELECT id FROM items").is_err());
+    // The file wasn't written over
+    assert_eq!(fs::read(&data_path).unwrap(), data);
+}
@@ -0,0 +1,218 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use csbase::config::config::Config;
+use csbase::engine::asl::{self, Record, Value};
+use csbase::engine::db::{Database, DatabaseDefinition};
+use csbase::engine::errors::{InsertError, QueryError, SystemError};
+use csbase::engine::integrity::{IntegrityProblem, RepairReport};
+use csbase::engine::pages::{Page, RowId};
+use csbase::engine::storage::{PageChanges, ScanControl, StorageEngine};
+
+static NEXT_DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/**
+* A database directory in the system temp directory, unique to each test and removed when it's dropped
+*/
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let dir_id = NEXT_DIR_ID.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("csbase-test-{}-{}", process::id(), dir_id));
+        let _ = fs::remove_dir_all(&path);
+        TempDir { path }
+    }
+
+    pub fn config(&self) -> Config {
+        Config { db_path: self.path.to_string_lossy().into_owned(), ..Config::default() }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/**
+* Open the file database stored in dir, creating it if it doesn't exist
+*/
+pub fn open_file_database(dir: &TempDir) -> Database {
+    bootstrapped(Database::with_config(dir.config()))
+}
+
+pub fn open_memory_database() -> Database {
+    bootstrapped(Database::in_memory())
+}
+
+/**
+* Run the test against the in-memory backend and against the file backend
+*/
+pub fn for_each_backend(test: fn(&mut Database)) {
+    let mut database = open_memory_database();
+    test(&mut database);
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    test(&mut database);
+    database.close().expect("Error while closing the database");
+}
+
+pub fn bootstrapped(mut database: Database) -> Database {
+    database.bootstrap().expect("Error while starting the database");
+    database
+}
+
+pub fn run(database: &mut Database, query: &str) -> String {
+    database.run_query(query).unwrap_or_else(|error| panic!("{} failed: {:?}", query, error))
+}
+
+/**
+* Get every row a SELECT returns, in order. They are compared as records, so NULLs equal each other
+*/
+pub fn select_values(database: &Database, query: &str) -> Vec<Record> {
+    database.query_iter(query).unwrap_or_else(|error| panic!("{} failed: {:?}", query, error))
+        .map(|row| Record { values: row.expect("Error while reading a row").get_values().to_vec() })
+        .collect()
+}
+
+pub fn rows(rows: Vec<Vec<Value>>) -> Vec<Record> {
+    rows.into_iter().map(|values| Record { values }).collect()
+}
+
+pub fn int(value: i32) -> Value {
+    Value::Int(value)
+}
+
+pub fn string(value: &str) -> Value {
+    Value::Str(String::from(value))
+}
+
+/**
+* Calls a CountingStorage received since it was created
+*/
+#[derive(Debug, Default)]
+pub struct StorageCalls {
+    // Table of every scan_records call
+    pub scans: Vec<String>,
+    // Table of every read_page call
+    pub page_reads: Vec<String>,
+    // Number of records of every insert_records call
+    pub inserted_batches: Vec<usize>,
+    // Page ids of every apply_page_changes call
+    pub page_changes: Vec<Vec<u32>>,
+    // Table of every replace_pages call, and of each table of every replace_tables_pages call
+    pub replaced_tables: Vec<String>,
+}
+
+/**
+* Storage that records the scans, page reads and page changes the database asks for before passing them on to
+* another storage
+*/
+#[derive(Debug)]
+pub struct CountingStorage {
+    storage: Box<dyn StorageEngine>,
+    calls: Rc<RefCell<StorageCalls>>,
+}
+
+impl CountingStorage {
+    pub fn new(storage: Box<dyn StorageEngine>) -> (CountingStorage, Rc<RefCell<StorageCalls>>) {
+        let calls = Rc::new(RefCell::new(StorageCalls::default()));
+        (CountingStorage { storage, calls: calls.clone() }, calls)
+    }
+}
+
+impl StorageEngine for CountingStorage {
+    fn initialize(&self) -> Result<(), SystemError> {
+        self.storage.initialize()
+    }
+
+    fn location(&self) -> String {
+        self.storage.location()
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        self.storage.temp_path()
+    }
+
+    fn flush(&self) -> Result<(), QueryError> {
+        self.storage.flush()
+    }
+
+    fn load_definitions(&self) -> Result<DatabaseDefinition, QueryError> {
+        self.storage.load_definitions()
+    }
+
+    fn store_definitions(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        self.storage.store_definitions(db_definition)
+    }
+
+    fn create_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        self.storage.create_table_files(table)
+    }
+
+    fn delete_table_files(&self, table: &asl::Table) -> Result<(), QueryError> {
+        self.storage.delete_table_files(table)
+    }
+
+    fn delete_database(&self, db_definition: &DatabaseDefinition) -> Result<(), QueryError> {
+        self.storage.delete_database(db_definition)
+    }
+
+    fn list_table_files(&self) -> Result<Vec<String>, QueryError> {
+        self.storage.list_table_files()
+    }
+
+    fn insert_record(&self, table: &asl::Table, record: &asl::Record) -> Result<(), QueryError> {
+        self.storage.insert_record(table, record)
+    }
+
+    fn insert_records(&self, table: &asl::Table, records: &[asl::Record]) -> Result<(), InsertError> {
+        self.calls.borrow_mut().inserted_batches.push(records.len());
+        self.storage.insert_records(table, records)
+    }
+
+    fn scan_records(&self, table: &asl::Table, condition: &Option<Box<asl::Expression>>,
+                    f: &mut dyn FnMut(RowId, asl::Record) -> Result<ScanControl, QueryError>) -> Result<(), QueryError> {
+        self.calls.borrow_mut().scans.push(table.name.clone());
+        self.storage.scan_records(table, condition, f)
+    }
+
+    fn apply_page_changes(&self, table: &asl::Table, page_changes: BTreeMap<u32, PageChanges>) -> Result<(), QueryError> {
+        self.calls.borrow_mut().page_changes.push(page_changes.keys().cloned().collect());
+        self.storage.apply_page_changes(table, page_changes)
+    }
+
+    fn replace_pages(&self, table: &asl::Table, pages: &[Page]) -> Result<(), QueryError> {
+        self.calls.borrow_mut().replaced_tables.push(table.name.clone());
+        self.storage.replace_pages(table, pages)
+    }
+
+    fn replace_tables_pages(&self, tables: &[(&asl::Table, Vec<Page>)]) -> Result<(), QueryError> {
+        self.calls.borrow_mut().replaced_tables.extend(tables.iter().map(|(table, _)| table.name.clone()));
+        self.storage.replace_tables_pages(tables)
+    }
+
+    fn read_page(&self, table: &asl::Table, page_idx: usize) -> Result<Option<Page>, QueryError> {
+        self.calls.borrow_mut().page_reads.push(table.name.clone());
+        self.storage.read_page(table, page_idx)
+    }
+
+    fn check_table(&self, table: &asl::Table) -> Result<Vec<IntegrityProblem>, QueryError> {
+        self.storage.check_table(table)
+    }
+
+    fn repair_table(&self, table: &asl::Table) -> Result<RepairReport, QueryError> {
+        self.storage.repair_table(table)
+    }
+}
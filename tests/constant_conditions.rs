@@ -0,0 +1,89 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use csbase::engine::asl::Value;
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+use csbase::engine::fs::DBFileSystem;
+
+use common::{bootstrapped, int, rows, run, select_values, CountingStorage, StorageCalls, TempDir};
+
+const ROWS: i32 = 300;
+
+/**
+* Database with a multi-page items table and a small tags table, and the calls its storage receives
+* after they are filled
+*/
+fn counting_database(dir: &TempDir) -> (Database, Rc<RefCell<StorageCalls>>) {
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)));
+    run(&mut database, "CREATE TABLE items (id INT, name STRING)");
+    run(&mut database, "CREATE TABLE tags (id INT, tag STRING)");
+    for id in 0..ROWS {
+        run(&mut database, &format!("INSERT INTO items (id, name) VALUES ({}, 'item {} with a longer name')", id, id));
+    }
+    run(&mut database, "INSERT INTO tags (id, tag) VALUES (1, 'new')");
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM items"), rows(vec![vec![int(ROWS)]]));
+    *calls.borrow_mut() = StorageCalls::default();
+    (database, calls)
+}
+
+fn assert_nothing_read(calls: &Rc<RefCell<StorageCalls>>) {
+    assert_eq!(calls.borrow().scans, Vec::<String>::new());
+    assert_eq!(calls.borrow().page_reads, Vec::<String>::new());
+}
+
+fn iterated_values(database: &Database, query: &str) -> Vec<Vec<Value>> {
+    database.query_iter(query).unwrap_or_else(|error| panic!("{} failed: {:?}", query, error))
+        .map(|row| row.expect("Error while reading a row").get_values().to_vec())
+        .collect()
+}
+
+#[test]
+fn false_condition_reads_no_pages() {
+    let dir = TempDir::new();
+    let (database, calls) = counting_database(&dir);
+    for query in &["SELECT * FROM items WHERE 1=0", "SELECT * FROM items WHERE 1 = 0 ORDER BY name",
+                   "SELECT * FROM items WHERE NULL = NULL", "SELECT * FROM items WHERE 1 > 2 AND 2 > 1",
+                   "SELECT * FROM items JOIN tags ON items.id = tags.id WHERE 1=0"] {
+        assert_eq!(select_values(&database, query), vec![], "{}", query);
+    }
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM items WHERE 1=0"), rows(vec![vec![int(0)]]));
+    assert!(iterated_values(&database, "SELECT id FROM items WHERE 1=0").is_empty());
+    assert_nothing_read(&calls);
+}
+
+#[test]
+fn false_condition_does_not_read_a_broken_table_file() {
+    let dir = TempDir::new();
+    let (mut database, _) = counting_database(&dir);
+    std::fs::write(dir.path.join("items_data.csbase"), vec![1; 100]).expect("Error while writing the table file");
+    assert_eq!(select_values(&database, "SELECT * FROM items WHERE 1=0"), vec![]);
+    assert!(database.run_query("SELECT * FROM items WHERE 1=1").is_err());
+}
+
+#[test]
+fn true_condition_returns_every_row() {
+    let dir = TempDir::new();
+    let (database, _) = counting_database(&dir);
+    let every_id = rows((0..ROWS).map(|id| vec![int(id)]).collect());
+    assert_eq!(select_values(&database, "SELECT id FROM items WHERE 1=1"), every_id);
+    assert_eq!(select_values(&database, "SELECT id FROM items WHERE 2 > 1 OR 1 = 0 ORDER BY id"), every_id);
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM items WHERE 1=1"), rows(vec![vec![int(ROWS)]]));
+    assert_eq!(iterated_values(&database, "SELECT id FROM items WHERE 1=1").len(), ROWS as usize);
+    assert_eq!(select_values(&database, "SELECT tags.tag FROM items JOIN tags ON items.id = tags.id WHERE 1=1"),
+               rows(vec![vec![Value::Str(String::from("new"))]]));
+}
+
+#[test]
+fn constant_condition_that_is_not_boolean_is_rejected() {
+    let dir = TempDir::new();
+    let (mut database, calls) = counting_database(&dir);
+    match database.run_query("SELECT * FROM items WHERE 1 + 1") {
+        Err(QueryError::ValidationError(_)) => (),
+        result => panic!("A non boolean condition should be rejected, but got {:?}", result),
+    }
+    assert_nothing_read(&calls);
+}
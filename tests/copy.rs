@@ -0,0 +1,97 @@
+mod common;
+
+use std::fs;
+
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+
+use common::{int, open_file_database, rows, run, select_values, string, TempDir};
+
+fn create_items(database: &mut Database) {
+    run(database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+    run(database, "INSERT INTO items (id, name) VALUES (1, 'one')");
+}
+
+/**
+* Write the CSV file in the database directory and get its path
+*/
+fn write_csv(dir: &TempDir, contents: &str) -> String {
+    let path = dir.path.join("items.csv");
+    fs::write(&path, contents).expect("Error while writing the CSV file");
+    path.to_string_lossy().into_owned()
+}
+
+fn assert_copy_rejected(database: &mut Database, query: &str, expected_message: &str) {
+    match database.run_query(query) {
+        Err(QueryError::ValidationError(message)) => assert!(message.contains(expected_message), "{}", message),
+        result => panic!("{} should be rejected, but got {:?}", query, result),
+    }
+    assert_eq!(select_values(database, "SELECT id, name FROM items"), rows(vec![vec![int(1), string("one")]]));
+}
+
+#[test]
+fn copy_loads_every_row() {
+    for trusted in &["", " TRUSTED"] {
+        let dir = TempDir::new();
+        let mut database = open_file_database(&dir);
+        create_items(&mut database);
+        let path = write_csv(&dir, "id,name\n2,two\n3,three\n");
+        assert_eq!(run(&mut database, &format!("COPY items FROM '{}'{}", path, trusted)), "Copied 2 records");
+        assert_eq!(select_values(&database, "SELECT id, name FROM items ORDER BY id"), rows(vec![
+            vec![int(1), string("one")],
+            vec![int(2), string("two")],
+            vec![int(3), string("three")],
+        ]));
+    }
+}
+
+#[test]
+fn copy_with_a_header_that_doesnt_match_is_rejected() {
+    for trusted in &["", " TRUSTED"] {
+        let dir = TempDir::new();
+        let mut database = open_file_database(&dir);
+        create_items(&mut database);
+        let path = write_csv(&dir, "name,id\ntwo,2\n");
+        assert_copy_rejected(&mut database, &format!("COPY items FROM '{}'{}", path, trusted),
+                             "doesn't match the columns of items");
+    }
+}
+
+#[test]
+fn copy_with_a_bad_first_row_is_rejected() {
+    for trusted in &["", " TRUSTED"] {
+        let dir = TempDir::new();
+        let mut database = open_file_database(&dir);
+        create_items(&mut database);
+        let path = write_csv(&dir, "id,name\ntwo,2\n3,three\n");
+        assert_copy_rejected(&mut database, &format!("COPY items FROM '{}'{}", path, trusted), "Line 2");
+        let path = write_csv(&dir, "id,name\n2\n3,three\n");
+        assert_copy_rejected(&mut database, &format!("COPY items FROM '{}'{}", path, trusted),
+                             "Expected 2 fields but found 1");
+    }
+}
+
+#[test]
+fn trusted_copy_checks_the_constraints_of_the_first_row() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_items(&mut database);
+    let path = write_csv(&dir, "id,name\n1,duplicate\n3,three\n");
+    assert_copy_rejected(&mut database, &format!("COPY items FROM '{}' TRUSTED", path),
+                         "The first row can't be loaded into items");
+}
+
+#[test]
+fn copy_without_trusted_inserts_nothing_if_a_later_row_is_bad() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_items(&mut database);
+    let path = write_csv(&dir, "id,name\n2,two\n3,three\nfour,4\n");
+    assert_copy_rejected(&mut database, &format!("COPY items FROM '{}'", path), "Line 4");
+    let path = write_csv(&dir, "id,name\n2,two\n3,three\n2,again\n");
+    match database.run_query(&format!("COPY items FROM '{}'", path)) {
+        Err(_) => (),
+        result => panic!("The duplicate key should be rejected, but got {:?}", result),
+    }
+    assert_eq!(select_values(&database, "SELECT id FROM items"), rows(vec![vec![int(1)]]));
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::fs;
+
+use csbase::config::config::TABLE_DEFINITIONS_FILE;
+use csbase::engine::db::Database;
+
+use common::{bootstrapped, int, open_file_database, rows, run, select_values, TempDir};
+
+fn open_droppable_database(dir: &TempDir) -> Database {
+    bootstrapped(Database::with_config(dir.config()).allow_drop_database())
+}
+
+#[test]
+fn dropped_database_leaves_no_definitions_behind() {
+    let dir = TempDir::new();
+    let mut database = open_droppable_database(&dir);
+    run(&mut database, "CREATE TABLE items (id INT)");
+    run(&mut database, "INSERT INTO items (id) VALUES (1)");
+    assert!(dir.path.join(TABLE_DEFINITIONS_FILE).exists());
+    assert_eq!(run(&mut database, "DROP DATABASE"), "Dropped database with 1 tables");
+    database.close().expect("Error while closing the database");
+    assert!(!dir.path.join(TABLE_DEFINITIONS_FILE).exists());
+    assert!(!dir.path.join("items_data.csbase").exists());
+}
+
+#[test]
+fn tables_created_after_dropping_the_database_are_kept() {
+    let dir = TempDir::new();
+    let mut database = bootstrapped(Database::with_config(dir.config()).allow_drop_database().insert_batch_rows(10));
+    run(&mut database, "CREATE TABLE items (id INT)");
+    run(&mut database, "DROP DATABASE");
+    run(&mut database, "CREATE TABLE tags (id INT)");
+    run(&mut database, "INSERT INTO tags (id) VALUES (1)");
+    database.close().expect("Error while closing the database");
+    let mut database = open_droppable_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM tags"), rows(vec![vec![int(1)]]));
+    assert!(database.run_query("SELECT id FROM items").is_err());
+}
+
+fn create_tables(database: &mut Database, table_names: &[&str]) {
+    for table_name in table_names {
+        run(database, &format!("CREATE TABLE {} (id INT)", table_name));
+        run(database, &format!("INSERT INTO {} (id) VALUES (1)", table_name));
+    }
+}
+
+fn data_file_exists(dir: &TempDir, table_name: &str) -> bool {
+    dir.path.join(format!("{}_data.csbase", table_name)).exists()
+}
+
+#[test]
+fn drop_several_tables_at_once() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_tables(&mut database, &["a", "b", "c", "d"]);
+    assert_eq!(run(&mut database, "DROP TABLE a, b, c"), "Dropped a, b, c");
+    for table_name in &["a", "b", "c"] {
+        assert!(!data_file_exists(&dir, table_name));
+        assert!(database.run_query(&format!("SELECT id FROM {}", table_name)).is_err());
+    }
+    database.close().expect("Error while closing the database");
+    let mut database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM d"), rows(vec![vec![int(1)]]));
+    for table_name in &["a", "b", "c"] {
+        assert!(database.run_query(&format!("SELECT id FROM {}", table_name)).is_err());
+    }
+}
+
+#[test]
+fn drop_with_a_missing_table_drops_nothing() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_tables(&mut database, &["a", "b"]);
+    assert!(database.run_query("DROP TABLE a, missing, b").is_err());
+    assert!(data_file_exists(&dir, "a") && data_file_exists(&dir, "b"));
+    assert_eq!(run(&mut database, "DROP TABLE IF EXISTS a, missing, b"), "Dropped a, b");
+    assert!(database.get_warnings().iter().any(|warning| warning.contains("missing")));
+}
+
+#[test]
+fn dropped_table_whose_file_cant_be_deleted_stays_dropped() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_tables(&mut database, &["a", "b"]);
+    // A directory in place of the data file of a can't be removed like a file
+    fs::remove_file(dir.path.join("a_data.csbase")).unwrap();
+    fs::create_dir(dir.path.join("a_data.csbase")).unwrap();
+    assert_eq!(run(&mut database, "DROP TABLE a, b"), "Dropped a, b");
+    assert!(database.get_warnings().iter().any(|warning| warning.contains("dropped table a couldn't be deleted")));
+    assert!(!data_file_exists(&dir, "b"));
+    database.close().expect("Error while closing the database");
+    let mut database = open_file_database(&dir);
+    assert!(database.run_query("SELECT id FROM a").is_err());
+    assert!(database.run_query("SELECT id FROM b").is_err());
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use csbase::engine::asl::{Comparator, Expression, Query, Value};
+use csbase::engine::errors::QueryError;
+use csbase::parse;
+
+fn parse_error(sql: &str) -> String {
+    match parse(sql) {
+        Err(QueryError::ParseError(error)) => error,
+        result => panic!("{} should fail to parse, but got {:?}", sql, result),
+    }
+}
+
+#[test]
+fn limit_out_of_range_is_a_parse_error() {
+    assert!(parse_error("UPDATE t SET a = 1 LIMIT 99999999999999999999").contains("LIMIT out of range"));
+    assert!(parse_error("DELETE FROM t LIMIT 99999999999999999999").contains("LIMIT out of range"));
+    match parse("DELETE FROM t LIMIT 10") {
+        Ok(Query::Delete(query)) => assert_eq!(query.limit, Some(10)),
+        result => panic!("Unexpected parse result {:?}", result),
+    }
+}
+
+#[test]
+fn integer_literal_out_of_range_is_a_parse_error() {
+    assert!(parse_error("SELECT 99999999999").contains("Integer literal out of range"));
+}
+
+/**
+* Parse the WHERE condition of a SELECT from t
+*/
+fn condition(condition: &str) -> Box<Expression> {
+    let sql = format!("SELECT a FROM t WHERE {}", condition);
+    match parse(&sql) {
+        Ok(Query::Select(query)) => query.condition.expect("The query should have a condition"),
+        result => panic!("Unexpected parse result {:?}", result),
+    }
+}
+
+/**
+* Evaluate the condition for a row where the column a has the given value
+*/
+fn evaluate(condition_sql: &str, a: Value) -> Value {
+    let identifier_values: HashMap<String, Value> = vec![(String::from("a"), a)].into_iter().collect();
+    condition(condition_sql).evaluate(Some(&identifier_values))
+        .unwrap_or_else(|error| panic!("{} failed: {:?}", condition_sql, error))
+}
+
+fn assert_bool(value: Value, expected: bool) {
+    match value {
+        Value::Bool(value) => assert_eq!(value, expected),
+        value => panic!("Expected {}, but got {:?}", expected, value),
+    }
+}
+
+#[test]
+fn not_like_is_parsed_as_a_negated_like() {
+    assert!(matches!(*condition("a NOT LIKE 'x%'"), Expression::Like(_, _, None, true, false)));
+    assert!(matches!(*condition("a LIKE 'x%'"), Expression::Like(_, _, None, false, false)));
+}
+
+#[test]
+fn not_like_of_null_is_null() {
+    assert!(evaluate("a NOT LIKE 'x%'", Value::Null).is_null());
+    assert!(evaluate("'xyz' NOT LIKE a", Value::Null).is_null());
+    assert_bool(evaluate("a NOT LIKE 'x%'", Value::Str(String::from("abc"))), true);
+}
+
+#[test]
+fn not_in_is_parsed_as_a_negated_in() {
+    match *condition("a NOT IN (1, 2, 3)") {
+        Expression::In(_, values, true) => assert_eq!(values.len(), 3),
+        expression => panic!("Unexpected condition {:?}", expression),
+    }
+}
+
+#[test]
+fn not_in_with_null_is_null_unless_a_value_matches() {
+    assert!(evaluate("a NOT IN (1, 2)", Value::Null).is_null());
+    assert!(evaluate("a NOT IN (1, NULL)", Value::Int(3)).is_null());
+    assert_bool(evaluate("a NOT IN (1, NULL)", Value::Int(1)), false);
+    assert_bool(evaluate("a NOT IN (1, 2)", Value::Int(3)), true);
+}
+
+#[test]
+fn not_between_is_parsed_as_a_negated_between() {
+    assert!(matches!(*condition("a NOT BETWEEN 1 AND 10"), Expression::Between(_, _, _, true)));
+    // The AND between the bounds doesn't end the condition
+    assert!(matches!(*condition("a NOT BETWEEN 1 AND 10 AND a > 0"), Expression::LogicOp(..)));
+}
+
+#[test]
+fn not_between_with_null_is_null_unless_a_bound_rules_the_value_out() {
+    assert!(evaluate("a NOT BETWEEN 1 AND 10", Value::Null).is_null());
+    assert!(evaluate("a NOT BETWEEN 1 AND NULL", Value::Int(5)).is_null());
+    assert_bool(evaluate("a NOT BETWEEN 1 AND NULL", Value::Int(0)), true);
+    assert_bool(evaluate("a NOT BETWEEN NULL AND 10", Value::Int(11)), true);
+}
+
+#[test]
+fn is_not_null_is_parsed_as_a_negated_is_null() {
+    assert!(matches!(*condition("a IS NOT NULL"), Expression::IsNull(_, true)));
+    assert!(matches!(*condition("a IS NULL"), Expression::IsNull(_, false)));
+}
+
+#[test]
+fn is_not_null_is_never_null() {
+    assert_bool(evaluate("a IS NOT NULL", Value::Null), false);
+    assert_bool(evaluate("a IS NOT NULL", Value::Int(1)), true);
+    // An unknown operand of AND leaves it unknown unless the other one is false
+    assert!(evaluate("a IS NOT NULL AND a NOT IN (1, NULL)", Value::Int(3)).is_null());
+    assert_bool(evaluate("a IS NOT NULL AND a NOT IN (1, NULL)", Value::Null), false);
+}
+
+/**
+* Parse the value compared with a in a WHERE condition
+*/
+fn compared_value(value_sql: &str) -> Expression {
+    match *condition(&format!("a = {}", value_sql)) {
+        Expression::Comp(_, _, value) => *value,
+        expression => panic!("Unexpected condition {:?}", expression),
+    }
+}
+
+fn assert_float_literal(value_sql: &str, expected: f32) {
+    match compared_value(value_sql) {
+        Expression::Value(Value::Float(value)) => assert_eq!(value, expected, "{}", value_sql),
+        expression => panic!("{} should be a Float, but got {:?}", value_sql, expression),
+    }
+}
+
+#[test]
+fn float_literals_with_exponents_are_parsed() {
+    assert_float_literal("1e3", 1000.0);
+    assert_float_literal("1E3", 1000.0);
+    assert_float_literal("1.5e3", 1500.0);
+    assert_float_literal("1.5E-2", 0.015);
+    assert_float_literal("2E-4", 0.0002);
+    assert_float_literal("2e+4", 20000.0);
+    assert!(parse_error("SELECT 1e39").contains("Float literal out of range"));
+}
+
+#[test]
+fn exponents_without_digits_are_rejected() {
+    for value_sql in &["1e", "1.5e", "1E+", "1.5e-"] {
+        parse_error(&format!("SELECT a FROM t WHERE a = {}", value_sql));
+    }
+}
+
+#[test]
+fn e_alone_is_an_identifier() {
+    for identifier in &["e", "e3", "E3"] {
+        match compared_value(identifier) {
+            Expression::Identifier(name) => assert_eq!(name, identifier.to_lowercase()),
+            expression => panic!("{} should be an identifier, but got {:?}", identifier, expression),
+        }
+    }
+}
+
+fn evaluate_str(condition_sql: &str, a: &str) -> Value {
+    evaluate(condition_sql, Value::Str(String::from(a)))
+}
+
+#[test]
+fn escaped_percent_sign_matches_itself() {
+    for condition_sql in &["a LIKE '50!%' ESCAPE '!'", "a LIKE '50\\%' ESCAPE '\\'"] {
+        assert_bool(evaluate_str(condition_sql, "50%"), true);
+        assert_bool(evaluate_str(condition_sql, "500"), false);
+        assert_bool(evaluate_str(condition_sql, "50% off"), false);
+    }
+    assert_bool(evaluate_str("a LIKE '%!%%' ESCAPE '!'", "save 50% now"), true);
+    assert_bool(evaluate_str("a LIKE '%!%%' ESCAPE '!'", "save 50 now"), false);
+}
+
+#[test]
+fn escaped_underscore_matches_itself() {
+    assert_bool(evaluate_str("a LIKE 'a!_b' ESCAPE '!'", "a_b"), true);
+    assert_bool(evaluate_str("a LIKE 'a!_b' ESCAPE '!'", "axb"), false);
+    assert_bool(evaluate_str("a LIKE 'a_b'", "axb"), true);
+}
+
+#[test]
+fn like_escape_must_be_a_single_character() {
+    let identifier_values: HashMap<String, Value> = vec![(String::from("a"), Value::Str(String::from("50%")))]
+        .into_iter().collect();
+    match condition("a LIKE '50!!%' ESCAPE '!!'").evaluate(Some(&identifier_values)) {
+        Err(QueryError::ValidationError(_)) => (),
+        result => panic!("A two character escape should be rejected, but got {:?}", result),
+    }
+}
+
+#[test]
+fn string_comparisons_are_case_sensitive_unless_asked_not_to_be() {
+    assert_bool(evaluate_str("a = 'abc'", "ABC"), false);
+    assert_bool(evaluate_str("a LIKE 'abc'", "ABC"), false);
+    assert_bool(evaluate_str("a ILIKE 'abc'", "ABC"), true);
+    assert_bool(evaluate_str("a ILIKE 'a%'", "ABC"), true);
+    assert_bool(evaluate_str("LOWER(a) = 'abc'", "ABC"), true);
+    assert_bool(evaluate_str("a = UPPER('abc')", "ABC"), true);
+    assert_bool(evaluate_str("LOWER(a) = LOWER('aBc')", "AbC"), true);
+    // Strings are ordered by their bytes, so uppercase letters go first
+    assert_bool(evaluate_str("a < 'abc'", "ABC"), true);
+}
+
+#[test]
+fn chained_comparisons_are_a_parse_error() {
+    for condition_sql in &["1 < 2 < 3", "3 > 2 > 1", "a >= 1 <= 2", "a = 1 = 2"] {
+        let error = parse_error(&format!("SELECT a FROM t WHERE {}", condition_sql));
+        assert!(error.contains("Comparisons can't be chained"), "{}: {}", condition_sql, error);
+    }
+    assert!(parse_error("SELECT 1 < 2 < 3").contains("Comparisons can't be chained"));
+}
+
+#[test]
+fn ordering_a_parenthesized_comparison_is_rejected() {
+    match &*condition("(1 < 2) < 3") {
+        Expression::Comp(left, Comparator::Lt, right) => {
+            assert!(matches!(**left, Expression::Comp(_, Comparator::Lt, _)), "Unexpected left operand {:?}", left);
+            assert!(matches!(**right, Expression::Value(Value::Int(3))), "Unexpected right operand {:?}", right);
+        },
+        expression => panic!("Unexpected condition {:?}", expression),
+    }
+    for (condition_sql, comparator) in &[("(1 < 2) < 3", "<"), ("3 >= (2 > 1)", ">=")] {
+        match condition(condition_sql).evaluate(Some(&HashMap::new())) {
+            Err(QueryError::ValidationError(message)) =>
+                assert_eq!(message, format!("Cannot compare boolean with {}", comparator)),
+            result => panic!("{} should be rejected, but got {:?}", condition_sql, result),
+        }
+    }
+    // Booleans can still be compared for equality
+    assert_bool(evaluate("(a < 2) = (2 < 3)", Value::Int(1)), true);
+}
+
+#[test]
+fn arithmetic_with_null_is_null() {
+    for expression_sql in &["NULL + 1", "1 + NULL", "a + 1", "1 - a", "a * 2.5", "a / 2", "a DIV 2", "a % 2",
+                            "(a + 1) * 2", "'x' + a"] {
+        assert!(evaluate(expression_sql, Value::Null).is_null(), "{} should be NULL", expression_sql);
+    }
+    // A NULL result in a comparison makes it unknown
+    assert!(evaluate("a + 1 > 0", Value::Null).is_null());
+    assert_bool(evaluate("a + 1 IS NULL", Value::Null), true);
+}
+
+#[test]
+fn arithmetic_with_mismatched_types_is_rejected() {
+    let identifier_values: HashMap<String, Value> = vec![(String::from("a"), Value::Int(1))].into_iter().collect();
+    for expression_sql in &["a + true", "a - 'x'", "true * 2"] {
+        match condition(expression_sql).evaluate(Some(&identifier_values)) {
+            Err(QueryError::ValidationError(message)) =>
+                assert!(message.starts_with("Invalid types for operator"), "{}: {}", expression_sql, message),
+            result => panic!("{} should be rejected, but got {:?}", expression_sql, result),
+        }
+    }
+}
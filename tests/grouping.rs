@@ -0,0 +1,55 @@
+mod common;
+
+use csbase::engine::asl::Value;
+
+use common::{for_each_backend, int, rows, run, select_values, string};
+
+#[test]
+fn nulls_are_grouped_together() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT, category STRING)");
+        for (id, category) in &[(1, "NULL"), (2, "'pens'"), (3, "NULL"), (4, "'pens'"), (5, "NULL")] {
+            run(database, &format!("INSERT INTO items (id, category) VALUES ({}, {})", id, category));
+        }
+        assert_eq!(select_values(database, "SELECT category, COUNT(*) FROM items GROUP BY category ORDER BY category"),
+                   rows(vec![vec![string("pens"), int(2)], vec![Value::Null, int(3)]]));
+        // A comparison with NULL is unknown, even with itself, so those rows are left out
+        assert_eq!(select_values(database, "SELECT id FROM items WHERE category = category ORDER BY id"),
+                   rows(vec![vec![int(2)], vec![int(4)]]));
+    });
+}
+
+#[test]
+fn column_of_only_nulls_is_a_single_group() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT, category STRING)");
+        for id in 0..4 {
+            run(database, &format!("INSERT INTO items (id) VALUES ({})", id));
+        }
+        assert_eq!(select_values(database, "SELECT category, COUNT(*) FROM items GROUP BY category"),
+                   rows(vec![vec![Value::Null, int(4)]]));
+        assert_eq!(select_values(database, "SELECT DISTINCT category FROM items"), rows(vec![vec![Value::Null]]));
+        assert!(select_values(database, "SELECT id FROM items WHERE category = category").is_empty());
+    });
+}
+
+#[test]
+fn null_and_empty_string_are_distinct_rows() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT, note STRING)");
+        for note in &["NULL", "''", "NULL", "''"] {
+            run(database, &format!("INSERT INTO items (id, note) VALUES (1, {})", note));
+        }
+        assert_eq!(select_values(database, "SELECT DISTINCT * FROM items ORDER BY note"),
+                   rows(vec![vec![int(1), string("")], vec![int(1), Value::Null]]));
+        assert_eq!(select_values(database, "SELECT note, COUNT(*) FROM items GROUP BY note ORDER BY note"),
+                   rows(vec![vec![string(""), int(2)], vec![Value::Null, int(2)]]));
+        assert_eq!(run(database, "SELECT DISTINCT note FROM items ORDER BY note"),
+                   " note \n------\n      \n NULL \n(2 rows)");
+
+        // With an empty null display they look the same, but they are still different rows
+        database.format_settings_mut().null_display = String::new();
+        assert_eq!(run(database, "SELECT DISTINCT note FROM items ORDER BY note"), " note \n------\n      \n      \n(2 rows)");
+        assert_eq!(select_values(database, "SELECT DISTINCT * FROM items").len(), 2);
+    });
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+
+use csbase::config::config::TABLE_DEFINITIONS_FILE;
+use csbase::engine::asl::Type;
+use csbase::engine::db::Database;
+
+use common::{int, open_file_database, open_memory_database, rows, run, select_values, string, TempDir};
+
+#[test]
+fn unquoted_identifiers_are_case_insensitive() {
+    let mut database = open_memory_database();
+    run(&mut database, "CREATE TABLE mytable (id INT, name STRING, PRIMARY KEY (id))");
+    run(&mut database, "INSERT INTO MyTable (ID, Name) VALUES (1, 'one')");
+    assert_eq!(select_values(&database, "SELECT * FROM MyTable"), rows(vec![vec![int(1), string("one")]]));
+    assert_eq!(select_values(&database, "SELECT NAME FROM MYTABLE WHERE Id = 1"), rows(vec![vec![string("one")]]));
+}
+
+#[test]
+fn quoted_identifiers_keep_their_case() {
+    let mut database = open_memory_database();
+    run(&mut database, "CREATE TABLE \"MyTable\" (\"Id\" INT, PRIMARY KEY (\"Id\"))");
+    run(&mut database, "CREATE TABLE mytable (id INT, PRIMARY KEY (id))");
+    run(&mut database, "INSERT INTO \"MyTable\" (\"Id\") VALUES (1)");
+    assert_eq!(select_values(&database, "SELECT \"Id\" FROM \"MyTable\""), rows(vec![vec![int(1)]]));
+    assert!(select_values(&database, "SELECT * FROM MyTable").is_empty());
+}
+
+/**
+* Definition files written before versioning may have mixed case names, which are lowercased when
+* the database is opened, along with the name of the table file
+*/
+#[test]
+fn unversioned_definitions_are_lowercased() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    run(&mut database, "CREATE TABLE \"MyTable\" (\"Id\" INT, \"Name\" STRING)");
+    run(&mut database, "INSERT INTO \"MyTable\" (\"Id\", \"Name\") VALUES (1, 'one')");
+    database.close().expect("Error while closing the database");
+    // The layout before versioning: tables by name, each with its name and its columns
+    let mut tables: HashMap<String, (String, Vec<(String, Type)>)> = HashMap::new();
+    tables.insert(String::from("MyTable"), (String::from("MyTable"), vec![
+        (String::from("Id"), Type::Int),
+        (String::from("Name"), Type::Str),
+    ]));
+    fs::write(dir.path.join(TABLE_DEFINITIONS_FILE), bincode::serialize(&tables).unwrap()).unwrap();
+
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id, name FROM MyTable"), rows(vec![vec![int(1), string("one")]]));
+    assert!(dir.path.join("mytable_data.csbase").exists());
+    assert!(!dir.path.join("MyTable_data.csbase").exists());
+}
+
+#[test]
+fn unversioned_names_that_only_differ_in_case_are_rejected() {
+    let dir = TempDir::new();
+    open_file_database(&dir).close().expect("Error while closing the database");
+    let mut tables: HashMap<String, (String, Vec<(String, Type)>)> = HashMap::new();
+    tables.insert(String::from("Items"), (String::from("Items"), vec![(String::from("id"), Type::Int)]));
+    tables.insert(String::from("items"), (String::from("items"), vec![(String::from("id"), Type::Int)]));
+    fs::write(dir.path.join(TABLE_DEFINITIONS_FILE), bincode::serialize(&tables).unwrap()).unwrap();
+    assert!(Database::with_config(dir.config()).bootstrap().is_err());
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use std::fs;
+use std::ops::Range;
+
+use csbase::config::config::Config;
+use csbase::engine::asl::{Record, Value};
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+use csbase::engine::fs::DBFileSystem;
+use csbase::engine::pages::PAGE_SIZE;
+use csbase::engine::storage::StorageEngine;
+
+use common::{bootstrapped, int, open_file_database, rows, run, select_values, CountingStorage, TempDir};
+
+fn open_batched_database(dir: &TempDir) -> Database {
+    bootstrapped(Database::with_config(Config { insert_batch_rows: Some(100), ..dir.config() }))
+}
+
+fn insert_items(database: &mut Database, ids: Range<i32>) {
+    for id in ids {
+        run(database, &format!("INSERT INTO items (id) VALUES ({})", id));
+    }
+}
+
+fn expected_items(ids: Range<i32>) -> Vec<Record> {
+    rows(ids.map(|id| vec![int(id)]).collect())
+}
+
+/**
+* Append a few bytes to the table file, so it ends with a truncated page and every append fails.
+* Returns the original contents, to restore them
+*/
+fn truncate_last_page(dir: &TempDir) -> Vec<u8> {
+    let data_path = dir.path.join("items_data.csbase");
+    let data = fs::read(&data_path).expect("Error while reading the table file");
+    let mut truncated_data = data.clone();
+    truncated_data.extend(&[0u8; 10]);
+    fs::write(&data_path, &truncated_data).expect("Error while writing the table file");
+    data
+}
+
+fn restore_last_page(dir: &TempDir, data: &[u8]) {
+    fs::write(dir.path.join("items_data.csbase"), data).expect("Error while writing the table file");
+}
+
+#[test]
+fn full_buffer_is_written_in_one_batch() {
+    let dir = TempDir::new();
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)).insert_batch_rows(100));
+    run(&mut database, "CREATE TABLE items (id INT)");
+    insert_items(&mut database, 0..250);
+    assert_eq!(calls.borrow().inserted_batches, vec![100, 100]);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..250));
+    assert_eq!(calls.borrow().inserted_batches, vec![100, 100, 50]);
+}
+
+#[test]
+fn batch_insert_fills_the_pages_like_single_inserts() {
+    let batch_dir = TempDir::new();
+    let single_dir = TempDir::new();
+    let table = {
+        let mut database = open_file_database(&batch_dir);
+        run(&mut database, "CREATE TABLE items (id INT, name STRING)");
+        database.close().expect("Error while closing the database");
+        let mut database = open_file_database(&single_dir);
+        run(&mut database, "CREATE TABLE items (id INT, name STRING)");
+        database.close().expect("Error while closing the database");
+        DBFileSystem::with_config(&batch_dir.config()).load_definitions().unwrap().get_table("items").unwrap().clone()
+    };
+    let records: Vec<Record> = (0..500)
+        .map(|id| Record { values: vec![int(id), Value::Str(format!("item number {}", id))] })
+        .collect();
+    let batch_storage = DBFileSystem::with_config(&batch_dir.config());
+    batch_storage.insert_records(&table, &records[..200]).unwrap();
+    batch_storage.insert_records(&table, &records[200..]).unwrap();
+    let single_storage = DBFileSystem::with_config(&single_dir.config());
+    for record in &records {
+        single_storage.insert_record(&table, record).unwrap();
+    }
+    let batch_data = fs::read(batch_dir.path.join("items_data.csbase")).unwrap();
+    assert!(batch_data.len() > 2 * PAGE_SIZE);
+    assert_eq!(batch_data, fs::read(single_dir.path.join("items_data.csbase")).unwrap());
+}
+
+#[test]
+fn buffered_rows_are_written_on_close() {
+    let dir = TempDir::new();
+    let mut database = open_batched_database(&dir);
+    run(&mut database, "CREATE TABLE items (id INT)");
+    insert_items(&mut database, 0..10);
+    database.close().expect("Error while closing the database");
+
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..10));
+}
+
+#[test]
+fn buffered_rows_are_written_when_the_database_is_dropped() {
+    let dir = TempDir::new();
+    let mut database = open_batched_database(&dir);
+    run(&mut database, "CREATE TABLE items (id INT)");
+    insert_items(&mut database, 0..10);
+    drop(database);
+
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..10));
+}
+
+#[test]
+fn rows_that_fail_to_be_written_are_kept_for_the_next_flush() {
+    let dir = TempDir::new();
+    let mut database = open_batched_database(&dir);
+    run(&mut database, "CREATE TABLE items (id INT)");
+    insert_items(&mut database, 0..5);
+    let data = truncate_last_page(&dir);
+
+    assert!(database.run_query("SELECT id FROM items").is_err());
+    let warnings = database.get_warnings();
+    assert!(warnings.iter().any(|warning| warning.contains("5 inserted rows of items") && warning.contains("Truncated page")),
+            "Unexpected warnings {:?}", warnings);
+    // More rows can be buffered while the earlier ones wait to be written
+    insert_items(&mut database, 5..8);
+
+    restore_last_page(&dir, &data);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..8));
+    database.close().expect("Error while closing the database");
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..8));
+}
+
+#[test]
+fn insert_that_fills_the_buffer_fails_with_the_write() {
+    let dir = TempDir::new();
+    let mut database = bootstrapped(Database::with_config(Config { insert_batch_rows: Some(3), ..dir.config() }));
+    run(&mut database, "CREATE TABLE items (id INT, PRIMARY KEY (id))");
+    insert_items(&mut database, 0..2);
+    let data = truncate_last_page(&dir);
+
+    match database.run_query("INSERT INTO items (id) VALUES (2)") {
+        Err(QueryError::PagingError(message)) => assert!(message.contains("Truncated page"), "{}", message),
+        result => panic!("The insert should fail, but got {:?}", result),
+    }
+    assert!(database.get_warnings().iter().any(|warning| warning.contains("2 inserted rows of items")));
+
+    restore_last_page(&dir, &data);
+    // The failed INSERT left nothing behind, not even its key
+    run(&mut database, "INSERT INTO items (id) VALUES (2)");
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..3));
+}
+
+#[test]
+fn closing_after_a_failed_flush_reports_the_lost_rows() {
+    let dir = TempDir::new();
+    let mut database = open_batched_database(&dir);
+    run(&mut database, "CREATE TABLE items (id INT)");
+    insert_items(&mut database, 0..5);
+    database.run_query("SELECT id FROM items").expect("Error while flushing the first rows");
+    insert_items(&mut database, 5..9);
+    let data = truncate_last_page(&dir);
+
+    match database.close() {
+        Err(QueryError::LostRows(message)) => assert!(message.starts_with("4 inserted rows"), "{}", message),
+        result => panic!("Closing should report the lost rows, but got {:?}", result),
+    }
+    restore_last_page(&dir, &data);
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), expected_items(0..5));
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+use csbase::engine::fs::DBFileSystem;
+
+use csbase::engine::asl::Value;
+
+use common::{bootstrapped, for_each_backend, int, open_memory_database, rows, run, select_values, string, CountingStorage, TempDir};
+
+fn create_tables(database: &mut Database, archive_columns: &str) {
+    run(database, "CREATE TABLE t (id INT, created DATE)");
+    run(database, &format!("CREATE TABLE archive ({})", archive_columns));
+    run(database, "INSERT INTO t (id, created) VALUES (1, DATE '2022-12-31')");
+}
+
+fn assert_rejected(database: &mut Database, query: &str, expected_message: &str) {
+    assert_rejected_into(database, "archive", query, expected_message);
+}
+
+fn assert_rejected_into(database: &mut Database, table: &str, query: &str, expected_message: &str) {
+    match database.run_query(query) {
+        Err(QueryError::ValidationError(message)) => assert!(message.contains(expected_message), "{}", message),
+        result => panic!("{} should be rejected, but got {:?}", query, result),
+    }
+    assert!(select_values(database, &format!("SELECT * FROM {}", table)).is_empty());
+}
+
+fn create_reordered_tables(database: &mut Database) {
+    run(database, "CREATE TABLE src (x STRING, y INT)");
+    run(database, "CREATE TABLE dst (a INT, b STRING, c INT DEFAULT 7)");
+    run(database, "INSERT INTO src (x, y) VALUES ('one', 1)");
+    run(database, "INSERT INTO src (x, y) VALUES ('two', 2)");
+}
+
+#[test]
+fn archived_rows_are_streamed_into_batch_inserts() {
+    let dir = TempDir::new();
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)));
+    run(&mut database, "CREATE TABLE t (id INT, created DATE)");
+    run(&mut database, "CREATE TABLE archive (id INT, created DATE)");
+    for id in 0..400 {
+        let created = if id % 4 == 3 { "2023-06-01" } else { "2022-06-01" };
+        run(&mut database, &format!("INSERT INTO t (id, created) VALUES ({}, DATE '{}')", id, created));
+    }
+    calls.borrow_mut().inserted_batches.clear();
+
+    let result = run(&mut database, "INSERT INTO archive SELECT * FROM t WHERE created < DATE '2023-01-01'");
+    assert_eq!(result, "Inserted 300 records");
+    // The rows are inserted while t is scanned, a full batch at a time
+    assert_eq!(calls.borrow().inserted_batches, vec![256, 44]);
+    let archived_ids: Vec<i32> = (0..400).filter(|id| id % 4 != 3).collect();
+    assert_eq!(select_values(&database, "SELECT id FROM archive ORDER BY id"),
+               rows(archived_ids.into_iter().map(|id| vec![int(id)]).collect()));
+    assert_eq!(select_values(&database, "SELECT * FROM archive WHERE created >= DATE '2023-01-01'"), vec![]);
+}
+
+#[test]
+fn archive_with_fewer_columns_is_rejected() {
+    let mut database = open_memory_database();
+    create_tables(&mut database, "id INT");
+    assert_rejected(&mut database, "INSERT INTO archive SELECT * FROM t", "archive has 1 columns but the SELECT returns 2");
+}
+
+#[test]
+fn archive_with_more_columns_is_rejected() {
+    let mut database = open_memory_database();
+    create_tables(&mut database, "id INT, created DATE, reason STRING");
+    assert_rejected(&mut database, "INSERT INTO archive SELECT * FROM t", "archive has 3 columns but the SELECT returns 2");
+}
+
+#[test]
+fn archive_with_other_column_types_is_rejected() {
+    let mut database = open_memory_database();
+    create_tables(&mut database, "id INT, created TIMESTAMP");
+    assert_rejected(&mut database, "INSERT INTO archive SELECT * FROM t", "Can't insert created of type 'Date' into column created");
+    run(&mut database, "CREATE TABLE swapped (created DATE, id INT)");
+    assert_rejected(&mut database, "INSERT INTO swapped SELECT * FROM t", "Can't insert id of type 'Int' into column created");
+}
+
+#[test]
+fn selected_columns_are_mapped_by_the_insert_column_list() {
+    for_each_backend(|database| {
+        create_reordered_tables(database);
+        assert_eq!(run(database, "INSERT INTO dst (b, a) SELECT x, y FROM src"), "Inserted 2 records");
+        // The omitted column gets its default
+        assert_eq!(select_values(database, "SELECT * FROM dst ORDER BY a"),
+                   rows(vec![vec![int(1), string("one"), int(7)], vec![int(2), string("two"), int(7)]]));
+        run(database, "INSERT INTO dst (c, b, a) SELECT y * 10, x, NULL FROM src WHERE y = 2");
+        assert_eq!(select_values(database, "SELECT a, b, c FROM dst WHERE c = 20"),
+                   rows(vec![vec![Value::Null, string("two"), int(20)]]));
+    });
+}
+
+#[test]
+fn reordered_columns_with_other_arity_are_rejected() {
+    let mut database = open_memory_database();
+    create_reordered_tables(&mut database);
+    assert_rejected_into(&mut database, "dst", "INSERT INTO dst (b, a) SELECT x FROM src",
+                         "The INSERT lists 2 columns but the SELECT returns 1");
+    assert_rejected_into(&mut database, "dst", "INSERT INTO dst (b) SELECT x, y FROM src",
+                         "The INSERT lists 1 columns but the SELECT returns 2");
+}
+
+#[test]
+fn reordered_columns_with_other_types_are_rejected() {
+    let mut database = open_memory_database();
+    create_reordered_tables(&mut database);
+    // In definition order the types would match, but b is listed first
+    assert_rejected_into(&mut database, "dst", "INSERT INTO dst (b, a) SELECT y, x FROM src",
+                         "Can't insert y of type 'Int' into column b of type 'Str'");
+    assert_rejected_into(&mut database, "dst", "INSERT INTO dst (a, b) SELECT x, y FROM src",
+                         "Can't insert x of type 'Str' into column a of type 'Int'");
+}
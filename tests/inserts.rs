@@ -0,0 +1,31 @@
+mod common;
+
+use csbase::engine::asl::Value;
+use csbase::engine::db::Database;
+
+use common::{for_each_backend, int, rows, run, select_values, string};
+
+fn create_notes(database: &mut Database) {
+    run(database, "CREATE TABLE notes (id INT, title STRING DEFAULT 'untitled', score INT DEFAULT 0, body STRING, \
+                   PRIMARY KEY (id))");
+}
+
+#[test]
+fn omitted_columns_get_their_default_or_null() {
+    for_each_backend(|database| {
+        create_notes(database);
+        run(database, "INSERT INTO notes (id) VALUES (1)");
+        run(database, "INSERT INTO notes (id, body) VALUES (2, 'text')");
+        run(database, "INSERT INTO notes (id, title, score) VALUES (3, NULL, NULL)");
+        run(database, "INSERT INTO notes (id, score, body) VALUES (4, 9, NULL)");
+        assert_eq!(select_values(database, "SELECT id, title, score, body FROM notes ORDER BY id"), rows(vec![
+            vec![int(1), string("untitled"), int(0), Value::Null],
+            vec![int(2), string("untitled"), int(0), string("text")],
+            // An explicit NULL isn't replaced by the default
+            vec![int(3), Value::Null, Value::Null, Value::Null],
+            vec![int(4), string("untitled"), int(9), Value::Null],
+        ]));
+        assert_eq!(select_values(database, "SELECT id FROM notes WHERE body IS NULL AND score = 0"),
+                   rows(vec![vec![int(1)]]));
+    });
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::time::Duration;
+
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+
+use common::{bootstrapped, for_each_backend, int, rows, run, select_values};
+
+fn create_tables(database: &mut Database, a_rows: i32, b_rows: i32) {
+    run(database, "CREATE TABLE a (id INT)");
+    run(database, "CREATE TABLE b (id INT)");
+    for id in 0..a_rows {
+        run(database, &format!("INSERT INTO a (id) VALUES ({})", id));
+    }
+    for id in 0..b_rows {
+        run(database, &format!("INSERT INTO b (id) VALUES ({})", id));
+    }
+}
+
+#[test]
+fn cross_join_combines_every_pair_of_rows() {
+    for_each_backend(|database| {
+        create_tables(database, 3, 4);
+        let expected_rows = (0..3).flat_map(|a| (0..4).map(move |b| vec![int(a), int(b)])).collect();
+        assert_eq!(select_values(database, "SELECT a.id, b.id FROM a CROSS JOIN b ORDER BY a.id, b.id"), rows(expected_rows));
+        assert_eq!(select_values(database, "SELECT COUNT(*) FROM a CROSS JOIN b"), rows(vec![vec![int(12)]]));
+        assert_eq!(select_values(database, "SELECT COUNT(*) FROM a CROSS JOIN b WHERE a.id = b.id"), rows(vec![vec![int(3)]]));
+        // A join with an empty table has no rows
+        run(database, "DELETE FROM b");
+        assert!(select_values(database, "SELECT a.id, b.id FROM a CROSS JOIN b").is_empty());
+    });
+}
+
+#[test]
+fn joins_past_the_row_limit_are_rejected() {
+    let mut database = bootstrapped(Database::in_memory().max_join_rows(12));
+    create_tables(&mut database, 3, 4);
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM a CROSS JOIN b"), rows(vec![vec![int(12)]]));
+    run(&mut database, "INSERT INTO b (id) VALUES (4)");
+    match database.run_query("SELECT a.id, b.id FROM a CROSS JOIN b") {
+        Err(QueryError::ValidationError(message)) => assert!(message.contains("more than 12 rows"), "{}", message),
+        result => panic!("The join should be rejected, but got {:?}", result),
+    }
+    // The limit counts the joined rows, not the pairs the condition is evaluated for
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM a JOIN b ON a.id = b.id"), rows(vec![vec![int(3)]]));
+}
+
+#[test]
+fn join_past_the_timeout_is_aborted() {
+    let mut database = bootstrapped(Database::in_memory());
+    create_tables(&mut database, 10, 10);
+    database.set_query_timeout(Some(Duration::from_millis(0)));
+    match database.run_query("SELECT a.id, b.id FROM a CROSS JOIN b") {
+        Err(QueryError::Timeout(message)) => assert!(message.contains("more than 0 ms"), "{}", message),
+        result => panic!("The join should time out, but got {:?}", result),
+    }
+    database.set_query_timeout(None);
+    run(&mut database, "SELECT a.id, b.id FROM a CROSS JOIN b");
+}
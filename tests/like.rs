@@ -0,0 +1,70 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use csbase::engine::asl::Value;
+use csbase::engine::db::Database;
+use csbase::engine::fs::DBFileSystem;
+
+use common::{bootstrapped, int, rows, run, select_values, CountingStorage, StorageCalls, TempDir};
+
+const ROWS: i32 = 3000;
+
+/**
+* Database with a multi-page items table where every 500th row, starting with 50, has a name that
+* starts with match, and the calls its storage receives after it's filled
+*/
+fn counting_database(dir: &TempDir) -> (Database, Rc<RefCell<StorageCalls>>) {
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)));
+    run(&mut database, "CREATE TABLE items (id INT, name STRING)");
+    for id in 0..ROWS {
+        let name = if id % 500 == 50 { format!("match {}", id) } else { format!("item {}", id) };
+        run(&mut database, &format!("INSERT INTO items (id, name) VALUES ({}, '{}')", id, name));
+    }
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM items"), rows(vec![vec![int(ROWS)]]));
+    *calls.borrow_mut() = StorageCalls::default();
+    (database, calls)
+}
+
+fn matching_ids() -> Vec<i32> {
+    (0..ROWS).filter(|id| id % 500 == 50).collect()
+}
+
+#[test]
+fn like_filters_a_multi_page_table() {
+    let dir = TempDir::new();
+    let (database, _) = counting_database(&dir);
+    let expected = rows(matching_ids().into_iter().map(|id| vec![int(id)]).collect());
+    assert_eq!(select_values(&database, "SELECT id FROM items WHERE name LIKE 'match%'"), expected);
+    assert_eq!(select_values(&database, "SELECT id FROM items WHERE name LIKE '%tch _%'"), expected);
+    assert_eq!(select_values(&database, "SELECT COUNT(*) FROM items WHERE name NOT LIKE 'match%'"),
+               rows(vec![vec![int(ROWS - expected.len() as i32)]]));
+}
+
+#[test]
+fn like_is_evaluated_on_each_page_as_it_is_read() {
+    let dir = TempDir::new();
+    let (database, calls) = counting_database(&dir);
+    let table_pages = database.table_size("items").expect("Error while reading the table size").page_count;
+    assert!(table_pages > 5, "The table should span several pages, but has {}", table_pages);
+
+    let mut matches = database.query_iter("SELECT id FROM items WHERE name LIKE 'match%'")
+        .expect("Error while reading the table");
+    let reads_before_scan = calls.borrow().page_reads.len();
+    let first_match = matches.next().expect("There should be a match").expect("Error while reading a row");
+    assert_eq!(first_match.get_values().to_vec(), vec![int(50)]);
+    // The first match is in the first page, so the rest of the table hasn't been read
+    assert_eq!(calls.borrow().page_reads.len(), reads_before_scan + 1);
+
+    let other_matches: Vec<i32> = matches
+        .map(|row| match row.expect("Error while reading a row").get_values()[0] {
+            Value::Int(id) => id,
+            ref value => panic!("Unexpected id {:?}", value),
+        })
+        .collect();
+    assert_eq!(other_matches, matching_ids()[1..].to_vec());
+    // Every page is read once, and one more read finds the end of the table
+    assert_eq!(calls.borrow().page_reads.len(), reads_before_scan + table_pages + 1);
+}
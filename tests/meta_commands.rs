@@ -0,0 +1,22 @@
+mod common;
+
+use csbase::engine::format::DEFAULT_NULL_DISPLAY;
+use csbase::repl::meta_commands::run_meta_command;
+
+use common::{open_file_database, run, TempDir};
+
+#[test]
+fn null_display_only_lasts_for_the_session() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    run(&mut database, "CREATE TABLE items (id INT, note STRING)");
+    run(&mut database, "INSERT INTO items (id) VALUES (1)");
+    let result = run_meta_command(&mut database, "\\pset null '(null)'").expect("Error while setting the null display");
+    assert_eq!(result, "Null display is \"(null)\".");
+    assert_eq!(run(&mut database, "SELECT note FROM items"), " note   \n--------\n (null) \n(1 row)");
+    database.close().expect("Error while closing the database");
+
+    let mut database = open_file_database(&dir);
+    assert_eq!(database.format_settings().null_display, DEFAULT_NULL_DISPLAY);
+    assert_eq!(run(&mut database, "SELECT note FROM items"), " note \n------\n NULL \n(1 row)");
+}
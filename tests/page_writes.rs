@@ -0,0 +1,99 @@
+mod common;
+
+use std::convert::TryInto;
+use std::fs;
+
+use csbase::engine::db::Database;
+use csbase::engine::fs::DBFileSystem;
+use csbase::config::config::Config;
+use csbase::engine::pages::{Page, PAGE_HEADER_SIZE, PAGE_SIZE};
+
+use common::{bootstrapped, int, rows, run, select_values, string, CountingStorage, TempDir};
+
+const ROWS: i32 = 300;
+
+/**
+* Read the pages of the table file
+*/
+fn read_pages(dir: &TempDir, table: &str) -> Vec<Vec<u8>> {
+    let bytes = fs::read(dir.path.join(format!("{}_data.csbase", table))).expect("Error while reading the table file");
+    bytes.chunks(PAGE_SIZE).map(|page| page.to_vec()).collect()
+}
+
+#[test]
+fn updating_a_row_rewrites_only_its_page() {
+    let dir = TempDir::new();
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)));
+    run(&mut database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+    for id in 0..ROWS {
+        run(&mut database, &format!("INSERT INTO items (id, name) VALUES ({}, 'item number {} with some padding')", id, id));
+    }
+    let pages_before = read_pages(&dir, "items");
+    assert!(pages_before.len() > 2, "The table should span several pages, but it has {}", pages_before.len());
+
+    run(&mut database, "UPDATE items SET name = 'renamed' WHERE id = 150");
+
+    let page_changes = calls.borrow().page_changes.clone();
+    assert_eq!(page_changes.len(), 1);
+    assert_eq!(page_changes[0].len(), 1, "Only the page of the row should change, but {:?} did", page_changes[0]);
+    let changed_page_id = page_changes[0][0];
+    let pages_after = read_pages(&dir, "items");
+    assert_eq!(pages_after.len(), pages_before.len());
+    for (idx, (page_before, page_after)) in pages_before.iter().zip(&pages_after).enumerate() {
+        // Page ids start at 1
+        let page_id = idx as u32 + 1;
+        assert_eq!(page_before != page_after, page_id == changed_page_id, "Unexpected contents of page {}", page_id);
+    }
+    assert_eq!(select_values(&database, "SELECT id, name FROM items WHERE name = 'renamed'"),
+               rows(vec![vec![int(150), string("renamed")]]));
+}
+
+#[test]
+fn deleting_rows_rewrites_only_their_pages() {
+    let dir = TempDir::new();
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)));
+    run(&mut database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+    for id in 0..ROWS {
+        run(&mut database, &format!("INSERT INTO items (id, name) VALUES ({}, 'item number {} with some padding')", id, id));
+    }
+    let page_count = read_pages(&dir, "items").len();
+
+    run(&mut database, "DELETE FROM items WHERE id = 0 OR id = 1");
+
+    assert_eq!(calls.borrow().page_changes, vec![vec![1]]);
+    assert_eq!(read_pages(&dir, "items").len(), page_count);
+    assert_eq!(select_values(&database, "SELECT id FROM items WHERE id < 3"), rows(vec![vec![int(2)]]));
+}
+
+/**
+* Fill a table through a database with the given fill factor, returning the free space of each of its
+* pages but the last one, which may not be full yet
+*/
+fn free_space_of_full_pages(fill_factor: u8) -> Vec<usize> {
+    let dir = TempDir::new();
+    let mut database = bootstrapped(Database::with_config(Config { fill_factor, ..dir.config() }));
+    run(&mut database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+    for id in 0..ROWS {
+        run(&mut database, &format!("INSERT INTO items (id, name) VALUES ({}, 'item number {} with some padding')", id, id));
+    }
+    let pages = read_pages(&dir, "items");
+    pages[..pages.len() - 1].iter()
+        .map(|page| Page::from_bytes(page.as_slice().try_into().unwrap()).get_header().get_free_space())
+        .collect()
+}
+
+#[test]
+fn pages_are_full_at_the_configured_fill_factor() {
+    let page_data_size = PAGE_SIZE - PAGE_HEADER_SIZE;
+    let free_space = free_space_of_full_pages(60);
+    assert!(free_space.len() > 1);
+    for page_free_space in &free_space {
+        assert!(*page_free_space >= page_data_size * 40 / 100, "A page only has {} free bytes", page_free_space);
+    }
+    // Filled to 100%, a page only has less space left than a record needs
+    for page_free_space in free_space_of_full_pages(100) {
+        assert!(page_free_space < 100, "A full page still has {} free bytes", page_free_space);
+    }
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use csbase::engine::asl::Value;
+use csbase::engine::db::Database;
+
+use common::{for_each_backend, int, open_memory_database, rows, run, select_values};
+
+/**
+* Get the columns and values of the only row a SELECT returns
+*/
+fn single_row(database: &Database, query: &str) -> (Vec<String>, Vec<Value>) {
+    let mut result = database.query_iter(query).unwrap_or_else(|error| panic!("{} failed: {:?}", query, error));
+    let row = result.next().expect("The SELECT should return a row").expect("Error while reading a row");
+    assert!(result.next().is_none(), "{} should return a single row", query);
+    (row.get_columns().to_vec(), row.get_values().to_vec())
+}
+
+#[test]
+fn select_without_from() {
+    let mut database = open_memory_database();
+    assert_eq!(single_row(&database, "SELECT 1+1"), (vec![String::from("1 + 1")], vec![int(2)]));
+    assert_eq!(single_row(&database, "SELECT 1 + 1 AS two, 2 * 3"),
+               (vec![String::from("two"), String::from("2 * 3")], vec![int(2), int(6)]));
+    assert_eq!(run(&mut database, "SELECT 1+1"), " 1 + 1 \n-------\n 2     \n(1 row)");
+    assert_eq!(run(&mut database, "SELECT 1 + 1 AS two"), " two \n-----\n 2   \n(1 row)");
+}
+
+#[test]
+fn count_of_every_row() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT)");
+        assert_eq!(select_values(database, "SELECT COUNT(*) FROM items"), rows(vec![vec![int(0)]]));
+        for id in 0..3 {
+            run(database, &format!("INSERT INTO items (id) VALUES ({})", id));
+        }
+        assert_eq!(single_row(database, "SELECT COUNT(*) FROM items"), (vec![String::from("COUNT(*)")], vec![int(3)]));
+        assert_eq!(single_row(database, "SELECT COUNT(*) AS n FROM items WHERE id > 0"), (vec![String::from("n")], vec![int(2)]));
+        assert_eq!(select_values(database, "SELECT COUNT(*) FROM items WHERE 1 = 0"), rows(vec![vec![int(0)]]));
+        assert_eq!(run(database, "SELECT COUNT(*) FROM items"), " COUNT(*) \n----------\n 3        \n(1 row)");
+    });
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use csbase::engine::asl::Record;
+use csbase::engine::db::Database;
+
+use common::{for_each_backend, run, select_values};
+
+const ROWS: i32 = 300;
+
+fn select_row_ids(database: &Database) -> Vec<Record> {
+    select_values(database, "SELECT id, rowid FROM items ORDER BY id")
+}
+
+#[test]
+fn row_ids_survive_deletes_updates_and_repair() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+        for id in 0..ROWS {
+            run(database, &format!("INSERT INTO items (id, name) VALUES ({}, 'item number {} with some padding')", id, id));
+        }
+        let row_ids = select_row_ids(database);
+
+        run(database, "DELETE FROM items WHERE id % 3 = 0");
+        run(database, "UPDATE items SET name = 'short' WHERE id % 3 = 1");
+        // The ids are 0..ROWS, so each id is the position of its row
+        let kept_row_ids: Vec<Record> = row_ids.into_iter().enumerate()
+            .filter(|(id, _)| id % 3 != 0)
+            .map(|(_, record)| record)
+            .collect();
+        assert_eq!(select_row_ids(database), kept_row_ids);
+
+        // Repairing rebuilds every page, packing its items again
+        run(database, "REPAIR TABLE items");
+        assert_eq!(select_row_ids(database), kept_row_ids);
+    });
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+use csbase::engine::rows::Row;
+
+use common::{open_memory_database, run};
+
+fn create_items(database: &mut Database) {
+    run(database, "CREATE TABLE items (id INT, price FLOAT, available BOOL, name STRING)");
+    run(database, "INSERT INTO items (id, price, available, name) VALUES (1, 1.5, true, 'pen')");
+    run(database, "INSERT INTO items (id) VALUES (2)");
+}
+
+fn get_rows(database: &Database) -> Vec<Row> {
+    database.query_iter("SELECT id, price, available, name FROM items ORDER BY id").unwrap()
+        .map(|row| row.expect("Error while reading a row"))
+        .collect()
+}
+
+fn assert_rejected<T: std::fmt::Debug>(result: Result<Option<T>, QueryError>, expected_message: &str) {
+    match result {
+        Err(QueryError::ValidationError(message)) => assert_eq!(message, expected_message),
+        result => panic!("Expected a ValidationError, but got {:?}", result),
+    }
+}
+
+#[test]
+fn typed_accessors_get_the_values() {
+    let mut database = open_memory_database();
+    create_items(&mut database);
+    let rows = get_rows(&database);
+    assert_eq!(rows[0].get_i32("id").unwrap(), Some(1));
+    assert_eq!(rows[0].get_f32("price").unwrap(), Some(1.5));
+    assert_eq!(rows[0].get_bool("available").unwrap(), Some(true));
+    assert_eq!(rows[0].get_str("name").unwrap(), Some("pen"));
+}
+
+#[test]
+fn typed_accessors_get_none_for_null() {
+    let mut database = open_memory_database();
+    create_items(&mut database);
+    let row = &get_rows(&database)[1];
+    assert_eq!(row.get_f32("price").unwrap(), None);
+    assert_eq!(row.get_bool("available").unwrap(), None);
+    assert_eq!(row.get_str("name").unwrap(), None);
+    // A NULL has no type, so any accessor gets None for it
+    assert_eq!(row.get_i32("name").unwrap(), None);
+}
+
+#[test]
+fn typed_accessors_reject_other_types() {
+    let mut database = open_memory_database();
+    create_items(&mut database);
+    let row = &get_rows(&database)[0];
+    assert_rejected(row.get_i32("name"), "The column name is not Int, it's Str");
+    assert_rejected(row.get_f32("id"), "The column id is not Float, it's Int");
+    assert_rejected(row.get_bool("price"), "The column price is not Bool, it's Float");
+    assert_rejected(row.get_str("available"), "The column available is not Str, it's Bool");
+}
+
+#[test]
+fn typed_accessors_reject_unknown_columns() {
+    let mut database = open_memory_database();
+    create_items(&mut database);
+    let row = &get_rows(&database)[0];
+    assert_rejected(row.get_i32("missing"), "The column missing isn't in the result");
+}
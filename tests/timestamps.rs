@@ -0,0 +1,47 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use csbase::engine::asl::Value;
+
+use common::{for_each_backend, int, rows, run, select_values};
+
+#[test]
+fn default_timestamps_of_later_rows_are_later() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE events (id INT, created TIMESTAMP DEFAULT CURRENT_TIMESTAMP)");
+        for id in 1..4 {
+            run(database, &format!("INSERT INTO events (id) VALUES ({})", id));
+            thread::sleep(Duration::from_millis(2));
+        }
+        let created: Vec<i64> = select_values(database, "SELECT created FROM events ORDER BY id").into_iter()
+            .map(|record| match record.values[0] {
+                Value::Timestamp(created) => created,
+                ref value => panic!("Expected a timestamp, but got {:?}", value),
+            })
+            .collect();
+        assert!(created[0] < created[1] && created[1] < created[2], "{:?}", created);
+        assert_eq!(select_values(database, "SELECT id FROM events ORDER BY created DESC"),
+                   rows(vec![vec![int(3)], vec![int(2)], vec![int(1)]]));
+    });
+}
+
+#[test]
+fn timestamps_are_compared_and_ordered() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE events (id INT, created TIMESTAMP)");
+        run(database, "INSERT INTO events (id, created) VALUES (1, TIMESTAMP '2024-03-01 10:00:00')");
+        run(database, "INSERT INTO events (id, created) VALUES (2, TIMESTAMP '2023-12-31 23:59:59.999999')");
+        run(database, "INSERT INTO events (id, created) VALUES (3, TIMESTAMP '2024-03-01 10:00:00.5')");
+        run(database, "INSERT INTO events (id, created) VALUES (4, NULL)");
+        assert_eq!(select_values(database, "SELECT id FROM events ORDER BY created"),
+                   rows(vec![vec![int(2)], vec![int(1)], vec![int(3)], vec![int(4)]]));
+        assert_eq!(select_values(database, "SELECT id FROM events WHERE created > TIMESTAMP '2024-01-01' ORDER BY id"),
+                   rows(vec![vec![int(1)], vec![int(3)]]));
+        assert_eq!(select_values(database, "SELECT id FROM events WHERE created <= TIMESTAMP '2024-03-01 10:00:00' ORDER BY id"),
+                   rows(vec![vec![int(1)], vec![int(2)]]));
+        assert_eq!(select_values(database, "SELECT id FROM events WHERE created = TIMESTAMP '2024-03-01T10:00:00'"),
+                   rows(vec![vec![int(1)]]));
+    });
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::fs;
+
+use csbase::engine::db::Database;
+
+use common::{for_each_backend, int, open_file_database, rows, run, select_values, string, TempDir};
+
+fn create_items(database: &mut Database) {
+    run(database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+    run(database, "INSERT INTO items (id, name) VALUES (1, 'one')");
+    run(database, "INSERT INTO items (id, name) VALUES (2, 'two')");
+}
+
+#[test]
+fn rollback_to_savepoint_discards_the_later_statements() {
+    for_each_backend(|database| {
+        create_items(database);
+        run(database, "BEGIN");
+        run(database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+        run(database, "UPDATE items SET name = 'uno' WHERE id = 1");
+        run(database, "SAVEPOINT before_delete");
+        run(database, "DELETE FROM items WHERE id = 2");
+        run(database, "ROLLBACK TO SAVEPOINT before_delete");
+        run(database, "COMMIT");
+        assert_eq!(select_values(database, "SELECT id, name FROM items ORDER BY id"), rows(vec![
+            vec![int(1), string("uno")],
+            vec![int(2), string("two")],
+            vec![int(3), string("three")],
+        ]));
+    });
+}
+
+#[test]
+fn staged_statements_are_only_visible_after_commit() {
+    for_each_backend(|database| {
+        create_items(database);
+        run(database, "BEGIN");
+        run(database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+        assert_eq!(select_values(database, "SELECT id FROM items ORDER BY id"), rows(vec![vec![int(1)], vec![int(2)]]));
+        run(database, "COMMIT");
+        assert_eq!(select_values(database, "SELECT id FROM items ORDER BY id"),
+                   rows(vec![vec![int(1)], vec![int(2)], vec![int(3)]]));
+    });
+}
+
+#[test]
+fn failed_commit_applies_nothing() {
+    for_each_backend(|database| {
+        create_items(database);
+        run(database, "BEGIN");
+        run(database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+        run(database, "UPDATE items SET name = 'changed'");
+        run(database, "DELETE FROM items WHERE id = 1");
+        // Staging doesn't check the primary key, so the duplicate is only found while committing
+        run(database, "INSERT INTO items (id, name) VALUES (3, 'again')");
+        assert!(database.run_query("COMMIT").is_err());
+        assert_eq!(select_values(database, "SELECT id, name FROM items ORDER BY id"), rows(vec![
+            vec![int(1), string("one")],
+            vec![int(2), string("two")],
+        ]));
+        // The transaction is over, and the unique keys of the discarded rows are forgotten
+        assert!(database.run_query("COMMIT").is_err());
+        run(database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+        assert_eq!(select_values(database, "SELECT id FROM items ORDER BY id"),
+                   rows(vec![vec![int(1)], vec![int(2)], vec![int(3)]]));
+    });
+}
+
+#[test]
+fn failed_commit_leaves_the_files_untouched() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_items(&mut database);
+    run(&mut database, "BEGIN");
+    run(&mut database, "UPDATE items SET name = 'changed'");
+    run(&mut database, "INSERT INTO items (id, name) VALUES (1, 'duplicate')");
+    assert!(database.run_query("COMMIT").is_err());
+    database.close().expect("Error while closing the database");
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id, name FROM items ORDER BY id"),
+               rows(vec![vec![int(1), string("one")], vec![int(2), string("two")]]));
+}
+
+#[test]
+fn commit_with_buffered_inserts() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    database.set_insert_batch_rows(Some(10)).unwrap();
+    create_items(&mut database);
+    run(&mut database, "BEGIN");
+    run(&mut database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+    run(&mut database, "INSERT INTO items (id, name) VALUES (1, 'duplicate')");
+    assert!(database.run_query("COMMIT").is_err());
+    run(&mut database, "BEGIN");
+    run(&mut database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+    run(&mut database, "COMMIT");
+    database.close().expect("Error while closing the database");
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"),
+               rows(vec![vec![int(1)], vec![int(2)], vec![int(3)]]));
+}
+
+#[test]
+fn commit_that_cant_install_a_table_installs_none() {
+    let dir = TempDir::new();
+    let mut database = open_file_database(&dir);
+    create_items(&mut database);
+    run(&mut database, "CREATE TABLE tags (id INT)");
+    run(&mut database, "INSERT INTO tags (id) VALUES (1)");
+    run(&mut database, "BEGIN");
+    run(&mut database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+    run(&mut database, "INSERT INTO tags (id) VALUES (2)");
+    // A directory in the way of the new file of tags makes writing it fail, whichever table is first
+    let blocked_path = dir.path.join("tags_data.csbase.tmp");
+    fs::create_dir(&blocked_path).unwrap();
+    assert!(database.run_query("COMMIT").is_err());
+    fs::remove_dir(&blocked_path).unwrap();
+    assert!(!dir.path.join("items_data.csbase.tmp").exists());
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"), rows(vec![vec![int(1)], vec![int(2)]]));
+    assert_eq!(select_values(&database, "SELECT id FROM tags"), rows(vec![vec![int(1)]]));
+    // The database is still usable, with the committed data only
+    run(&mut database, "INSERT INTO items (id, name) VALUES (3, 'three')");
+    database.close().expect("Error while closing the database");
+    let database = open_file_database(&dir);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"),
+               rows(vec![vec![int(1)], vec![int(2)], vec![int(3)]]));
+    assert_eq!(select_values(&database, "SELECT id FROM tags"), rows(vec![vec![int(1)]]));
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use csbase::engine::db::Database;
+use csbase::engine::errors::QueryError;
+use csbase::engine::fs::DBFileSystem;
+use csbase::engine::memory::InMemoryStorage;
+
+use common::{bootstrapped, int, rows, run, select_values, CountingStorage, StorageCalls, TempDir};
+
+const ROWS: i32 = 200;
+
+fn counting_database() -> (Database, Rc<RefCell<StorageCalls>>) {
+    let (storage, calls) = CountingStorage::new(Box::new(InMemoryStorage::new()));
+    (bootstrapped(Database::with_storage(Box::new(storage))), calls)
+}
+
+fn scan_count(calls: &Rc<RefCell<StorageCalls>>, table: &str) -> usize {
+    calls.borrow().scans.iter().filter(|scanned_table| scanned_table.as_str() == table).count()
+}
+
+fn assert_conflict(database: &mut Database, query: &str) {
+    match database.run_query(query) {
+        Err(QueryError::Conflict(_)) => (),
+        result => panic!("{} should conflict, but got {:?}", query, result),
+    }
+}
+
+#[test]
+fn inserts_check_keys_without_scanning_the_table() {
+    let (mut database, calls) = counting_database();
+    run(&mut database, "CREATE TABLE items (id INT, code STRING, PRIMARY KEY (id), UNIQUE (code))");
+    for id in 0..ROWS {
+        run(&mut database, &format!("INSERT INTO items (id, code) VALUES ({}, 'code {}')", id, id));
+    }
+    assert_conflict(&mut database, "INSERT INTO items (id, code) VALUES (7, 'new code')");
+    assert_conflict(&mut database, "INSERT INTO items (id, code) VALUES (1000, 'code 7')");
+    assert_eq!(scan_count(&calls, "items"), 0);
+}
+
+#[test]
+fn updates_and_deletes_keep_the_index_up_to_date() {
+    let (mut database, calls) = counting_database();
+    run(&mut database, "CREATE TABLE items (id INT, PRIMARY KEY (id))");
+    for id in 1..=3 {
+        run(&mut database, &format!("INSERT INTO items (id) VALUES ({})", id));
+    }
+    // Each new key is the key of another updated row
+    run(&mut database, "UPDATE items SET id = id + 1");
+    run(&mut database, "DELETE FROM items WHERE id = 3");
+    let scans_before_inserts = scan_count(&calls, "items");
+
+    run(&mut database, "INSERT INTO items (id) VALUES (1)");
+    run(&mut database, "INSERT INTO items (id) VALUES (3)");
+    assert_conflict(&mut database, "INSERT INTO items (id) VALUES (4)");
+    assert_eq!(scan_count(&calls, "items"), scans_before_inserts);
+    assert_eq!(select_values(&database, "SELECT id FROM items ORDER BY id"),
+               rows(vec![vec![int(1)], vec![int(2)], vec![int(3)], vec![int(4)]]));
+}
+
+#[test]
+fn update_checks_keys_against_the_index() {
+    let (mut database, _) = counting_database();
+    run(&mut database, "CREATE TABLE items (id INT, PRIMARY KEY (id))");
+    run(&mut database, "INSERT INTO items (id) VALUES (1)");
+    run(&mut database, "INSERT INTO items (id) VALUES (2)");
+    assert_conflict(&mut database, "UPDATE items SET id = 2 WHERE id = 1");
+    // The failed update left the keys as they were
+    assert_conflict(&mut database, "INSERT INTO items (id) VALUES (1)");
+    run(&mut database, "INSERT INTO items (id) VALUES (3)");
+}
+
+#[test]
+fn index_is_built_with_one_scan_after_reopening() {
+    let dir = TempDir::new();
+    let mut database = bootstrapped(Database::with_config(dir.config()));
+    run(&mut database, "CREATE TABLE items (id INT, PRIMARY KEY (id))");
+    run(&mut database, "INSERT INTO items (id) VALUES (1)");
+    database.close().expect("Error while closing the database");
+
+    let (storage, calls) = CountingStorage::new(Box::new(DBFileSystem::with_config(&dir.config())));
+    let mut database = bootstrapped(Database::with_storage(Box::new(storage)));
+    let scans_after_bootstrap = scan_count(&calls, "items");
+    assert_conflict(&mut database, "INSERT INTO items (id) VALUES (1)");
+    for id in 2..10 {
+        run(&mut database, &format!("INSERT INTO items (id) VALUES ({})", id));
+    }
+    assert_eq!(scan_count(&calls, "items"), scans_after_bootstrap + 1);
+}
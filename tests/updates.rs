@@ -0,0 +1,35 @@
+mod common;
+
+use csbase::engine::errors::QueryError;
+
+use common::{for_each_backend, int, rows, run, select_values, string};
+
+#[test]
+fn assignments_see_the_row_before_the_update() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE pairs (id INT, a INT, b INT, PRIMARY KEY (id))");
+        run(database, "INSERT INTO pairs (id, a, b) VALUES (1, 10, 20)");
+        run(database, "INSERT INTO pairs (id, a, b) VALUES (2, 30, 40)");
+        run(database, "UPDATE pairs SET a = b, b = a");
+        assert_eq!(select_values(database, "SELECT id, a, b FROM pairs ORDER BY id"), rows(vec![
+            vec![int(1), int(20), int(10)],
+            vec![int(2), int(40), int(30)],
+        ]));
+        // Each assignment reads a, not the value the one before it assigned
+        run(database, "UPDATE pairs SET a = a + 1, b = a WHERE id = 1");
+        assert_eq!(select_values(database, "SELECT a, b FROM pairs WHERE id = 1"), rows(vec![vec![int(21), int(20)]]));
+    });
+}
+
+#[test]
+fn assigning_a_column_twice_is_rejected() {
+    for_each_backend(|database| {
+        run(database, "CREATE TABLE items (id INT, name STRING, PRIMARY KEY (id))");
+        run(database, "INSERT INTO items (id, name) VALUES (1, 'one')");
+        match database.run_query("UPDATE items SET name = 'uno', name = 'eins'") {
+            Err(QueryError::ValidationError(message)) => assert!(message.contains("assigned more than once"), "{}", message),
+            result => panic!("The update should be rejected, but got {:?}", result),
+        }
+        assert_eq!(select_values(database, "SELECT name FROM items"), rows(vec![vec![string("one")]]));
+    });
+}